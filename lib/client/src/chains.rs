@@ -95,6 +95,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn can_relay_writes_works() {
         let chain = get_chain(ChainID::Ethereum);
         let result = chain.can_relay_writes();
@@ -89,6 +89,7 @@ mod tests {
 
     #[test]
     #[cfg(feature = "blocking")]
+    #[allow(clippy::bool_assert_comparison)]
     fn read_works() {
         let mut client = MockClient::new(ChainID::Local);
         client.respond_with(b"[{}]".to_vec());
@@ -0,0 +1,120 @@
+use ed25519_zebra::{batch, Signature, VerificationKey, VerificationKeyBytes};
+use rand_core::OsRng;
+
+use crate::errors::{CryptoError, CryptoResult};
+
+/// Length of a serialized signature.
+pub const EDDSA_SIGNATURE_LEN: usize = 64;
+/// Length of a serialized public key.
+pub const EDDSA_PUBKEY_LEN: usize = 32;
+
+/// Verifies an ed25519 signature over a message.
+pub fn ed25519_verify(message: &[u8], signature: &[u8], public_key: &[u8]) -> CryptoResult<bool> {
+    let signature = read_signature(signature)?;
+    let public_key = read_pubkey(public_key)?;
+    let public_key =
+        VerificationKey::try_from(public_key).map_err(|_| CryptoError::InvalidPubkeyFormat)?;
+    Ok(public_key.verify(&signature, message).is_ok())
+}
+
+/// Verifies a batch of ed25519 signatures, which is faster than verifying
+/// them one by one. Returns `true` only if every signature is valid.
+///
+/// Besides one message and public key per signature, this accepts a single
+/// message signed by many keys, or many messages signed by a single key.
+pub fn ed25519_batch_verify(
+    messages: &[&[u8]],
+    signatures: &[&[u8]],
+    public_keys: &[&[u8]],
+) -> CryptoResult<bool> {
+    let messages_len = messages.len();
+    let signatures_len = signatures.len();
+    let public_keys_len = public_keys.len();
+
+    let mut messages = messages.to_vec();
+    let mut public_keys = public_keys.to_vec();
+    if messages_len == signatures_len && messages_len == public_keys_len {
+        // Nothing to do
+    } else if messages_len == 1 && signatures_len == public_keys_len {
+        messages = messages.repeat(signatures_len);
+    } else if public_keys_len == 1 && messages_len == signatures_len {
+        public_keys = public_keys.repeat(signatures_len);
+    } else {
+        return Err(CryptoError::batch_err(
+            "Mismatched or erroneous number of messages, signatures, or public keys",
+        ));
+    }
+
+    let mut verifier = batch::Verifier::new();
+    for ((message, signature), public_key) in messages
+        .into_iter()
+        .zip(signatures.iter())
+        .zip(public_keys.into_iter())
+    {
+        let signature = read_signature(signature)?;
+        let public_key = VerificationKeyBytes::from(read_pubkey(public_key)?);
+        verifier.queue((public_key, signature, message));
+    }
+    Ok(verifier.verify(OsRng).is_ok())
+}
+
+fn read_signature(data: &[u8]) -> CryptoResult<Signature> {
+    let data: [u8; EDDSA_SIGNATURE_LEN] = data
+        .try_into()
+        .map_err(|_| CryptoError::InvalidSignatureFormat)?;
+    Ok(Signature::from_bytes(&data))
+}
+
+fn read_pubkey(data: &[u8]) -> CryptoResult<[u8; EDDSA_PUBKEY_LEN]> {
+    data.try_into()
+        .map_err(|_| CryptoError::InvalidPubkeyFormat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_zebra::SigningKey;
+
+    fn sign(seed: u8, message: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let key = SigningKey::from([seed; 32]);
+        let public_key: [u8; 32] = VerificationKey::from(&key).into();
+        (key.sign(message).to_bytes().to_vec(), public_key.to_vec())
+    }
+
+    #[test]
+    fn ed25519_verify_works() {
+        let (signature, public_key) = sign(1, b"hello");
+        assert!(ed25519_verify(b"hello", &signature, &public_key).unwrap());
+        assert!(!ed25519_verify(b"bye", &signature, &public_key).unwrap());
+        assert_eq!(
+            ed25519_verify(b"hello", &signature[..63], &public_key),
+            Err(CryptoError::InvalidSignatureFormat)
+        );
+        assert_eq!(
+            ed25519_verify(b"hello", &signature, &public_key[..31]),
+            Err(CryptoError::InvalidPubkeyFormat)
+        );
+    }
+
+    #[test]
+    fn ed25519_batch_verify_works() {
+        let (sig1, key1) = sign(1, b"a");
+        let (sig2, key2) = sign(2, b"b");
+        assert!(ed25519_batch_verify(&[b"a", b"b"], &[&sig1, &sig2], &[&key1, &key2]).unwrap());
+        assert!(!ed25519_batch_verify(&[b"a", b"a"], &[&sig1, &sig2], &[&key1, &key2]).unwrap());
+        assert!(ed25519_batch_verify(&[], &[], &[]).unwrap());
+
+        // One message, many keys.
+        let (sig3, key3) = sign(3, b"a");
+        assert!(ed25519_batch_verify(&[b"a"], &[&sig1, &sig3], &[&key1, &key3]).unwrap());
+
+        // One key, many messages.
+        let (sig4, _) = sign(1, b"b");
+        assert!(ed25519_batch_verify(&[b"a", b"b"], &[&sig1, &sig4], &[&key1]).unwrap());
+
+        assert!(matches!(
+            ed25519_batch_verify(&[b"a", b"b"], &[&sig1], &[&key1, &key2]),
+            Err(CryptoError::BatchErr { .. })
+        ));
+    }
+}
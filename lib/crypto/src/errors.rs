@@ -0,0 +1,38 @@
+use thiserror::Error;
+
+pub type CryptoResult<T> = core::result::Result<T, CryptoError>;
+
+/// Errors from malformed inputs. A well-formed signature that doesn't verify
+/// isn't an error.
+///
+/// Each error has a code that's passed to functions across the Wasm boundary.
+/// 0 and 1 are reserved for successful verification results.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CryptoError {
+    #[error("Invalid hash format")]
+    InvalidHashFormat,
+    #[error("Invalid signature format")]
+    InvalidSignatureFormat,
+    #[error("Invalid public key format")]
+    InvalidPubkeyFormat,
+    #[error("Invalid recovery parameter. Supported values: 0 to 3.")]
+    InvalidRecoveryParam,
+    #[error("Batch verify error: {msg}")]
+    BatchErr { msg: String },
+}
+
+impl CryptoError {
+    pub fn batch_err(msg: impl Into<String>) -> Self {
+        CryptoError::BatchErr { msg: msg.into() }
+    }
+
+    pub fn code(&self) -> u32 {
+        match self {
+            CryptoError::InvalidHashFormat => 3,
+            CryptoError::InvalidSignatureFormat => 4,
+            CryptoError::InvalidPubkeyFormat => 5,
+            CryptoError::InvalidRecoveryParam => 6,
+            CryptoError::BatchErr { .. } => 7,
+        }
+    }
+}
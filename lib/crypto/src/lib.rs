@@ -0,0 +1,19 @@
+//! Cryptography used by the Tableland Functions VM and the `tableland_std` mocks.
+//!
+//! Kept in its own crate so functions and hosts verify signatures and compute
+//! hashes with exactly the same implementation.
+
+mod ed25519;
+mod errors;
+mod keccak;
+mod secp256k1;
+
+pub use crate::ed25519::{
+    ed25519_batch_verify, ed25519_verify, EDDSA_PUBKEY_LEN, EDDSA_SIGNATURE_LEN,
+};
+pub use crate::errors::{CryptoError, CryptoResult};
+pub use crate::keccak::keccak256;
+pub use crate::secp256k1::{
+    secp256k1_recover_pubkey, secp256k1_verify, ECDSA_PUBKEY_MAX_LEN, ECDSA_SIGNATURE_LEN,
+    MESSAGE_HASH_MAX_LEN,
+};
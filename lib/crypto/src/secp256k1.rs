@@ -0,0 +1,95 @@
+use k256::ecdsa::signature::hazmat::PrehashVerifier;
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+
+use crate::errors::{CryptoError, CryptoResult};
+
+/// Max length of a message hash.
+pub const MESSAGE_HASH_MAX_LEN: usize = 32;
+/// Length of a serialized signature, `r || s`.
+pub const ECDSA_SIGNATURE_LEN: usize = 64;
+/// Max length of a public key, which is 65 bytes uncompressed.
+pub const ECDSA_PUBKEY_MAX_LEN: usize = 65;
+
+/// Verifies a secp256k1 signature over a 32-byte message hash. The public key
+/// may be compressed or uncompressed. Signatures with a high `s` are accepted.
+pub fn secp256k1_verify(
+    message_hash: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+) -> CryptoResult<bool> {
+    let message_hash = read_hash(message_hash)?;
+    let signature = read_signature(signature)?;
+    let signature = signature.normalize_s().unwrap_or(signature);
+    let public_key =
+        VerifyingKey::from_sec1_bytes(public_key).map_err(|_| CryptoError::InvalidPubkeyFormat)?;
+    Ok(public_key.verify_prehash(&message_hash, &signature).is_ok())
+}
+
+/// Recovers the uncompressed public key that signed a 32-byte message hash.
+/// For Ethereum signatures, the recovery parameter is `v - 27`.
+pub fn secp256k1_recover_pubkey(
+    message_hash: &[u8],
+    signature: &[u8],
+    recovery_param: u8,
+) -> CryptoResult<Vec<u8>> {
+    let message_hash = read_hash(message_hash)?;
+    let signature = read_signature(signature)?;
+    let recovery_id =
+        RecoveryId::from_byte(recovery_param).ok_or(CryptoError::InvalidRecoveryParam)?;
+    let public_key = VerifyingKey::recover_from_prehash(&message_hash, &signature, recovery_id)
+        .map_err(|_| CryptoError::InvalidSignatureFormat)?;
+    Ok(public_key.to_encoded_point(false).as_bytes().to_vec())
+}
+
+fn read_hash(data: &[u8]) -> CryptoResult<[u8; MESSAGE_HASH_MAX_LEN]> {
+    data.try_into().map_err(|_| CryptoError::InvalidHashFormat)
+}
+
+fn read_signature(data: &[u8]) -> CryptoResult<Signature> {
+    if data.len() != ECDSA_SIGNATURE_LEN {
+        return Err(CryptoError::InvalidSignatureFormat);
+    }
+    Signature::from_slice(data).map_err(|_| CryptoError::InvalidSignatureFormat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keccak256;
+    use k256::ecdsa::SigningKey;
+
+    #[test]
+    fn secp256k1_works() {
+        let key = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        let hash = keccak256(b"hello");
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+        let signature = signature.to_vec();
+        let public_key = key.verifying_key().to_encoded_point(false);
+        let compressed = key.verifying_key().to_encoded_point(true);
+
+        assert!(secp256k1_verify(&hash, &signature, public_key.as_bytes()).unwrap());
+        assert!(secp256k1_verify(&hash, &signature, compressed.as_bytes()).unwrap());
+        assert!(!secp256k1_verify(&keccak256(b"bye"), &signature, public_key.as_bytes()).unwrap());
+        assert_eq!(
+            secp256k1_verify(&hash[..31], &signature, public_key.as_bytes()),
+            Err(CryptoError::InvalidHashFormat)
+        );
+        assert_eq!(
+            secp256k1_verify(&hash, &signature[..63], public_key.as_bytes()),
+            Err(CryptoError::InvalidSignatureFormat)
+        );
+        assert_eq!(
+            secp256k1_verify(&hash, &signature, &[4u8; 33]),
+            Err(CryptoError::InvalidPubkeyFormat)
+        );
+
+        let recovered = secp256k1_recover_pubkey(&hash, &signature, recovery_id.to_byte()).unwrap();
+        assert_eq!(recovered, public_key.as_bytes());
+        let other = secp256k1_recover_pubkey(&hash, &signature, recovery_id.to_byte() ^ 1);
+        assert_ne!(other.ok().as_deref(), Some(public_key.as_bytes()));
+        assert_eq!(
+            secp256k1_recover_pubkey(&hash, &signature, 27),
+            Err(CryptoError::InvalidRecoveryParam)
+        );
+    }
+}
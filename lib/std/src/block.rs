@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+/// The latest block the worker has processed for its chain. It's fixed for
+/// the duration of an invocation.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Block {
+    pub number: u64,
+    /// Unix timestamp of the block, in seconds.
+    pub timestamp: u64,
+}
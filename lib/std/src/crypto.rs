@@ -0,0 +1,40 @@
+use thiserror::Error;
+
+/// Errors from the crypto methods of [`Api`](crate::Api) when an input is malformed.
+/// A well-formed signature that doesn't verify isn't an error.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CryptoError {
+    #[error("Invalid hash format")]
+    InvalidHashFormat,
+    #[error("Invalid signature format")]
+    InvalidSignatureFormat,
+    #[error("Invalid public key format")]
+    InvalidPubkeyFormat,
+    #[error("Invalid recovery parameter. Supported values: 0 to 3.")]
+    InvalidRecoveryParam,
+    #[error("Batch verify error")]
+    BatchErr,
+    #[error("Unknown error: {error_code}")]
+    UnknownErr { error_code: u32 },
+}
+
+impl CryptoError {
+    /// Maps an error code returned by a crypto import to an error.
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            3 => CryptoError::InvalidHashFormat,
+            4 => CryptoError::InvalidSignatureFormat,
+            5 => CryptoError::InvalidPubkeyFormat,
+            6 => CryptoError::InvalidRecoveryParam,
+            7 => CryptoError::BatchErr,
+            error_code => CryptoError::UnknownErr { error_code },
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<tableland_crypto::CryptoError> for CryptoError {
+    fn from(err: tableland_crypto::CryptoError) -> Self {
+        CryptoError::from_code(err.code())
+    }
+}
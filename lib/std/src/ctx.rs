@@ -1,3 +1,5 @@
+use crate::block::Block;
+use crate::meta::Meta;
 use crate::traits::Api;
 
 /// Holds all external dependencies of the contract.
@@ -6,15 +8,44 @@ use crate::traits::Api;
 /// for mock storages and a bridge storage in the VM.
 pub struct OwnedCtx<A: Api> {
     pub tableland: A,
+    pub meta: Meta,
 }
 
 pub struct CtxMut<'a> {
     pub tableland: &'a dyn Api,
+    pub env: Env<'a>,
+    pub meta: &'a Meta,
 }
 
 #[derive(Clone)]
 pub struct Ctx<'a> {
     pub tableland: &'a dyn Api,
+    pub env: Env<'a>,
+    pub meta: &'a Meta,
+}
+
+/// Variables and secrets the worker operator set for this function.
+#[derive(Clone, Copy)]
+pub struct Env<'a> {
+    api: &'a dyn Api,
+}
+
+impl<'a> Env<'a> {
+    pub fn new(api: &'a dyn Api) -> Self {
+        Env { api }
+    }
+
+    /// Returns the value of a variable, if set.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.api.env_get(key)
+    }
+}
+
+impl<'a> Ctx<'a> {
+    /// Returns the latest block the worker has processed, if known.
+    pub fn block(&self) -> Option<Block> {
+        self.tableland.block()
+    }
 }
 
 // Use custom implementation on order to implement Copy in case `C` is not `Copy`.
@@ -27,26 +58,39 @@ impl<A: Api> OwnedCtx<A> {
     pub fn as_ref(&'_ self) -> Ctx<'_> {
         Ctx {
             tableland: &self.tableland,
+            env: Env::new(&self.tableland),
+            meta: &self.meta,
         }
     }
 
     pub fn as_mut(&'_ mut self) -> CtxMut<'_> {
         CtxMut {
             tableland: &self.tableland,
+            env: Env::new(&self.tableland),
+            meta: &self.meta,
         }
     }
 }
 
 impl<'a> CtxMut<'a> {
+    /// Returns the latest block the worker has processed, if known.
+    pub fn block(&self) -> Option<Block> {
+        self.tableland.block()
+    }
+
     pub fn as_ref(&'_ self) -> Ctx<'_> {
         Ctx {
             tableland: self.tableland,
+            env: self.env,
+            meta: self.meta,
         }
     }
 
     pub fn branch(&'_ mut self) -> CtxMut<'_> {
         CtxMut {
             tableland: self.tableland,
+            env: self.env,
+            meta: self.meta,
         }
     }
 }
@@ -78,6 +122,32 @@ mod tests {
         query(deps.as_ref())
     }
 
+    #[test]
+    fn env_works() {
+        let owned = OwnedCtx {
+            tableland: MockApi::default().with_env("TABLE", "players_31337_7"),
+            meta: Meta::default(),
+        };
+        let ctx = owned.as_ref();
+        assert_eq!(ctx.env.get("TABLE"), Some("players_31337_7".to_string()));
+        assert_eq!(ctx.env.get("OTHER"), None);
+    }
+
+    #[test]
+    fn block_works() {
+        let block = Block {
+            number: 10,
+            timestamp: 1_700_000_000,
+        };
+        let owned = OwnedCtx {
+            tableland: MockApi::default().with_block(block),
+            meta: Meta::default(),
+        };
+        assert_eq!(owned.as_ref().block(), Some(block));
+        let mut deps = mock_dependencies(Vec::new());
+        assert_eq!(deps.as_mut().block(), None);
+    }
+
     #[test]
     fn deps_implements_copy() {
         #[derive(Clone, Serialize, Deserialize)]
@@ -86,6 +156,7 @@ mod tests {
         // With C: Copy
         let owned = OwnedCtx {
             tableland: MockApi::default(),
+            meta: Meta::default(),
         };
         let ctx: Ctx = owned.as_ref();
         let _copy1 = ctx;
@@ -94,6 +165,7 @@ mod tests {
         // Without C: Copy
         let owned = OwnedCtx {
             tableland: MockApi::default(),
+            meta: Meta::default(),
         };
         let ctx: Ctx = owned.as_ref();
         let _copy1 = ctx;
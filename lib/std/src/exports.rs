@@ -16,7 +16,7 @@ use crate::memory::{alloc, consume_region, release_buffer, Region};
 #[cfg(feature = "abort")]
 use crate::panic::install_panic_handler;
 use crate::results::FuncResult;
-use crate::CtxMut;
+use crate::{CtxMut, Meta};
 
 /// interface_version_* exports mark which Wasm VM interface level this contract is compiled for.
 /// They can be checked by tableland_vm.
@@ -24,6 +24,12 @@ use crate::CtxMut;
 #[no_mangle]
 extern "C" fn interface_version_8() -> () {}
 
+/// Marks that the function needs the crypto imports, so hosts that don't provide
+/// them refuse it up front.
+#[cfg(feature = "crypto")]
+#[no_mangle]
+extern "C" fn requires_crypto() {}
+
 /// allocate reserves the given number of bytes in wasm memory and returns a pointer
 /// to a Region defining this data. This space is managed by the calling process
 /// and should be accompanied by a corresponding deallocate
@@ -79,13 +85,14 @@ where
     let req: Vec<u8> = unsafe { consume_region(req_ptr) };
     let req: Request = try_into_func_result!(from_slice(&req));
 
-    let mut ctx = make_ctx();
+    let mut ctx = make_ctx(req.meta().unwrap_or_default());
     fetch_fn(req, ctx.as_mut()).into()
 }
 
 /// Makes all bridges to external dependencies (i.e. Wasm imports) that are injected by the VM
-pub(crate) fn make_ctx() -> OwnedCtx<ExternalApi> {
+pub(crate) fn make_ctx(meta: Meta) -> OwnedCtx<ExternalApi> {
     OwnedCtx {
         tableland: ExternalApi::new(),
+        meta,
     }
 }
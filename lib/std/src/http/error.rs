@@ -38,3 +38,9 @@ impl From<std::string::FromUtf8Error> for Error {
         Error::bad_encoding(e.to_string())
     }
 }
+
+impl From<crate::CryptoError> for Error {
+    fn from(e: crate::CryptoError) -> Self {
+        Error::bad_encoding(e.to_string())
+    }
+}
@@ -2,6 +2,8 @@ use http::{header::HeaderMap, method::Method, uri::Uri};
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 
+use crate::meta::Meta;
+
 /// A [Request](https://developer.mozilla.org/en-US/docs/Web/API/Request) representation for
 /// handling incoming and creating outbound HTTP requests.
 /// Inspired by https://github.com/cloudflare/workers-rs/blob/main/worker/src/request.rs.
@@ -25,6 +27,12 @@ pub struct Request {
     )]
     headers: HeaderMap,
     body: Option<ByteBuf>,
+    /// Ethereum address of the caller, set by the host after verifying the request signature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    caller: Option<String>,
+    /// Details about the worker and the invocation, set by the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
 }
 
 impl Request {
@@ -42,9 +50,23 @@ impl Request {
             method,
             headers,
             body,
+            caller: None,
+            meta: None,
         }
     }
 
+    /// Set the verified caller address.
+    pub fn with_caller(mut self, caller: Option<String>) -> Self {
+        self.caller = caller;
+        self
+    }
+
+    /// Set the worker and invocation details.
+    pub fn with_meta(mut self, meta: Meta) -> Self {
+        self.meta = Some(meta);
+        self
+    }
+
     /// Get the target function content identifier.
     pub fn id(&self) -> String {
         self.id.clone()
@@ -69,4 +91,15 @@ impl Request {
     pub fn body(&self) -> Option<ByteBuf> {
         self.body.clone()
     }
+
+    /// The Ethereum address (lowercase, `0x`-prefixed) that signed this `Request`, if any.
+    /// Only set when the host has verified the signature.
+    pub fn caller(&self) -> Option<String> {
+        self.caller.clone()
+    }
+
+    /// The worker and invocation details. Functions read these through `ctx.meta`.
+    pub fn meta(&self) -> Option<Meta> {
+        self.meta.clone()
+    }
 }
@@ -1,7 +1,8 @@
-use http::header::{HeaderMap, HeaderValue};
+use http::header::{HeaderMap, HeaderValue, CACHE_CONTROL, ETAG, LAST_MODIFIED};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 use serde_json::Value;
+use std::time::{Duration, UNIX_EPOCH};
 
 use super::{Error, Result};
 
@@ -199,8 +200,66 @@ impl Response {
         self
     }
 
+    /// Set this response's `Cache-Control` header, e.g., `public, max-age=60`.
+    pub fn with_cache_control(self, value: impl AsRef<str>) -> Result<Self> {
+        self.with_header(CACHE_CONTROL.as_str(), value.as_ref())
+    }
+
+    /// Set this response's `ETag` header. The tag is quoted if needed, e.g., `v1` becomes `"v1"`.
+    /// Weak tags like `W/"v1"` are passed through. If unset, the Worker derives a strong tag from
+    /// the response body.
+    pub fn with_etag(self, tag: impl AsRef<str>) -> Result<Self> {
+        let tag = tag.as_ref();
+        if tag.starts_with('"') || tag.starts_with("W/\"") {
+            self.with_header(ETAG.as_str(), tag)
+        } else {
+            self.with_header(ETAG.as_str(), &format!("\"{}\"", tag))
+        }
+    }
+
+    /// Set this response's `Last-Modified` header from a unix timestamp in seconds. Clients
+    /// that send `If-Modified-Since` will get a `304 Not Modified` if nothing changed.
+    pub fn with_last_modified(self, timestamp: u64) -> Result<Self> {
+        let date = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(timestamp));
+        self.with_header(LAST_MODIFIED.as_str(), &date)
+    }
+
+    fn with_header(mut self, name: &'static str, value: &str) -> Result<Self> {
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| Error::bad_encoding(format!("invalid {} header value", name)))?;
+        self.headers.insert(name, value);
+        Ok(self)
+    }
+
     /// Read the `Headers` on this response.
     pub fn headers(&self) -> &HeaderMap {
         &self.headers
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_headers_work() {
+        let res = Response::ok("hi")
+            .unwrap()
+            .with_cache_control("public, max-age=60")
+            .unwrap()
+            .with_etag("v1")
+            .unwrap()
+            .with_last_modified(784111777)
+            .unwrap();
+        assert_eq!(res.headers()[CACHE_CONTROL], "public, max-age=60");
+        assert_eq!(res.headers()[ETAG], "\"v1\"");
+        assert_eq!(
+            res.headers()[LAST_MODIFIED],
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+
+        let res = Response::empty().unwrap().with_etag("W/\"v2\"").unwrap();
+        assert_eq!(res.headers()[ETAG], "W/\"v2\"");
+        assert!(Response::empty().unwrap().with_etag("a\nb").is_err());
+    }
+}
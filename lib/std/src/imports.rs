@@ -1,8 +1,13 @@
 use serde_json::{from_slice, to_vec, Value};
 use tableland_client_types::ReadOptions;
 
+use crate::block::Block;
+#[cfg(feature = "crypto")]
+use crate::crypto::CryptoError;
 use crate::http::Result;
 use crate::memory::{build_region, consume_region, Region};
+#[cfg(feature = "crypto")]
+use crate::sections::encode_sections;
 use crate::tableland::ReadRequest;
 use crate::traits::Api;
 
@@ -11,6 +16,39 @@ extern "C" {
     /// Performs a Tableland read query.
     fn read(source_ptr: u32) -> u32;
 
+    /// Looks up a variable in the function's environment. Returns 0 if it isn't set.
+    fn env_get(key_ptr: u32) -> u32;
+
+    /// Returns the latest block the worker has processed. Returns 0 if it isn't known.
+    fn block() -> u32;
+
+    /// Hashes data with keccak256. Returns a pointer to the 32-byte hash.
+    #[cfg(feature = "crypto")]
+    fn keccak256(data_ptr: u32) -> u32;
+
+    /// Verifies a secp256k1 signature over a message hash.
+    /// Returns 0 on verification success, 1 on verification failure, and values
+    /// greater than 1 in case of error.
+    #[cfg(feature = "crypto")]
+    fn secp256k1_verify(hash_ptr: u32, signature_ptr: u32, public_key_ptr: u32) -> u32;
+
+    /// Recovers the public key that signed a message hash. Returns a pointer to the
+    /// public key in the high 32 bits, or an error code in the low 32 bits.
+    #[cfg(feature = "crypto")]
+    fn secp256k1_recover_pubkey(hash_ptr: u32, signature_ptr: u32, recovery_param: u32) -> u64;
+
+    /// Verifies an ed25519 signature over a message.
+    /// Returns 0 on verification success, 1 on verification failure, and values
+    /// greater than 1 in case of error.
+    #[cfg(feature = "crypto")]
+    fn ed25519_verify(message_ptr: u32, signature_ptr: u32, public_key_ptr: u32) -> u32;
+
+    /// Verifies a batch of ed25519 signatures. Arguments are encoded sections.
+    /// Returns 0 on verification success, 1 on verification failure, and values
+    /// greater than 1 in case of error.
+    #[cfg(feature = "crypto")]
+    fn ed25519_batch_verify(messages_ptr: u32, signatures_ptr: u32, public_keys_ptr: u32) -> u32;
+
     /// Writes a debug message (UFT-8 encoded) to the host for debugging purposes.
     /// The host is free to log or process this in any way it considers appropriate.
     /// In production environments it is expected that those messages are discarded.
@@ -47,6 +85,114 @@ impl Api for ExternalApi {
         Ok(data)
     }
 
+    fn env_get(&self, key: &str) -> Option<String> {
+        let key = build_region(key.as_bytes());
+        let key_ptr = &*key as *const Region as u32;
+
+        let value_ptr = unsafe { env_get(key_ptr) };
+        if value_ptr == 0 {
+            return None;
+        }
+        Some(unsafe { consume_string_region_written_by_vm(value_ptr as *mut Region) })
+    }
+
+    fn block(&self) -> Option<Block> {
+        let block_ptr = unsafe { block() };
+        if block_ptr == 0 {
+            return None;
+        }
+        let data = unsafe { consume_region(block_ptr as *mut Region) };
+        from_slice(&data).ok()
+    }
+
+    #[cfg(feature = "crypto")]
+    fn keccak256(&self, data: &[u8]) -> [u8; 32] {
+        let data = build_region(data);
+        let data_ptr = &*data as *const Region as u32;
+
+        let hash_ptr = unsafe { keccak256(data_ptr) };
+        let hash = unsafe { consume_region(hash_ptr as *mut Region) };
+        hash.try_into().unwrap()
+    }
+
+    #[cfg(feature = "crypto")]
+    fn secp256k1_verify(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+    ) -> core::result::Result<bool, CryptoError> {
+        let hash = build_region(message_hash);
+        let hash_ptr = &*hash as *const Region as u32;
+        let sig = build_region(signature);
+        let sig_ptr = &*sig as *const Region as u32;
+        let pubkey = build_region(public_key);
+        let pubkey_ptr = &*pubkey as *const Region as u32;
+
+        let result = unsafe { secp256k1_verify(hash_ptr, sig_ptr, pubkey_ptr) };
+        verification_result(result)
+    }
+
+    #[cfg(feature = "crypto")]
+    fn secp256k1_recover_pubkey(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        recovery_param: u8,
+    ) -> core::result::Result<Vec<u8>, CryptoError> {
+        let hash = build_region(message_hash);
+        let hash_ptr = &*hash as *const Region as u32;
+        let sig = build_region(signature);
+        let sig_ptr = &*sig as *const Region as u32;
+
+        let result = unsafe { secp256k1_recover_pubkey(hash_ptr, sig_ptr, recovery_param.into()) };
+        let error_code = (result & 0xFFFFFFFF) as u32;
+        let pubkey_ptr = (result >> 32) as u32;
+        match error_code {
+            0 => Ok(unsafe { consume_region(pubkey_ptr as *mut Region) }),
+            code => Err(CryptoError::from_code(code)),
+        }
+    }
+
+    #[cfg(feature = "crypto")]
+    fn ed25519_verify(
+        &self,
+        message: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+    ) -> core::result::Result<bool, CryptoError> {
+        let msg = build_region(message);
+        let msg_ptr = &*msg as *const Region as u32;
+        let sig = build_region(signature);
+        let sig_ptr = &*sig as *const Region as u32;
+        let pubkey = build_region(public_key);
+        let pubkey_ptr = &*pubkey as *const Region as u32;
+
+        let result = unsafe { ed25519_verify(msg_ptr, sig_ptr, pubkey_ptr) };
+        verification_result(result)
+    }
+
+    #[cfg(feature = "crypto")]
+    fn ed25519_batch_verify(
+        &self,
+        messages: &[&[u8]],
+        signatures: &[&[u8]],
+        public_keys: &[&[u8]],
+    ) -> core::result::Result<bool, CryptoError> {
+        let msgs = encode_sections(messages);
+        let msgs = build_region(&msgs);
+        let msgs_ptr = &*msgs as *const Region as u32;
+        let sigs = encode_sections(signatures);
+        let sigs = build_region(&sigs);
+        let sigs_ptr = &*sigs as *const Region as u32;
+        let pubkeys = encode_sections(public_keys);
+        let pubkeys = build_region(&pubkeys);
+        let pubkeys_ptr = &*pubkeys as *const Region as u32;
+
+        let result = unsafe { ed25519_batch_verify(msgs_ptr, sigs_ptr, pubkeys_ptr) };
+        verification_result(result)
+    }
+
     fn debug(&self, message: &str) {
         // keep the boxes in scope, so we free it at the end (don't cast to pointers same line as build_region)
         let region = build_region(message.as_bytes());
@@ -55,9 +201,18 @@ impl Api for ExternalApi {
     }
 }
 
+/// Maps the result of a verification import.
+#[cfg(feature = "crypto")]
+fn verification_result(result: u32) -> core::result::Result<bool, CryptoError> {
+    match result {
+        0 => Ok(true),
+        1 => Ok(false),
+        code => Err(CryptoError::from_code(code)),
+    }
+}
+
 /// Takes a pointer to a Region and reads the data into a String.
 /// This is for trusted string sources only.
-#[allow(dead_code)]
 unsafe fn consume_string_region_written_by_vm(from: *mut Region) -> String {
     let data = consume_region(from);
     // We trust the VM/chain to return correct UTF-8, so let's save some gas
@@ -3,15 +3,21 @@
 
 // Exposed on all platforms
 
+mod block;
+mod crypto;
 mod ctx;
 mod http;
+mod meta;
 mod panic;
 mod results;
 mod tableland;
 mod traits;
 
-pub use crate::ctx::{Ctx, CtxMut, OwnedCtx};
+pub use crate::block::Block;
+pub use crate::crypto::CryptoError;
+pub use crate::ctx::{Ctx, CtxMut, Env, OwnedCtx};
 pub use crate::http::{Error, Request, Response, Result, Router};
+pub use crate::meta::Meta;
 pub use crate::results::FuncResult;
 pub use crate::tableland::ReadRequest;
 pub use crate::traits::Api;
@@ -24,6 +30,8 @@ mod exports;
 mod imports;
 #[cfg(target_arch = "wasm32")]
 mod memory; // Used by exports and imports only. This assumes pointers are 32 bit long, which makes it untestable on dev machines.
+#[cfg(all(target_arch = "wasm32", feature = "crypto"))]
+mod sections; // Used by imports only.
 
 #[cfg(target_arch = "wasm32")]
 pub use crate::exports::do_fetch;
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+/// Read-only details about the worker and the invocation, set by the host.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Meta {
+    /// ID of the chain the worker reads tables from.
+    pub chain_id: u64,
+    /// Name of the chain the worker reads tables from.
+    pub chain_name: String,
+    /// Content identifier of the running function.
+    pub cid: String,
+    /// Worker-assigned ID of the request.
+    pub request_id: String,
+    /// Address of the client, as seen by the worker or forwarded by a trusted proxy.
+    pub client_ip: Option<String>,
+    /// Public base URL of the worker, if configured.
+    pub base_url: Option<String>,
+}
+
+impl Meta {
+    /// Returns the full name of a table on the worker's chain, e.g., `players_31337_7`.
+    pub fn table_name(&self, prefix: &str, table_id: u64) -> String {
+        format!("{}_{}_{}", prefix, self.chain_id, table_id)
+    }
+
+    /// Returns the absolute URL of a path on this function, if the worker has a public base URL.
+    pub fn url(&self, path: &str) -> Option<String> {
+        let base = self.base_url.as_deref()?.trim_end_matches('/');
+        Some(format!(
+            "{}/v1/functions/{}/{}",
+            base,
+            self.cid,
+            path.trim_start_matches('/')
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meta_works() {
+        let mut meta = Meta {
+            chain_id: 31337,
+            cid: "bafy".to_string(),
+            ..Meta::default()
+        };
+        assert_eq!(meta.table_name("players", 7), "players_31337_7");
+        assert_eq!(meta.url("/1/image"), None);
+        meta.base_url = Some("https://functions.example.com/".to_string());
+        assert_eq!(
+            meta.url("/1/image").unwrap(),
+            "https://functions.example.com/v1/functions/bafy/1/image"
+        );
+    }
+}
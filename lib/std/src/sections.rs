@@ -0,0 +1,21 @@
+/// Encodes multiple sections of data into one vector.
+///
+/// Each section is suffixed by a section length encoded as big endian uint32.
+/// This matches `decode_sections` in tableland_vm.
+///
+/// The resulting data looks like this:
+///
+/// ```ignore
+/// section1 || section1_len || section2 || section2_len || section3 || section3_len || …
+/// ```
+pub fn encode_sections(sections: &[&[u8]]) -> Vec<u8> {
+    let out_len: usize =
+        sections.iter().map(|section| section.len()).sum::<usize>() + 4 * sections.len();
+    let mut out_data = Vec::with_capacity(out_len);
+    for section in sections {
+        let section_len = (section.len() as u32).to_be_bytes();
+        out_data.extend_from_slice(section);
+        out_data.extend_from_slice(&section_len);
+    }
+    out_data
+}
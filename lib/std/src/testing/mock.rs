@@ -1,25 +1,61 @@
 use serde_json::{from_slice, Value};
+use std::collections::HashMap;
 use tableland_client_types::ReadOptions;
 
+use crate::block::Block;
+#[cfg(feature = "crypto")]
+use crate::crypto::CryptoError;
 use crate::ctx::OwnedCtx;
 use crate::http::{Request, Result};
+use crate::meta::Meta;
 use crate::traits::Api;
 
 /// Creates all external requirements that can be injected for unit tests.
 pub fn mock_dependencies(data: Vec<u8>) -> OwnedCtx<MockApi> {
     OwnedCtx {
         tableland: MockApi::new(data),
+        meta: mock_meta(),
+    }
+}
+
+/// Returns metadata for a function on a local chain.
+pub fn mock_meta() -> Meta {
+    Meta {
+        chain_id: 31337,
+        chain_name: "Local".to_string(),
+        cid: "mock_id".to_string(),
+        request_id: "mock_request_id".to_string(),
+        client_ip: Some("127.0.0.1".to_string()),
+        base_url: None,
     }
 }
 
 #[derive(Clone, Default)]
 pub struct MockApi {
     data: Vec<u8>,
+    env: HashMap<String, String>,
+    block: Option<Block>,
 }
 
 impl MockApi {
     fn new(data: Vec<u8>) -> Self {
-        MockApi { data }
+        MockApi {
+            data,
+            env: HashMap::new(),
+            block: None,
+        }
+    }
+
+    /// Sets a variable in the mock environment.
+    pub fn with_env(mut self, key: &str, value: &str) -> Self {
+        self.env.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets the latest block.
+    pub fn with_block(mut self, block: Block) -> Self {
+        self.block = Some(block);
+        self
     }
 }
 
@@ -28,6 +64,73 @@ impl Api for MockApi {
         Ok(from_slice(self.data.as_slice()).unwrap())
     }
 
+    fn env_get(&self, key: &str) -> Option<String> {
+        self.env.get(key).cloned()
+    }
+
+    fn block(&self) -> Option<Block> {
+        self.block
+    }
+
+    #[cfg(feature = "crypto")]
+    fn keccak256(&self, data: &[u8]) -> [u8; 32] {
+        tableland_crypto::keccak256(data)
+    }
+
+    #[cfg(feature = "crypto")]
+    fn secp256k1_verify(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+    ) -> core::result::Result<bool, CryptoError> {
+        Ok(tableland_crypto::secp256k1_verify(
+            message_hash,
+            signature,
+            public_key,
+        )?)
+    }
+
+    #[cfg(feature = "crypto")]
+    fn secp256k1_recover_pubkey(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        recovery_param: u8,
+    ) -> core::result::Result<Vec<u8>, CryptoError> {
+        Ok(tableland_crypto::secp256k1_recover_pubkey(
+            message_hash,
+            signature,
+            recovery_param,
+        )?)
+    }
+
+    #[cfg(feature = "crypto")]
+    fn ed25519_verify(
+        &self,
+        message: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+    ) -> core::result::Result<bool, CryptoError> {
+        Ok(tableland_crypto::ed25519_verify(
+            message, signature, public_key,
+        )?)
+    }
+
+    #[cfg(feature = "crypto")]
+    fn ed25519_batch_verify(
+        &self,
+        messages: &[&[u8]],
+        signatures: &[&[u8]],
+        public_keys: &[&[u8]],
+    ) -> core::result::Result<bool, CryptoError> {
+        Ok(tableland_crypto::ed25519_batch_verify(
+            messages,
+            signatures,
+            public_keys,
+        )?)
+    }
+
     fn debug(&self, message: &str) {
         println!("{}", message);
     }
@@ -55,4 +158,24 @@ mod tests {
             .unwrap();
         println!("{}", serde_json::to_string(&json).unwrap());
     }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn crypto_works() {
+        let api = MockApi::default();
+        assert_eq!(api.keccak256(b"")[..4], [0xc5, 0xd2, 0x46, 0x01],);
+        assert_eq!(
+            api.secp256k1_verify(&[0u8; 31], &[0u8; 64], &[0u8; 33]),
+            Err(CryptoError::InvalidHashFormat)
+        );
+        assert_eq!(
+            api.secp256k1_recover_pubkey(&[0u8; 32], &[1u8; 64], 4),
+            Err(CryptoError::InvalidRecoveryParam)
+        );
+        assert_eq!(
+            api.ed25519_verify(b"", &[0u8; 64], &[0u8; 31]),
+            Err(CryptoError::InvalidPubkeyFormat)
+        );
+        assert_eq!(api.ed25519_batch_verify(&[], &[], &[]), Ok(true));
+    }
 }
@@ -1,6 +1,9 @@
 use serde_json::Value;
 use tableland_client_types::ReadOptions;
 
+use crate::block::Block;
+#[cfg(feature = "crypto")]
+use crate::crypto::CryptoError;
 use crate::http::Result;
 
 /// Api are callbacks to system functions implemented outside of the wasm modules.
@@ -18,6 +21,57 @@ pub trait Api {
     /// Performs a Tableland read query.
     fn read(&self, statement: &str, options: ReadOptions) -> Result<Value>;
 
+    /// Returns the value of a variable set for this function by the worker operator, if any.
+    fn env_get(&self, key: &str) -> Option<String>;
+
+    /// Returns the latest block the worker has processed, if known. The block is pinned
+    /// when the invocation starts, so every call returns the same value.
+    fn block(&self) -> Option<Block>;
+
+    /// Returns the keccak256 hash of `data`, as used by Ethereum.
+    #[cfg(feature = "crypto")]
+    fn keccak256(&self, data: &[u8]) -> [u8; 32];
+
+    /// Verifies a secp256k1 signature (`r || s`) over a 32-byte message hash. The public key
+    /// may be compressed or uncompressed.
+    #[cfg(feature = "crypto")]
+    fn secp256k1_verify(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+    ) -> core::result::Result<bool, CryptoError>;
+
+    /// Recovers the uncompressed public key that signed a 32-byte message hash. For Ethereum
+    /// signatures, `recovery_param` is `v - 27`.
+    #[cfg(feature = "crypto")]
+    fn secp256k1_recover_pubkey(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        recovery_param: u8,
+    ) -> core::result::Result<Vec<u8>, CryptoError>;
+
+    /// Verifies an ed25519 signature over a message.
+    #[cfg(feature = "crypto")]
+    fn ed25519_verify(
+        &self,
+        message: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+    ) -> core::result::Result<bool, CryptoError>;
+
+    /// Verifies a batch of ed25519 signatures, returning `true` only if all are valid.
+    /// Takes one message and public key per signature, a single message signed by many
+    /// keys, or many messages signed by a single key.
+    #[cfg(feature = "crypto")]
+    fn ed25519_batch_verify(
+        &self,
+        messages: &[&[u8]],
+        signatures: &[&[u8]],
+        public_keys: &[&[u8]],
+    ) -> core::result::Result<bool, CryptoError>;
+
     /// Emits a debugging message that is handled depending on the environment (typically printed to console or ignored).
     /// Those messages are not persisted to chain.
     fn debug(&self, message: &str);
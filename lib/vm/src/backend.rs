@@ -1,5 +1,6 @@
 use serde_json::Value;
 use tableland_client_types::ReadOptions;
+use tableland_std::Block;
 use thiserror::Error;
 
 /// A structure that represents gas cost to be deducted from the remaining gas.
@@ -79,6 +80,12 @@ pub struct Backend<A: BackendApi> {
 pub trait BackendApi: Clone + Send {
     /// Performs a Tableland read query.
     fn read(&self, statement: &str, options: ReadOptions, gas_limit: u64) -> BackendResult<Value>;
+
+    /// Returns the value of a variable in the function's environment, if set.
+    fn env_get(&self, key: &str) -> BackendResult<Option<String>>;
+
+    /// Returns the latest block the host has processed, pinned for the invocation.
+    fn block(&self) -> BackendResult<Option<Block>>;
 }
 
 /// A result type for calling into the backend. Such a call can cause
@@ -1,4 +1,5 @@
-use parity_wasm::elements::{External, ImportEntry, Module};
+use parity_wasm::elements::{External, ImportEntry, Instruction, Module};
+use serde::Serialize;
 use std::collections::BTreeSet;
 use std::collections::HashSet;
 
@@ -9,7 +10,30 @@ use crate::static_analysis::{deserialize_wasm, ExportInfo};
 
 /// Lists all imports we provide upon instantiating the instance in Instance::from_module()
 /// This should be updated when new imports are added
-const SUPPORTED_IMPORTS: &[&str] = &["env.abort", "env.debug", "env.read"];
+const SUPPORTED_IMPORTS: &[&str] = &[
+    "env.abort",
+    "env.block",
+    "env.debug",
+    "env.ed25519_batch_verify",
+    "env.ed25519_verify",
+    "env.env_get",
+    "env.keccak256",
+    "env.read",
+    "env.secp256k1_recover_pubkey",
+    "env.secp256k1_verify",
+];
+
+/// Imports that may only be used by contracts requiring the crypto capability,
+/// so hosts can opt out of them.
+const CRYPTO_IMPORTS: &[&str] = &[
+    "env.ed25519_batch_verify",
+    "env.ed25519_verify",
+    "env.keccak256",
+    "env.secp256k1_recover_pubkey",
+    "env.secp256k1_verify",
+];
+
+const CRYPTO_CAPABILITY: &str = "crypto";
 
 /// Lists all entry points we expect to be present when calling a contract.
 /// Other optional exports exist, e.g. "execute", "migrate" and "query".
@@ -35,10 +59,184 @@ pub fn check_wasm(wasm_code: &[u8], available_capabilities: &HashSet<String>) ->
     check_interface_version(&module)?;
     check_wasm_exports(&module)?;
     check_wasm_imports(&module, SUPPORTED_IMPORTS)?;
+    check_wasm_crypto_imports(&module)?;
     check_wasm_capabilities(&module, available_capabilities)?;
     Ok(())
 }
 
+/// The outcome of running every static check on a module, for reporting back to
+/// whoever added it. Unlike [`check_wasm`], this doesn't stop at the first problem.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub valid: bool,
+    /// Module size in bytes.
+    pub size: usize,
+    /// The interface_version_* marker export, if any.
+    pub interface_version: Option<String>,
+    pub missing_exports: Vec<String>,
+    pub unsupported_imports: Vec<String>,
+    pub missing_capabilities: Vec<String>,
+    pub memory_issues: Vec<String>,
+    /// Number of float operators. Floats are allowed but may be nondeterministic
+    /// across hosts, so this is informational only.
+    pub float_operators: usize,
+    /// Every failed check.
+    pub errors: Vec<String>,
+}
+
+/// Runs all static checks on a module and collects the results.
+pub fn validate_wasm(
+    wasm_code: &[u8],
+    available_capabilities: &HashSet<String>,
+) -> ValidationReport {
+    let mut report = ValidationReport {
+        size: wasm_code.len(),
+        ..Default::default()
+    };
+    let module = match deserialize_wasm(wasm_code) {
+        Ok(m) => m,
+        Err(e) => {
+            report.errors.push(e.to_string());
+            return report;
+        }
+    };
+
+    if let Err(e) = check_wasm_memories(&module) {
+        report.memory_issues.push(e.to_string());
+        report.errors.push(e.to_string());
+    }
+    let mut record = |res: VmResult<()>| {
+        if let Err(e) = res {
+            report.errors.push(e.to_string());
+        }
+    };
+    record(check_interface_version(&module));
+    record(check_wasm_exports(&module));
+    record(check_wasm_imports(&module, SUPPORTED_IMPORTS));
+    record(check_wasm_crypto_imports(&module));
+    record(check_wasm_capabilities(&module, available_capabilities));
+    let mut versions: Vec<String> = module
+        .exported_function_names(Some(INTERFACE_VERSION_PREFIX))
+        .into_iter()
+        .collect();
+    versions.sort();
+    report.interface_version = versions.into_iter().next();
+
+    let exports = module.exported_function_names(None);
+    report.missing_exports = REQUIRED_EXPORTS
+        .iter()
+        .filter(|e| !exports.contains(**e))
+        .map(|e| e.to_string())
+        .collect();
+    report.unsupported_imports = module
+        .import_section()
+        .map_or(vec![], |s| s.entries().to_vec())
+        .iter()
+        .filter(|ie| {
+            !SUPPORTED_IMPORTS.contains(&full_import_name(ie).as_str())
+                || !matches!(ie.external(), External::Function(_))
+        })
+        .map(full_import_name)
+        .collect();
+    let mut missing: Vec<String> = required_capabilities_from_module(&module)
+        .difference(available_capabilities)
+        .cloned()
+        .collect();
+    missing.sort();
+    report.missing_capabilities = missing;
+    report.float_operators = count_float_operators(&module);
+
+    report.valid = report.errors.is_empty();
+    report
+}
+
+fn count_float_operators(module: &Module) -> usize {
+    module.code_section().map_or(0, |code| {
+        code.bodies()
+            .iter()
+            .flat_map(|body| body.code().elements())
+            .filter(|instr| is_float_operator(instr))
+            .count()
+    })
+}
+
+/// Returns whether an instruction loads, stores, computes on or converts to or
+/// from a float.
+fn is_float_operator(instr: &Instruction) -> bool {
+    use Instruction::*;
+    matches!(
+        instr,
+        F32Load(..)
+            | F64Load(..)
+            | F32Store(..)
+            | F64Store(..)
+            | F32Const(_)
+            | F64Const(_)
+            | F32Eq
+            | F32Ne
+            | F32Lt
+            | F32Gt
+            | F32Le
+            | F32Ge
+            | F64Eq
+            | F64Ne
+            | F64Lt
+            | F64Gt
+            | F64Le
+            | F64Ge
+            | F32Abs
+            | F32Neg
+            | F32Ceil
+            | F32Floor
+            | F32Trunc
+            | F32Nearest
+            | F32Sqrt
+            | F32Add
+            | F32Sub
+            | F32Mul
+            | F32Div
+            | F32Min
+            | F32Max
+            | F32Copysign
+            | F64Abs
+            | F64Neg
+            | F64Ceil
+            | F64Floor
+            | F64Trunc
+            | F64Nearest
+            | F64Sqrt
+            | F64Add
+            | F64Sub
+            | F64Mul
+            | F64Div
+            | F64Min
+            | F64Max
+            | F64Copysign
+            | I32TruncSF32
+            | I32TruncUF32
+            | I32TruncSF64
+            | I32TruncUF64
+            | I64TruncSF32
+            | I64TruncUF32
+            | I64TruncSF64
+            | I64TruncUF64
+            | F32ConvertSI32
+            | F32ConvertUI32
+            | F32ConvertSI64
+            | F32ConvertUI64
+            | F32DemoteF64
+            | F64ConvertSI32
+            | F64ConvertUI32
+            | F64ConvertSI64
+            | F64ConvertUI64
+            | F64PromoteF32
+            | I32ReinterpretF32
+            | I64ReinterpretF64
+            | F32ReinterpretI32
+            | F64ReinterpretI64
+    )
+}
+
 fn check_wasm_memories(module: &Module) -> VmResult<()> {
     let section = match module.memory_section() {
         Some(section) => section,
@@ -152,6 +350,29 @@ fn full_import_name(ie: &ImportEntry) -> String {
     format!("{}.{}", ie.module(), ie.field())
 }
 
+/// Crypto imports are gated by the crypto capability, which contracts declare by
+/// enabling the `crypto` feature of tableland_std.
+fn check_wasm_crypto_imports(module: &Module) -> VmResult<()> {
+    let imports = module
+        .import_section()
+        .map_or(vec![], |import_section| import_section.entries().to_vec());
+    let crypto_imports: BTreeSet<_> = imports
+        .iter()
+        .map(full_import_name)
+        .filter(|name| CRYPTO_IMPORTS.contains(&name.as_str()))
+        .collect();
+    if !crypto_imports.is_empty()
+        && !required_capabilities_from_module(module).contains(CRYPTO_CAPABILITY)
+    {
+        return Err(VmError::static_validation_err(format!(
+            "Wasm contract uses crypto imports {} without requiring the \"{}\" capability",
+            crypto_imports.to_string_limited(200),
+            CRYPTO_CAPABILITY
+        )));
+    }
+    Ok(())
+}
+
 fn check_wasm_capabilities(
     module: &Module,
     available_capabilities: &HashSet<String>,
@@ -187,6 +408,67 @@ mod tests {
         check_wasm(CONTRACT, &default_capabilities()).unwrap();
     }
 
+    #[test]
+    fn validate_wasm_passes_for_latest_contract() {
+        let report = validate_wasm(CONTRACT, &default_capabilities());
+        assert!(report.valid, "{:?}", report.errors);
+        assert_eq!(report.size, CONTRACT.len());
+        assert_eq!(
+            report.interface_version,
+            Some("interface_version_8".to_string())
+        );
+        assert!(report.missing_exports.is_empty());
+    }
+
+    #[test]
+    fn validate_wasm_collects_all_issues() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "db_read" (func (param i32) (result i32)))
+                (memory 1 5)
+                (func (result f32) f32.const 1 f32.const 2 f32.add)
+                (export "allocate" (func 1))
+                (export "requires_iterator" (func 1))
+            )"#,
+        )
+        .unwrap();
+        let report = validate_wasm(&wasm, &default_capabilities());
+        assert!(!report.valid);
+        assert_eq!(report.errors.len(), 5);
+        assert_eq!(report.memory_issues.len(), 1);
+        assert!(report.errors.contains(&report.memory_issues[0]));
+        assert_eq!(report.interface_version, None);
+        assert_eq!(report.missing_exports, vec!["deallocate", "fetch"]);
+        assert_eq!(report.unsupported_imports, vec!["env.db_read"]);
+        assert_eq!(report.missing_capabilities, vec!["iterator"]);
+        assert_eq!(report.float_operators, 3);
+
+        let report = validate_wasm(b"not wasm", &default_capabilities());
+        assert!(!report.valid);
+        assert_eq!(report.errors.len(), 1);
+    }
+
+    #[test]
+    fn count_float_operators_works() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (memory 1)
+                (func (param i32) (result i32)
+                    local.get 0
+                    f64.convert_i32_s
+                    i32.trunc_f64_s
+                    i32.const 1
+                    i32.add)
+                (func (param i64) (result i64)
+                    local.get 0
+                    f64.reinterpret_i64
+                    i64.reinterpret_f64)
+            )"#,
+        )
+        .unwrap();
+        assert_eq!(count_float_operators(&deserialize_wasm(&wasm).unwrap()), 4);
+    }
+
     #[test]
     fn check_wasm_memories_ok() {
         let wasm = wat::parse_str("(module (memory 1))").unwrap();
@@ -524,6 +806,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn check_wasm_crypto_imports_works() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (import "env" "keccak256" (func (param i32) (result i32)))
+        )"#,
+        )
+        .unwrap();
+        match check_wasm_crypto_imports(&deserialize_wasm(&wasm).unwrap()).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => assert_eq!(
+                msg,
+                r#"Wasm contract uses crypto imports {"env.keccak256"} without requiring the "crypto" capability"#
+            ),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+
+        let wasm = wat::parse_str(
+            r#"(module
+            (import "env" "keccak256" (func (param i32) (result i32)))
+            (type (func))
+            (func (type 1) nop)
+            (export "requires_crypto" (func 1))
+        )"#,
+        )
+        .unwrap();
+        check_wasm_crypto_imports(&deserialize_wasm(&wasm).unwrap()).unwrap();
+    }
+
     #[test]
     fn check_wasm_capabilities_ok() {
         let wasm = wat::parse_str(
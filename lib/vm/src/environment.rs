@@ -29,6 +29,10 @@ pub struct GasConfig {
     pub ed25519_batch_verify_cost: u64,
     /// ed25519 batch signature verification cost (single public key)
     pub ed25519_batch_verify_one_pubkey_cost: u64,
+    /// keccak256 hashing base cost
+    pub keccak256_cost: u64,
+    /// keccak256 hashing cost per input byte
+    pub keccak256_per_byte_cost: u64,
 }
 
 impl Default for GasConfig {
@@ -46,6 +50,9 @@ impl Default for GasConfig {
             // From https://docs.rs/ed25519-zebra/2.2.0/ed25519_zebra/batch/index.html
             ed25519_batch_verify_cost: 63 * GAS_PER_US / 2,
             ed25519_batch_verify_one_pubkey_cost: 63 * GAS_PER_US / 4,
+            // ~1 us plus ~4 ns per byte in crypto benchmarks
+            keccak256_cost: GAS_PER_US,
+            keccak256_per_byte_cost: GAS_PER_US / 250,
         }
     }
 }
@@ -1,11 +1,17 @@
 //! Import implementations
+use tableland_crypto::{
+    ed25519_batch_verify, ed25519_verify, keccak256, secp256k1_recover_pubkey, secp256k1_verify,
+    CryptoError, ECDSA_PUBKEY_MAX_LEN, ECDSA_SIGNATURE_LEN, EDDSA_PUBKEY_LEN, EDDSA_SIGNATURE_LEN,
+    MESSAGE_HASH_MAX_LEN,
+};
 use tableland_std::ReadRequest;
 
-use crate::backend::BackendApi;
+use crate::backend::{BackendApi, GasInfo};
 use crate::conversion::{ref_to_u32, to_u32};
 use crate::environment::{process_gas_info, Environment};
 use crate::errors::{CommunicationError, VmError, VmResult};
 use crate::memory::{read_region, write_region};
+use crate::sections::decode_sections;
 use crate::serde::{from_slice, to_vec};
 
 /// A kibi (kilo binary)
@@ -16,6 +22,33 @@ const MI: usize = 1024 * 1024;
 /// Max length for a Tableland read query statement.
 const MAX_LENGTH_QUERY_REQUEST: usize = KI;
 
+/// Max length for an environment variable name.
+const MAX_LENGTH_ENV_KEY: usize = 256;
+
+/// Max length of data to hash with keccak256.
+const MAX_LENGTH_KECCAK256_INPUT: usize = 128 * KI;
+
+/// Max length of a message hash for secp256k1 verification and recovery.
+const MAX_LENGTH_MESSAGE_HASH: usize = MESSAGE_HASH_MAX_LEN;
+
+/// Length of a serialized secp256k1 signature.
+const MAX_LENGTH_ECDSA_SIGNATURE: usize = ECDSA_SIGNATURE_LEN;
+
+/// Max length of a serialized secp256k1 public key.
+const MAX_LENGTH_ECDSA_PUBKEY: usize = ECDSA_PUBKEY_MAX_LEN;
+
+/// Max length of a message for ed25519 verification.
+const MAX_LENGTH_ED25519_MESSAGE: usize = 128 * KI;
+
+/// Length of a serialized ed25519 signature.
+const MAX_LENGTH_ED25519_SIGNATURE: usize = EDDSA_SIGNATURE_LEN;
+
+/// Length of a serialized ed25519 public key.
+const MAX_LENGTH_ED25519_PUBKEY: usize = EDDSA_PUBKEY_LEN;
+
+/// Max number of signatures in an ed25519 batch.
+const MAX_COUNT_ED25519_BATCH: usize = 256;
+
 /// Max length for a debug message
 const MAX_LENGTH_DEBUG: usize = 2 * MI;
 
@@ -49,6 +82,140 @@ pub fn do_read<A: BackendApi>(env: &Environment<A>, request_ptr: u32) -> VmResul
     write_to_contract::<A>(env, &serialized)
 }
 
+/// Returns a pointer to the value of an environment variable, or 0 if it isn't set.
+pub fn do_env_get<A: BackendApi>(env: &Environment<A>, key_ptr: u32) -> VmResult<u32> {
+    let key = read_region(&env.memory(), key_ptr, MAX_LENGTH_ENV_KEY)?;
+    let key = String::from_utf8(key).map_err(CommunicationError::invalid_utf8)?;
+
+    let (result, gas_info) = env.api.env_get(key.as_str());
+    process_gas_info::<A>(env, gas_info)?;
+    match result? {
+        Some(value) => write_to_contract::<A>(env, value.as_bytes()),
+        None => Ok(0),
+    }
+}
+
+/// Returns a pointer to the JSON-encoded latest block, or 0 if it isn't known.
+pub fn do_block<A: BackendApi>(env: &Environment<A>) -> VmResult<u32> {
+    let (result, gas_info) = env.api.block();
+    process_gas_info::<A>(env, gas_info)?;
+    match result? {
+        Some(block) => write_to_contract::<A>(env, &to_vec(&block)?),
+        None => Ok(0),
+    }
+}
+
+/// Returns a pointer to the 32-byte keccak256 hash of the data.
+pub fn do_keccak256<A: BackendApi>(env: &Environment<A>, data_ptr: u32) -> VmResult<u32> {
+    let data = read_region(&env.memory(), data_ptr, MAX_LENGTH_KECCAK256_INPUT)?;
+    let gas_info = GasInfo::with_cost(
+        env.gas_config.keccak256_cost + env.gas_config.keccak256_per_byte_cost * data.len() as u64,
+    );
+    process_gas_info::<A>(env, gas_info)?;
+    write_to_contract::<A>(env, &keccak256(&data))
+}
+
+/// Returns 0 if the signature is valid, 1 if it isn't, or an error code if an input is malformed.
+pub fn do_secp256k1_verify<A: BackendApi>(
+    env: &Environment<A>,
+    hash_ptr: u32,
+    signature_ptr: u32,
+    pubkey_ptr: u32,
+) -> VmResult<u32> {
+    let hash = read_region(&env.memory(), hash_ptr, MAX_LENGTH_MESSAGE_HASH)?;
+    let signature = read_region(&env.memory(), signature_ptr, MAX_LENGTH_ECDSA_SIGNATURE)?;
+    let pubkey = read_region(&env.memory(), pubkey_ptr, MAX_LENGTH_ECDSA_PUBKEY)?;
+
+    let gas_info = GasInfo::with_cost(env.gas_config.secp256k1_verify_cost);
+    process_gas_info::<A>(env, gas_info)?;
+    Ok(verification_code(secp256k1_verify(
+        &hash, &signature, &pubkey,
+    )))
+}
+
+/// Returns a pointer to the recovered uncompressed public key in the high half, or an error
+/// code in the low half if an input is malformed.
+pub fn do_secp256k1_recover_pubkey<A: BackendApi>(
+    env: &Environment<A>,
+    hash_ptr: u32,
+    signature_ptr: u32,
+    recovery_param: u32,
+) -> VmResult<u64> {
+    let hash = read_region(&env.memory(), hash_ptr, MAX_LENGTH_MESSAGE_HASH)?;
+    let signature = read_region(&env.memory(), signature_ptr, MAX_LENGTH_ECDSA_SIGNATURE)?;
+    let recovery_param = match u8::try_from(recovery_param) {
+        Ok(p) => p,
+        Err(_) => return Ok(to_low_half(CryptoError::InvalidRecoveryParam.code())),
+    };
+
+    let gas_info = GasInfo::with_cost(env.gas_config.secp256k1_recover_pubkey_cost);
+    process_gas_info::<A>(env, gas_info)?;
+    match secp256k1_recover_pubkey(&hash, &signature, recovery_param) {
+        Ok(pubkey) => Ok(to_high_half(write_to_contract::<A>(env, &pubkey)?)),
+        Err(err) => Ok(to_low_half(err.code())),
+    }
+}
+
+/// Returns 0 if the signature is valid, 1 if it isn't, or an error code if an input is malformed.
+pub fn do_ed25519_verify<A: BackendApi>(
+    env: &Environment<A>,
+    message_ptr: u32,
+    signature_ptr: u32,
+    pubkey_ptr: u32,
+) -> VmResult<u32> {
+    let message = read_region(&env.memory(), message_ptr, MAX_LENGTH_ED25519_MESSAGE)?;
+    let signature = read_region(&env.memory(), signature_ptr, MAX_LENGTH_ED25519_SIGNATURE)?;
+    let pubkey = read_region(&env.memory(), pubkey_ptr, MAX_LENGTH_ED25519_PUBKEY)?;
+
+    let gas_info = GasInfo::with_cost(env.gas_config.ed25519_verify_cost);
+    process_gas_info::<A>(env, gas_info)?;
+    Ok(verification_code(ed25519_verify(
+        &message, &signature, &pubkey,
+    )))
+}
+
+/// Verifies a batch of ed25519 signatures. Each argument points to sections encoded with
+/// `encode_sections`. Returns 0 if every signature is valid, 1 if any isn't, or an error code
+/// if an input is malformed.
+pub fn do_ed25519_batch_verify<A: BackendApi>(
+    env: &Environment<A>,
+    messages_ptr: u32,
+    signatures_ptr: u32,
+    pubkeys_ptr: u32,
+) -> VmResult<u32> {
+    let messages = read_region(
+        &env.memory(),
+        messages_ptr,
+        (MAX_LENGTH_ED25519_MESSAGE + 4) * MAX_COUNT_ED25519_BATCH,
+    )?;
+    let signatures = read_region(
+        &env.memory(),
+        signatures_ptr,
+        (MAX_LENGTH_ED25519_SIGNATURE + 4) * MAX_COUNT_ED25519_BATCH,
+    )?;
+    let pubkeys = read_region(
+        &env.memory(),
+        pubkeys_ptr,
+        (MAX_LENGTH_ED25519_PUBKEY + 4) * MAX_COUNT_ED25519_BATCH,
+    )?;
+
+    let messages = decode_sections(&messages);
+    let signatures = decode_sections(&signatures);
+    let pubkeys = decode_sections(&pubkeys);
+
+    let gas_cost = if pubkeys.len() == 1 {
+        env.gas_config.ed25519_batch_verify_one_pubkey_cost
+    } else {
+        env.gas_config.ed25519_batch_verify_cost
+    } * signatures.len() as u64;
+    process_gas_info::<A>(env, GasInfo::with_cost(gas_cost))?;
+    Ok(verification_code(ed25519_batch_verify(
+        &messages,
+        &signatures,
+        &pubkeys,
+    )))
+}
+
 /// Prints a debug message to console.
 /// This does not charge gas, so debug printing should be disabled when used in a blockchain module.
 pub fn do_debug<A: BackendApi>(env: &Environment<A>, message_ptr: u32) -> VmResult<()> {
@@ -67,6 +234,25 @@ pub fn do_abort<A: BackendApi>(env: &Environment<A>, message_ptr: u32) -> VmResu
     Err(VmError::aborted(msg))
 }
 
+/// Maps a verification result to what's returned to the contract.
+fn verification_code(result: Result<bool, CryptoError>) -> u32 {
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => err.code(),
+    }
+}
+
+/// Returns the data shifted by 32 bits towards the most significant bit.
+fn to_high_half(data: u32) -> u64 {
+    (data as u64) << 32
+}
+
+/// Returns the data as the least significant half of a u64.
+fn to_low_half(data: u32) -> u64 {
+    data.into()
+}
+
 /// Creates a Region in the contract, writes the given data to it and returns the memory location
 fn write_to_contract<A: BackendApi>(env: &Environment<A>, input: &[u8]) -> VmResult<u32> {
     let out_size = to_u32(input.len())?;
@@ -9,7 +9,10 @@ use crate::capabilities::required_capabilities_from_module;
 use crate::conversion::{ref_to_u32, to_u32};
 use crate::environment::Environment;
 use crate::errors::{CommunicationError, VmError, VmResult};
-use crate::imports::{do_abort, do_debug, do_read};
+use crate::imports::{
+    do_abort, do_block, do_debug, do_ed25519_batch_verify, do_ed25519_verify, do_env_get,
+    do_keccak256, do_read, do_secp256k1_recover_pubkey, do_secp256k1_verify,
+};
 use crate::memory::{read_region, write_region};
 use crate::size::Size;
 use crate::wasm_backend::compile;
@@ -88,6 +91,60 @@ where
             Function::new_native_with_env(store, env.clone(), do_read),
         );
 
+        // Looks up a variable in the function's environment.
+        // Takes a pointer argument of a memory region that must contain an UTF-8 encoded key.
+        // Returns a pointer to a new region holding the value, or 0 if the variable isn't set.
+        env_imports.insert(
+            "env_get",
+            Function::new_native_with_env(store, env.clone(), do_env_get),
+        );
+
+        // Returns the latest block the host has processed, which is fixed for the invocation.
+        // Returns a pointer to a new region holding the JSON-encoded block, or 0 if it isn't known.
+        env_imports.insert(
+            "block",
+            Function::new_native_with_env(store, env.clone(), do_block),
+        );
+
+        // Hashes data with keccak256.
+        // Returns a pointer to a new region holding the 32-byte hash.
+        env_imports.insert(
+            "keccak256",
+            Function::new_native_with_env(store, env.clone(), do_keccak256),
+        );
+
+        // Verifies a secp256k1 signature over a 32-byte message hash.
+        // Returns 0 on verification success, 1 on verification failure, and values
+        // greater than 1 in case of error.
+        env_imports.insert(
+            "secp256k1_verify",
+            Function::new_native_with_env(store, env.clone(), do_secp256k1_verify),
+        );
+
+        // Recovers the public key that signed a 32-byte message hash.
+        // Returns a pointer to a new region holding the uncompressed public key in the high
+        // 32 bits, or an error code in the low 32 bits.
+        env_imports.insert(
+            "secp256k1_recover_pubkey",
+            Function::new_native_with_env(store, env.clone(), do_secp256k1_recover_pubkey),
+        );
+
+        // Verifies an ed25519 signature over a message.
+        // Returns 0 on verification success, 1 on verification failure, and values
+        // greater than 1 in case of error.
+        env_imports.insert(
+            "ed25519_verify",
+            Function::new_native_with_env(store, env.clone(), do_ed25519_verify),
+        );
+
+        // Verifies a batch of ed25519 signatures, passed as encoded sections.
+        // Returns 0 on verification success, 1 on verification failure, and values
+        // greater than 1 in case of error.
+        env_imports.insert(
+            "ed25519_batch_verify",
+            Function::new_native_with_env(store, env.clone(), do_ed25519_batch_verify),
+        );
+
         // Allows the contract to emit debug logs that the host can either process or ignore.
         // This is never written to chain.
         // Takes a pointer argument of a memory region that must contain an UTF-8 encoded string.
@@ -154,6 +211,12 @@ where
         self.env.memory().size().0 as _
     }
 
+    /// Returns the approximate size in bytes of the compiled module plus the
+    /// instance's current memory. Useful as a cache cost.
+    pub fn size(&self) -> usize {
+        loupe::size_of_val(self._inner.module()) + self.env.memory().data_size() as usize
+    }
+
     /// Returns the currently remaining gas.
     pub fn get_gas_left(&self) -> u64 {
         self.env.get_gas_left()
@@ -512,6 +575,16 @@ mod tests {
         assert_eq!(instance.memory_pages(), 19);
     }
 
+    #[test]
+    fn size_includes_module_and_memory() {
+        let mut instance = mock_instance(CONTRACT, Vec::new());
+        let size = instance.size();
+        assert!(size > 17 * 64 * 1024);
+
+        instance.allocate(100 * 1024).expect("error allocating");
+        assert!(instance.size() >= size + 100 * 1024);
+    }
+
     #[test]
     fn get_gas_left_works() {
         let instance = mock_instance_with_gas_limit(CONTRACT, 123321, Vec::new());
@@ -1,6 +1,7 @@
 use serde_json::Value;
+use std::collections::HashMap;
 use tableland_client_types::ReadOptions;
-use tableland_std::Request;
+use tableland_std::{Block, Request};
 
 use crate::serde::from_slice;
 use crate::{Backend, BackendApi, BackendError, BackendResult, GasInfo};
@@ -10,6 +11,10 @@ const GAS_COST_QUERY_FLAT: u64 = 100_000;
 const GAS_COST_QUERY_REQUEST_MULTIPLIER: u64 = 0;
 /// Gas per reponse byte
 const GAS_COST_QUERY_RESPONSE_MULTIPLIER: u64 = 100;
+/// Base gas per environment lookup
+const GAS_COST_ENV_FLAT: u64 = 10_000;
+/// Base gas per block lookup
+const GAS_COST_BLOCK_FLAT: u64 = 10_000;
 
 /// All external requirements that can be injected for unit tests.
 /// It sets the given balance for the contract itself, nothing else
@@ -26,11 +31,29 @@ pub fn mock_backend() -> Backend<MockApi> {
 pub struct MockApi {
     /// Data for mock query response.
     data: Vec<u8>,
+    /// Variables for the mock environment.
+    env: HashMap<String, String>,
+    /// The mock latest block.
+    block: Option<Block>,
 }
 
 impl MockApi {
     pub fn new(data: Vec<u8>) -> Self {
-        MockApi { data }
+        MockApi {
+            data,
+            env: HashMap::new(),
+            block: None,
+        }
+    }
+
+    pub fn with_env(mut self, key: &str, value: &str) -> Self {
+        self.env.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_block(mut self, block: Block) -> Self {
+        self.block = Some(block);
+        self
     }
 }
 
@@ -55,6 +78,20 @@ impl BackendApi for MockApi {
 
         (Ok(response), gas_info)
     }
+
+    fn env_get(&self, key: &str) -> BackendResult<Option<String>> {
+        (
+            Ok(self.env.get(key).cloned()),
+            GasInfo::with_externally_used(GAS_COST_ENV_FLAT),
+        )
+    }
+
+    fn block(&self) -> BackendResult<Option<Block>> {
+        (
+            Ok(self.block),
+            GasInfo::with_externally_used(GAS_COST_BLOCK_FLAT),
+        )
+    }
 }
 
 pub fn mock_get_request(path: &'static str) -> Request {
@@ -85,4 +122,14 @@ mod tests {
         .0
         .unwrap();
     }
+
+    #[test]
+    fn env_get_works() {
+        let api = MockApi::default().with_env("TABLE", "players_31337_7");
+        assert_eq!(
+            api.env_get("TABLE").0.unwrap(),
+            Some("players_31337_7".to_string())
+        );
+        assert_eq!(api.env_get("OTHER").0.unwrap(), None);
+    }
 }
@@ -0,0 +1,190 @@
+use std::sync::Arc;
+use warp::{http::HeaderMap, Filter, Rejection};
+
+use crate::config::{ApiKey, ApiKeysConfig};
+use crate::errors::{StoreError, WorkerError};
+use crate::instance::DEFAULT_GAS_LIMIT;
+use crate::ledger::{Ledger, Reservation};
+
+/// Header used to identify a client by API key.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Resolves the request's API key and reserves an invocation's worth of gas
+/// against its quota, rejecting with 401 if the key is unknown (or missing
+/// when keys are required) and 402 if the key has used up its gas quota for
+/// the current period.
+pub fn with_api_key(
+    config: ApiKeysConfig,
+    ledger: Ledger,
+) -> impl Filter<Extract = (Option<Reservation>,), Error = Rejection> + Clone {
+    let config = Arc::new(config);
+    warp::header::headers_cloned()
+        .and(warp::any().map(move || config.clone()))
+        .and(warp::any().map(move || ledger.clone()))
+        .and_then(check_api_key)
+}
+
+async fn check_api_key(
+    headers: HeaderMap,
+    config: Arc<ApiKeysConfig>,
+    ledger: Ledger,
+) -> Result<Option<Reservation>, Rejection> {
+    let reject = |e: StoreError| warp::reject::custom(WorkerError::new(e, None));
+
+    let key = match headers.get(API_KEY_HEADER) {
+        Some(v) => v.to_str().unwrap_or_default(),
+        None if config.required => {
+            return Err(reject(StoreError::unauthorized_err("missing API key")))
+        }
+        None => return Ok(None),
+    };
+    let key = match find_key(&config.keys, key) {
+        Some(k) => k,
+        None => return Err(reject(StoreError::unauthorized_err("invalid API key"))),
+    };
+    let reservation = ledger
+        .reserve(key, DEFAULT_GAS_LIMIT)
+        .await
+        .map_err(reject)?;
+    Ok(Some(reservation))
+}
+
+pub fn find_key<'a>(keys: &'a [ApiKey], key: &str) -> Option<&'a ApiKey> {
+    keys.iter()
+        .find(|k| constant_time_eq(k.key.as_bytes(), key.as_bytes()))
+}
+
+/// Rejects with 401 unless the request carries `Authorization: Bearer <token>`.
+/// Admin endpoints are disabled when no token is configured.
+pub fn with_admin(token: String) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let token = Arc::new(token);
+    warp::header::optional::<String>("authorization")
+        .and(warp::any().map(move || token.clone()))
+        .and_then(check_admin)
+        .untuple_one()
+}
+
+async fn check_admin(header: Option<String>, token: Arc<String>) -> Result<(), Rejection> {
+    let given = header
+        .as_deref()
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if token.is_empty() || !constant_time_eq(token.as_bytes(), given.as_bytes()) {
+        return Err(warp::reject::custom(WorkerError::new(
+            StoreError::unauthorized_err("invalid admin token"),
+            None,
+        )));
+    }
+    Ok(())
+}
+
+/// Compares every byte of the longer input, so the time taken doesn't depend
+/// on where, or whether, the inputs differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let byte = |s: &[u8], i: usize| s.get(i).copied().unwrap_or_default() as usize;
+    (0..a.len().max(b.len())).fold(a.len() ^ b.len(), |acc, i| acc | (byte(a, i) ^ byte(b, i))) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use tableland_vm::GasReport;
+
+    fn config(required: bool) -> ApiKeysConfig {
+        ApiKeysConfig {
+            required,
+            keys: vec![ApiKey {
+                name: "acme".to_string(),
+                key: "secret".to_string(),
+                gas_quota: 10,
+                period: 3600,
+            }],
+        }
+    }
+
+    async fn status(
+        filter: &(impl Filter<Extract = (Option<Reservation>,), Error = Rejection> + Clone + 'static),
+        key: Option<&str>,
+    ) -> Result<Option<String>, StoreError> {
+        let mut req = warp::test::request();
+        if let Some(k) = key {
+            req = req.header(API_KEY_HEADER, k);
+        }
+        req.filter(filter)
+            .await
+            .map(|r| r.map(|r| r.name().to_string()))
+            .map_err(|e| e.find::<WorkerError>().unwrap().error.clone())
+    }
+
+    #[tokio::test]
+    async fn with_api_key_works() {
+        let ledger = Ledger::new(Db::open_in_memory().unwrap());
+        let filter = with_api_key(config(false), ledger.clone());
+
+        assert_eq!(status(&filter, None).await.unwrap(), None);
+        assert_eq!(
+            status(&filter, Some("secret")).await.unwrap(),
+            Some("acme".to_string())
+        );
+        assert!(matches!(
+            status(&filter, Some("nope")).await,
+            Err(StoreError::Unauthorized(_))
+        ));
+
+        let report = GasReport {
+            limit: 100,
+            remaining: 90,
+            used_externally: 4,
+            used_internally: 6,
+        };
+        ledger.record(Some("acme".to_string()), "cid".to_string(), report, false);
+        assert!(matches!(
+            status(&filter, Some("secret")).await,
+            Err(StoreError::QuotaExceeded(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn with_api_key_required() {
+        let ledger = Ledger::new(Db::open_in_memory().unwrap());
+        let filter = with_api_key(config(true), ledger);
+        assert!(matches!(
+            status(&filter, None).await,
+            Err(StoreError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn with_admin_works() {
+        let filter = with_admin("token".to_string());
+        assert!(warp::test::request()
+            .header("authorization", "Bearer token")
+            .filter(&filter)
+            .await
+            .is_ok());
+        assert!(warp::test::request()
+            .header("authorization", "Bearer nope")
+            .filter(&filter)
+            .await
+            .is_err());
+        assert!(warp::test::request().filter(&filter).await.is_err());
+
+        // No token disables admin endpoints.
+        let filter = with_admin(String::new());
+        assert!(warp::test::request()
+            .header("authorization", "Bearer ")
+            .filter(&filter)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn constant_time_eq_works() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret\0"));
+        assert!(!constant_time_eq(b"", b"secret"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
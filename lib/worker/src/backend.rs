@@ -1,6 +1,10 @@
 use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use tableland_client::Tableland;
 use tableland_client_types::ReadOptions;
+use tableland_std::Block;
 use tableland_vm::{BackendApi, BackendError, BackendResult, GasInfo};
 
 /// Base gas per request
@@ -9,6 +13,76 @@ const GAS_COST_QUERY_FLAT: u64 = 100_000;
 const GAS_COST_QUERY_REQUEST_MULTIPLIER: u64 = 0;
 /// Gas per reponse byte
 const GAS_COST_QUERY_RESPONSE_MULTIPLIER: u64 = 100;
+/// Base gas per environment lookup
+const GAS_COST_ENV_FLAT: u64 = 10_000;
+/// Base gas per block lookup
+const GAS_COST_BLOCK_FLAT: u64 = 10_000;
+
+/// Tag recorded by [`track_reads`] when the function read the latest block.
+/// Table tags are all digits, so it can't collide with one.
+pub const BLOCK_TAG: &str = "block";
+
+thread_local! {
+    /// Tables read on this thread while a [`track_reads`] call is running.
+    static READS: RefCell<Option<HashSet<String>>> = RefCell::new(None);
+    /// What the function running on this thread sees, set by [`with_invocation`].
+    static INVOCATION: RefCell<Option<Invocation>> = RefCell::new(None);
+}
+
+/// Host state that's fixed for the duration of an invocation.
+#[derive(Clone, Default)]
+pub struct Invocation {
+    /// The function's variables, for [`Api::env_get`].
+    pub vars: Arc<HashMap<String, String>>,
+    /// The latest block when the invocation started, for [`Api::block`].
+    pub block: Option<Block>,
+}
+
+/// Runs `f` as `invocation`. Like [`track_reads`], `f` must call into the
+/// instance directly.
+pub fn with_invocation<R>(invocation: Invocation, f: impl FnOnce() -> R) -> R {
+    let prev = INVOCATION.with(|i| i.replace(Some(invocation)));
+    let out = f();
+    INVOCATION.with(|i| i.replace(prev));
+    out
+}
+
+/// Runs `f`, returning its result along with the tags of every table it read
+/// through [`Api::read`], plus [`BLOCK_TAG`] if it read the block. Reads happen
+/// synchronously on the thread running the function, so `f` must call into the
+/// instance directly.
+pub fn track_reads<R>(f: impl FnOnce() -> R) -> (R, HashSet<String>) {
+    let prev = READS.with(|r| r.replace(Some(HashSet::new())));
+    let out = f();
+    let tables = READS.with(|r| r.replace(prev)).unwrap_or_default();
+    (out, tables)
+}
+
+/// Returns the tag for a Tableland table name, `{chain_id}_{table_id}`. The
+/// prefix is optional in queries, so it's left out.
+pub fn table_tag(name: &str) -> Option<String> {
+    let name = name.trim_matches(|c| c == '"' || c == '`' || c == '[' || c == ']');
+    let mut parts = name.rsplitn(3, '_');
+    let (id, chain, prefix) = (parts.next()?, parts.next()?, parts.next()?);
+    let numeric = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let valid_prefix = prefix
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'_')
+        && !prefix.starts_with(|c: char| c.is_ascii_digit());
+    if !numeric(id) || !numeric(chain) || !valid_prefix {
+        return None;
+    }
+    Some(format!("{}_{}", chain, id))
+}
+
+/// Returns the tags of the tables named in a statement. Anything that looks
+/// like a table name counts, so this may over-report, e.g., for string
+/// literals, but never misses a table.
+fn tables(statement: &str) -> impl Iterator<Item = String> + '_ {
+    statement
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .filter_map(table_tag)
+}
 
 #[derive(Clone)]
 pub struct Api<T: Tableland> {
@@ -29,6 +103,11 @@ impl<T: Tableland> BackendApi for Api<T> {
         if gas_info.externally_used > gas_limit {
             return (Err(BackendError::out_of_gas()), gas_info);
         }
+        READS.with(|r| {
+            if let Some(reads) = r.borrow_mut().as_mut() {
+                reads.extend(tables(statement));
+            }
+        });
 
         let (val, len) = match self.client.read(statement, options) {
             Ok(res) => res,
@@ -42,6 +121,24 @@ impl<T: Tableland> BackendApi for Api<T> {
 
         (Ok(val), gas_info)
     }
+
+    fn env_get(&self, key: &str) -> BackendResult<Option<String>> {
+        let value = INVOCATION.with(|i| i.borrow().as_ref().and_then(|i| i.vars.get(key).cloned()));
+        (Ok(value), GasInfo::with_externally_used(GAS_COST_ENV_FLAT))
+    }
+
+    fn block(&self) -> BackendResult<Option<Block>> {
+        let block = INVOCATION.with(|i| i.borrow().as_ref().and_then(|i| i.block));
+        READS.with(|r| {
+            if let Some(reads) = r.borrow_mut().as_mut() {
+                reads.insert(BLOCK_TAG.to_string());
+            }
+        });
+        (
+            Ok(block),
+            GasInfo::with_externally_used(GAS_COST_BLOCK_FLAT),
+        )
+    }
 }
 
 #[cfg(test)]
@@ -64,4 +161,59 @@ mod tests {
         .0
         .unwrap();
     }
+
+    #[test]
+    fn track_reads_works() {
+        let mut client = MockClient::new(ChainID::Local);
+        client.respond_with(b"[{}]".to_vec());
+        let api = Api::new(client);
+        let read = |s: &str| {
+            api.read(s, ReadOptions::default(), DEFAULT_QUERY_GAS_LIMIT)
+                .0
+                .unwrap()
+        };
+
+        let (_, tables) = track_reads(|| {
+            read("select * from players_31337_2 join \"_31337_3\" on id;");
+            read("select count(*) from teams_31337_4 where name = 'x_1';");
+        });
+        let mut tables: Vec<_> = tables.into_iter().collect();
+        tables.sort();
+        assert_eq!(tables, vec!["31337_2", "31337_3", "31337_4"]);
+
+        // Reads outside of a tracked call aren't recorded.
+        read("select * from players_31337_5;");
+        assert!(track_reads(|| ()).1.is_empty());
+    }
+
+    #[test]
+    fn with_invocation_works() {
+        let api = Api::new(MockClient::new(ChainID::Local));
+        let invocation = Invocation {
+            vars: Arc::new(HashMap::from([("TABLE".to_string(), "t_1_2".to_string())])),
+            block: Some(Block {
+                number: 10,
+                timestamp: 1_700_000_000,
+            }),
+        };
+        let ((value, block), tags) = with_invocation(invocation, || {
+            track_reads(|| (api.env_get("TABLE").0.unwrap(), api.block().0.unwrap()))
+        });
+        assert!(tags.contains(BLOCK_TAG));
+        assert_eq!(value.as_deref(), Some("t_1_2"));
+        assert_eq!(block.unwrap().number, 10);
+        assert_eq!(api.env_get("TABLE").0.unwrap(), None);
+        assert_eq!(api.block().0.unwrap(), None);
+    }
+
+    #[test]
+    fn table_tag_works() {
+        assert_eq!(table_tag("healthbot_80001_1").as_deref(), Some("80001_1"));
+        assert_eq!(table_tag("my_table_2_80001_1").as_deref(), Some("80001_1"));
+        assert_eq!(table_tag("_80001_1").as_deref(), Some("80001_1"));
+        assert_eq!(table_tag("`t_1_2`").as_deref(), Some("1_2"));
+        assert_eq!(table_tag("players"), None);
+        assert_eq!(table_tag("t_1"), None);
+        assert_eq!(table_tag("1t_1_2"), None);
+    }
 }
@@ -0,0 +1,94 @@
+use std::sync::{Arc, RwLock};
+use tableland_std::Block;
+
+use crate::response_cache::ResponseCache;
+
+/// Tracks the latest confirmed block on the worker's chain, which functions
+/// read with `ctx.block()`.
+#[derive(Clone, Default)]
+pub struct BlockTracker {
+    latest: Arc<RwLock<Option<Block>>>,
+    responses: Option<ResponseCache>,
+}
+
+impl BlockTracker {
+    /// Drops cached responses that read the block whenever it moves.
+    pub fn new(responses: ResponseCache) -> Self {
+        BlockTracker {
+            responses: Some(responses),
+            ..BlockTracker::default()
+        }
+    }
+
+    pub fn latest(&self) -> Option<Block> {
+        *self.latest.read().unwrap()
+    }
+
+    /// Sets the latest block.
+    pub fn set(&self, block: Option<Block>) {
+        let prev = std::mem::replace(&mut *self.latest.write().unwrap(), block);
+        if prev != block {
+            if let Some(responses) = &self.responses {
+                responses.invalidate_block();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::BLOCK_TAG;
+    use crate::config::ResponseCacheConfig;
+    use crate::response_cache::CachedResponse;
+    use std::collections::HashSet;
+    use warp::http::{HeaderMap, Method, StatusCode};
+
+    #[test]
+    fn set_drops_responses_that_read_the_block() {
+        let cache = ResponseCache::new(ResponseCacheConfig {
+            enabled: true,
+            ..Default::default()
+        });
+        let blocks = BlockTracker::new(cache.clone());
+        let headers = HeaderMap::new();
+        let key = |path| {
+            cache
+                .base_key("a", &Method::GET, path, false, None)
+                .unwrap()
+        };
+        let response = CachedResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: vec![],
+        };
+        let tags = |t: &[&str]| t.iter().map(|t| t.to_string()).collect::<HashSet<_>>();
+        cache.insert(
+            key("/block"),
+            &headers,
+            response.clone(),
+            tags(&[BLOCK_TAG]),
+        );
+        cache.insert(
+            key("/table"),
+            &headers,
+            response.clone(),
+            tags(&["31337_1"]),
+        );
+
+        let block = |number| {
+            Some(Block {
+                number,
+                timestamp: 1_700_000_000 + number * 12,
+            })
+        };
+        blocks.set(block(10));
+        assert!(cache.get(&key("/block"), &headers).is_none());
+        assert!(cache.get(&key("/table"), &headers).is_some());
+
+        // Only a new block drops them.
+        cache.insert(key("/block"), &headers, response, tags(&[BLOCK_TAG]));
+        blocks.set(block(10));
+        assert!(cache.get(&key("/block"), &headers).is_some());
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use stretto::{
+    AsyncCache, AsyncCacheBuilder, CacheCallback, DefaultCoster, DefaultKeyBuilder,
+    DefaultUpdateValidator, Item,
+};
+use tableland_client::TablelandClient;
+use tableland_vm::Instance;
+
+use crate::backend::Api;
+use crate::errors::StoreError;
+use crate::ledger::now;
+
+/// Seconds between mtime updates for a module that's served from memory.
+const TOUCH_INTERVAL: u64 = 60;
+
+pub type FnInstance = Instance<Api<TablelandClient>>;
+
+/// A cached instance along with its CID, so evictions can be logged.
+pub struct CachedFunction {
+    pub cid: String,
+    pub instance: FnInstance,
+    /// When the module file was last touched, in unix seconds.
+    touched: AtomicU64,
+}
+
+impl CachedFunction {
+    pub fn new(cid: String, instance: FnInstance) -> Self {
+        CachedFunction {
+            cid,
+            instance,
+            touched: AtomicU64::new(now()),
+        }
+    }
+
+    /// Marks the module file in `dir` as recently used, at most once every
+    /// [`TOUCH_INTERVAL`] seconds. Hits that never reach the disk would
+    /// otherwise leave the hottest modules looking the least recently used.
+    pub fn touch(&self, dir: &str) {
+        if !touch_due(&self.touched, now()) {
+            return;
+        }
+        let path = PathBuf::from(format!("{}/{}.wasm", dir, self.cid));
+        tokio::task::spawn_blocking(move || touch(&path));
+    }
+}
+
+/// Returns whether a touch is due, claiming it if so.
+fn touch_due(touched: &AtomicU64, now: u64) -> bool {
+    let last = touched.load(Ordering::Relaxed);
+    now >= last + TOUCH_INTERVAL
+        && touched
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+}
+
+pub type FnCache = AsyncCache<
+    String,
+    CachedFunction,
+    DefaultKeyBuilder<String>,
+    DefaultCoster<CachedFunction>,
+    DefaultUpdateValidator<CachedFunction>,
+    EvictionLogger,
+>;
+
+pub struct EvictionLogger;
+
+impl CacheCallback for EvictionLogger {
+    type Value = CachedFunction;
+
+    fn on_exit(&self, _val: Option<Self::Value>) {}
+
+    fn on_evict(&self, item: Item<Self::Value>) {
+        if let Some(f) = item.val {
+            println!("evicted {} ({} bytes)", f.cid, item.cost);
+        }
+    }
+
+    fn on_reject(&self, item: Item<Self::Value>) {
+        if let Some(f) = item.val {
+            println!("rejected {} ({} bytes)", f.cid, item.cost);
+        }
+    }
+}
+
+/// Creates an instance cache holding up to `max_memory` bytes of instances.
+pub fn new_fn_cache(max_memory: u64) -> Result<FnCache, StoreError> {
+    AsyncCacheBuilder::new(12960, max_memory as i64)
+        .set_ignore_internal_cost(true)
+        .set_callback(EvictionLogger)
+        .finalize(tokio::spawn)
+        .map_err(|e| StoreError::cache_err(e.to_string()))
+}
+
+/// Marks a cached module file as recently used.
+pub fn touch(path: &Path) {
+    if let Err(e) = filetime::set_file_mtime(path, filetime::FileTime::now()) {
+        eprintln!("error touching {}: {}", path.display(), e);
+    }
+}
+
+/// Removes the least recently used `.wasm` files in `dir` until their total
+/// size is at most `max_bytes`. Files for CIDs in `keep` are never removed.
+/// Returns the removed CIDs.
+pub fn gc_dir(dir: &Path, max_bytes: u64, keep: &HashSet<String>) -> std::io::Result<Vec<String>> {
+    let mut files: Vec<(SystemTime, u64, String)> = vec![];
+    let mut total = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("wasm") {
+            continue;
+        }
+        let cid = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(c) => c.to_string(),
+            None => continue,
+        };
+        let meta = entry.metadata()?;
+        total += meta.len();
+        if !keep.contains(&cid) {
+            files.push((meta.modified()?, meta.len(), cid));
+        }
+    }
+
+    files.sort();
+    let mut removed = vec![];
+    for (_, len, cid) in files {
+        if total <= max_bytes {
+            break;
+        }
+        std::fs::remove_file(dir.join(format!("{}.wasm", cid)))?;
+        total -= len;
+        removed.push(cid);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filetime::FileTime;
+
+    #[test]
+    fn gc_dir_works() {
+        let dir = std::env::temp_dir().join(format!("tbl-gc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, cid) in ["a", "b", "c", "d"].iter().enumerate() {
+            let path = dir.join(format!("{}.wasm", cid));
+            std::fs::write(&path, [0u8; 10]).unwrap();
+            filetime::set_file_mtime(&path, FileTime::from_unix_time(1_000 + i as i64, 0)).unwrap();
+        }
+        std::fs::write(dir.join("notes.txt"), [0u8; 100]).unwrap();
+
+        // "a" is the oldest but kept.
+        let keep = HashSet::from(["a".to_string()]);
+        let removed = gc_dir(&dir, 25, &keep).unwrap();
+        assert_eq!(removed, vec!["b".to_string(), "c".to_string()]);
+        assert!(dir.join("a.wasm").exists());
+        assert!(dir.join("d.wasm").exists());
+        assert!(dir.join("notes.txt").exists());
+
+        assert!(gc_dir(&dir, 25, &keep).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn touch_due_works() {
+        let touched = AtomicU64::new(1_000);
+        assert!(!touch_due(&touched, 1_000 + TOUCH_INTERVAL - 1));
+        assert!(touch_due(&touched, 1_000 + TOUCH_INTERVAL));
+        assert!(!touch_due(&touched, 1_000 + TOUCH_INTERVAL));
+        assert_eq!(touched.load(Ordering::Relaxed), 1_000 + TOUCH_INTERVAL);
+    }
+}
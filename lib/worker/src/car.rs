@@ -0,0 +1,211 @@
+//! Reading function modules out of CARv1 archives.
+mod cbor;
+pub mod cid;
+mod unixfs;
+mod varint;
+
+use std::collections::HashMap;
+
+use crate::errors::StoreError;
+use cid::{Cid, DAG_PB, RAW};
+use unixfs::PbNode;
+
+/// Max depth of a UnixFS file DAG.
+const MAX_DEPTH: usize = 32;
+/// Max number of nodes visited while reassembling a file. Shared children are
+/// visited once per link, so this bounds DAGs that fan out to the same block.
+const MAX_VISITS: usize = 1 << 16;
+
+/// A parsed CAR with verified blocks.
+pub struct Car {
+    pub roots: Vec<Cid>,
+    blocks: HashMap<Cid, Vec<u8>>,
+}
+
+/// Parses a CARv1 archive, checking every block against its CID.
+pub fn read_car(buf: &[u8]) -> Result<Car, StoreError> {
+    let eof = || StoreError::car_err("unexpected end of CAR");
+    let (header_len, n) = varint::decode(buf)?;
+    let header_end = n.checked_add(header_len as usize).ok_or_else(eof)?;
+    let header = cbor::decode(buf.get(n..header_end).ok_or_else(eof)?)?;
+    if header.get("version") != Some(&cbor::Value::Uint(1)) {
+        return Err(StoreError::car_err("unsupported CAR version"));
+    }
+    let roots = match header.get("roots") {
+        Some(cbor::Value::Array(roots)) => roots
+            .iter()
+            .map(|r| match r {
+                // CIDs in DAG-CBOR are tag 42 with a leading identity multibase byte.
+                cbor::Value::Tag(42, v) => match v.as_ref() {
+                    cbor::Value::Bytes(b) if b.first() == Some(&0) => {
+                        Ok(Cid::read_bytes(&b[1..])?.0)
+                    }
+                    _ => Err(StoreError::car_err("invalid root CID")),
+                },
+                _ => Err(StoreError::car_err("invalid root CID")),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(StoreError::car_err("missing CAR roots")),
+    };
+
+    let mut blocks = HashMap::new();
+    let mut pos = header_end;
+    while pos < buf.len() {
+        let (len, n) = varint::decode(&buf[pos..])?;
+        pos += n;
+        let end = pos.checked_add(len as usize).ok_or_else(eof)?;
+        let section = buf.get(pos..end).ok_or_else(eof)?;
+        let (cid, n) = Cid::read_bytes(section)?;
+        let data = &section[n..];
+        cid.verify(data)?;
+        blocks.insert(cid, data.to_vec());
+        pos = end;
+    }
+    Ok(Car { roots, blocks })
+}
+
+impl Car {
+    /// Reassembles the UnixFS file rooted at `root`.
+    pub fn extract_file(&self, root: &Cid, max_size: usize) -> Result<Vec<u8>, StoreError> {
+        let mut out = vec![];
+        let mut visits = 0;
+        self.collect(root, max_size, 0, &mut visits, &mut out)?;
+        Ok(out)
+    }
+
+    fn collect(
+        &self,
+        cid: &Cid,
+        max_size: usize,
+        depth: usize,
+        visits: &mut usize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), StoreError> {
+        *visits += 1;
+        if depth > MAX_DEPTH || *visits > MAX_VISITS {
+            return Err(StoreError::car_err("file DAG is too large"));
+        }
+        let block = self
+            .blocks
+            .get(cid)
+            .ok_or_else(|| StoreError::car_err(format!("missing block {}", cid)))?;
+
+        match cid.codec {
+            RAW => out.extend(block),
+            DAG_PB => {
+                let node = PbNode::decode(block)?;
+                out.extend(unixfs::file_data(&node.data)?);
+                for link in node.links.iter() {
+                    self.collect(link, max_size, depth + 1, visits, out)?;
+                }
+            }
+            c => {
+                return Err(StoreError::car_err(format!(
+                    "unsupported codec {:#x} in file DAG",
+                    c
+                )))
+            }
+        }
+        if out.len() > max_size {
+            return Err(StoreError::PayloadTooLarge);
+        }
+        Ok(())
+    }
+}
+
+/// Extracts the module from a single-root CAR, returning its CID and bytes.
+pub fn import(buf: &[u8], max_size: usize) -> Result<(String, Vec<u8>), StoreError> {
+    let car = read_car(buf)?;
+    let root = match car.roots.as_slice() {
+        [root] => root,
+        _ => return Err(StoreError::car_err("CAR must have exactly one root")),
+    };
+    let module = car.extract_file(root, max_size)?;
+    Ok((root.to_string(), module))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unixfs::testing::file_node;
+    use super::*;
+
+    fn block(cid: &Cid, data: &[u8]) -> Vec<u8> {
+        let mut section = cid.to_bytes();
+        section.extend(data);
+        let mut out = varint::encode(section.len() as u64);
+        out.extend(section);
+        out
+    }
+
+    fn car(roots: &[&Cid], blocks: &[(&Cid, &[u8])]) -> Vec<u8> {
+        // {"roots": [42(h'00..')...], "version": 1}
+        let mut header = vec![0xa2, 0x65];
+        header.extend(b"roots");
+        header.push(0x80 | roots.len() as u8);
+        for root in roots {
+            let mut bytes = vec![0];
+            bytes.extend(root.to_bytes());
+            header.extend([0xd8, 0x2a, 0x58, bytes.len() as u8]);
+            header.extend(bytes);
+        }
+        header.push(0x67);
+        header.extend(b"version");
+        header.push(0x01);
+
+        let mut out = varint::encode(header.len() as u64);
+        out.extend(header);
+        for (cid, data) in blocks {
+            out.extend(block(cid, data));
+        }
+        out
+    }
+
+    #[test]
+    fn import_raw_works() {
+        let cid = Cid::new_v1(RAW, b"module");
+        let (s, module) = import(&car(&[&cid], &[(&cid, b"module")]), 1024).unwrap();
+        assert_eq!(s, cid.to_string());
+        assert_eq!(module, b"module");
+    }
+
+    #[test]
+    fn import_multi_block_works() {
+        let a = Cid::new_v1(RAW, b"aaa");
+        let b = Cid::new_v1(RAW, b"bbb");
+        let inner = file_node(b"", &[b.to_bytes()]);
+        let inner_cid = Cid::new_v1(DAG_PB, &inner);
+        let root = file_node(b"head-", &[a.to_bytes(), inner_cid.to_bytes()]);
+        let root_cid = Cid::new_v1(DAG_PB, &root);
+
+        let buf = car(
+            &[&root_cid],
+            &[
+                (&root_cid, &root),
+                (&a, b"aaa"),
+                (&inner_cid, &inner),
+                (&b, b"bbb"),
+            ],
+        );
+        let (s, module) = import(&buf, 1024).unwrap();
+        assert_eq!(s, root_cid.to_string());
+        assert_eq!(module, b"head-aaabbb");
+
+        assert!(matches!(import(&buf, 5), Err(StoreError::PayloadTooLarge)));
+    }
+
+    #[test]
+    fn import_rejects_bad_blocks() {
+        let cid = Cid::new_v1(RAW, b"module");
+        let other = Cid::new_v1(RAW, b"other");
+
+        // Tampered block data.
+        assert!(import(&car(&[&cid], &[(&cid, b"modulE")]), 1024).is_err());
+        // Root not in the CAR.
+        assert!(import(&car(&[&cid], &[(&other, b"other")]), 1024).is_err());
+        // Multiple roots.
+        assert!(import(&car(&[&cid, &other], &[(&cid, b"module")]), 1024).is_err());
+        // Truncated.
+        let buf = car(&[&cid], &[(&cid, b"module")]);
+        assert!(import(&buf[..buf.len() - 1], 1024).is_err());
+    }
+}
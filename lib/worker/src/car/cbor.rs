@@ -0,0 +1,151 @@
+//! A minimal DAG-CBOR decoder, enough to read CAR headers.
+use crate::errors::StoreError;
+
+/// Max nesting depth accepted when decoding.
+const MAX_DEPTH: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Uint(u64),
+    Neg(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Tag(u64, Box<Value>),
+    Bool(bool),
+    Null,
+}
+
+impl Value {
+    /// Looks up a text key in a map.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| matches!(k, Value::Text(t) if t == key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes a single value that must span all of `buf`.
+pub fn decode(buf: &[u8]) -> Result<Value, StoreError> {
+    let (value, n) = decode_value(buf, 0)?;
+    if n != buf.len() {
+        return Err(StoreError::car_err("trailing bytes after CBOR value"));
+    }
+    Ok(value)
+}
+
+fn decode_value(buf: &[u8], depth: usize) -> Result<(Value, usize), StoreError> {
+    let eof = || StoreError::car_err("unexpected end of CBOR");
+    if depth > MAX_DEPTH {
+        return Err(StoreError::car_err("CBOR nested too deeply"));
+    }
+
+    let first = *buf.first().ok_or_else(eof)?;
+    let major = first >> 5;
+    let info = first & 0x1f;
+    let (arg, mut pos) = match info {
+        0..=23 => (info as u64, 1),
+        24..=27 => {
+            let len = 1 << (info - 24);
+            let bytes = buf.get(1..1 + len).ok_or_else(eof)?;
+            (
+                bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64),
+                1 + len,
+            )
+        }
+        _ => return Err(StoreError::car_err("unsupported CBOR length")),
+    };
+
+    let value = match major {
+        0 => Value::Uint(arg),
+        1 => Value::Neg(arg),
+        2 | 3 => {
+            let end = pos.checked_add(arg as usize).ok_or_else(eof)?;
+            let bytes = buf.get(pos..end).ok_or_else(eof)?.to_vec();
+            pos = end;
+            if major == 2 {
+                Value::Bytes(bytes)
+            } else {
+                Value::Text(
+                    String::from_utf8(bytes).map_err(|_| StoreError::car_err("invalid UTF-8"))?,
+                )
+            }
+        }
+        4 => {
+            let mut items = vec![];
+            for _ in 0..arg {
+                let (v, n) = decode_value(&buf[pos..], depth + 1)?;
+                items.push(v);
+                pos += n;
+            }
+            Value::Array(items)
+        }
+        5 => {
+            let mut entries = vec![];
+            for _ in 0..arg {
+                let (k, n) = decode_value(&buf[pos..], depth + 1)?;
+                pos += n;
+                let (v, n) = decode_value(&buf[pos..], depth + 1)?;
+                pos += n;
+                entries.push((k, v));
+            }
+            Value::Map(entries)
+        }
+        6 => {
+            let (v, n) = decode_value(&buf[pos..], depth + 1)?;
+            pos += n;
+            Value::Tag(arg, Box::new(v))
+        }
+        _ => match info {
+            20 => Value::Bool(false),
+            21 => Value::Bool(true),
+            22 => Value::Null,
+            _ => return Err(StoreError::car_err("unsupported CBOR simple value")),
+        },
+    };
+    Ok((value, pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_works() {
+        // {"a": [1, -2, h'ff', true, null], "b": 42("x")}
+        let buf = [
+            0xa2, 0x61, b'a', 0x85, 0x01, 0x21, 0x41, 0xff, 0xf5, 0xf6, 0x61, b'b', 0xd8, 0x2a,
+            0x61, b'x',
+        ];
+        let value = decode(&buf).unwrap();
+        assert_eq!(
+            value.get("a"),
+            Some(&Value::Array(vec![
+                Value::Uint(1),
+                Value::Neg(1),
+                Value::Bytes(vec![0xff]),
+                Value::Bool(true),
+                Value::Null
+            ]))
+        );
+        assert_eq!(
+            value.get("b"),
+            Some(&Value::Tag(42, Box::new(Value::Text("x".to_string()))))
+        );
+        assert_eq!(value.get("c"), None);
+    }
+
+    #[test]
+    fn decode_rejects_invalid() {
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[0x62, b'a']).is_err());
+        assert!(decode(&[0x01, 0x02]).is_err());
+        assert!(decode(&[0x9f]).is_err());
+        assert!(decode(&[0x81; 32]).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+use super::varint;
+use crate::errors::StoreError;
+
+pub const RAW: u64 = 0x55;
+pub const DAG_PB: u64 = 0x70;
+pub const SHA2_256: u64 = 0x12;
+
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// A content identifier. Only sha2-256 multihashes are supported.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Cid {
+    pub version: u64,
+    pub codec: u64,
+    pub digest: Vec<u8>,
+}
+
+impl Cid {
+    /// Creates a CIDv1 for `data`.
+    #[cfg(test)]
+    pub fn new_v1(codec: u64, data: &[u8]) -> Self {
+        Cid {
+            version: 1,
+            codec,
+            digest: Sha256::digest(data).to_vec(),
+        }
+    }
+
+    /// Reads a binary CID from the front of `buf`, returning it and the
+    /// number of bytes read.
+    pub fn read_bytes(buf: &[u8]) -> Result<(Self, usize), StoreError> {
+        // CIDv0 is a bare sha2-256 multihash.
+        if buf.len() >= 34 && buf[0] == SHA2_256 as u8 && buf[1] == 32 {
+            return Ok((
+                Cid {
+                    version: 0,
+                    codec: DAG_PB,
+                    digest: buf[2..34].to_vec(),
+                },
+                34,
+            ));
+        }
+
+        let mut pos = 0;
+        let mut next = |buf: &[u8]| -> Result<u64, StoreError> {
+            let (v, n) = varint::decode(buf.get(pos..).unwrap_or_default())?;
+            pos += n;
+            Ok(v)
+        };
+        let version = next(buf)?;
+        if version != 1 {
+            return Err(StoreError::car_err(format!(
+                "unsupported CID version {}",
+                version
+            )));
+        }
+        let codec = next(buf)?;
+        let hash_code = next(buf)?;
+        if hash_code != SHA2_256 {
+            return Err(StoreError::car_err(format!(
+                "unsupported multihash {:#x}",
+                hash_code
+            )));
+        }
+        let len = next(buf)? as usize;
+        if len != 32 || buf.len() < pos + len {
+            return Err(StoreError::car_err("invalid multihash length"));
+        }
+        let digest = buf[pos..pos + len].to_vec();
+        Ok((
+            Cid {
+                version,
+                codec,
+                digest,
+            },
+            pos + len,
+        ))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        if self.version == 1 {
+            out.extend(varint::encode(1));
+            out.extend(varint::encode(self.codec));
+        }
+        out.extend(varint::encode(SHA2_256));
+        out.extend(varint::encode(self.digest.len() as u64));
+        out.extend(&self.digest);
+        out
+    }
+
+    /// Checks that `data` hashes to this CID.
+    pub fn verify(&self, data: &[u8]) -> Result<(), StoreError> {
+        if Sha256::digest(data).as_slice() != self.digest.as_slice() {
+            return Err(StoreError::car_err(format!(
+                "hash mismatch for block {}",
+                self
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.version == 0 {
+            write!(f, "{}", base58_encode(&self.to_bytes()))
+        } else {
+            write!(f, "b{}", base32_encode(&self.to_bytes()))
+        }
+    }
+}
+
+impl FromStr for Cid {
+    type Err = StoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || StoreError::car_err(format!("invalid CID {}", s));
+        let bytes = if s.len() == 46 && s.starts_with("Qm") {
+            base58_decode(s).ok_or_else(invalid)?
+        } else if let Some(rest) = s.strip_prefix('b') {
+            base32_decode(rest).ok_or_else(invalid)?
+        } else {
+            return Err(invalid());
+        };
+        let (cid, n) = Cid::read_bytes(&bytes)?;
+        if n != bytes.len() {
+            return Err(invalid());
+        }
+        Ok(cid)
+    }
+}
+
+/// RFC 4648 base32, lowercase and unpadded, as used by multibase `b`.
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for b in data {
+        buffer = (buffer << 8) | *b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        let v = BASE32_ALPHABET.iter().position(|a| *a == c)? as u32;
+        buffer = (buffer << 5) | v;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn base58_encode(data: &[u8]) -> String {
+    let zeros = data.iter().take_while(|b| **b == 0).count();
+    let mut digits: Vec<u8> = vec![];
+    for b in data {
+        let mut carry = *b as u32;
+        for d in digits.iter_mut() {
+            carry += (*d as u32) << 8;
+            *d = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let mut out = "1".repeat(zeros);
+    out.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|d| BASE58_ALPHABET[*d as usize] as char),
+    );
+    out
+}
+
+fn base58_decode(s: &str) -> Option<Vec<u8>> {
+    let zeros = s.bytes().take_while(|c| *c == b'1').count();
+    let mut bytes: Vec<u8> = vec![];
+    for c in s.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|a| *a == c)? as u32;
+        for b in bytes.iter_mut() {
+            carry += (*b as u32) * 58;
+            *b = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let mut out = vec![0; zeros];
+    out.extend(bytes.iter().rev());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cid_v1_works() {
+        let cid = Cid::new_v1(RAW, b"hello world");
+        assert_eq!(
+            cid.to_string(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+        assert_eq!(cid.to_string().parse::<Cid>().unwrap(), cid);
+        assert_eq!(Cid::read_bytes(&cid.to_bytes()).unwrap(), (cid.clone(), 36));
+        assert!(cid.verify(b"hello world").is_ok());
+        assert!(cid.verify(b"goodbye").is_err());
+    }
+
+    #[test]
+    fn cid_v0_works() {
+        let cid = Cid {
+            version: 0,
+            codec: DAG_PB,
+            digest: Sha256::digest(b"hello world").to_vec(),
+        };
+        let s = cid.to_string();
+        assert_eq!(s, "QmaozNR7DZHQK1ZcU9p7QdrshMvXqWK6gpu5rmrkPdT3L4");
+        assert_eq!(s.parse::<Cid>().unwrap(), cid);
+        assert_eq!(Cid::read_bytes(&cid.to_bytes()).unwrap(), (cid, 34));
+    }
+
+    #[test]
+    fn cid_rejects_invalid() {
+        assert!("zb2rhe5P4gXftAwvA4eXQ5HJwsER2owDyS9sKaQRRVQPn93bA"
+            .parse::<Cid>()
+            .is_err());
+        assert!("bafy!".parse::<Cid>().is_err());
+        assert!("Qm".parse::<Cid>().is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! Minimal dag-pb and UnixFS decoding for reassembling files.
+use super::cid::Cid;
+use super::varint;
+use crate::errors::StoreError;
+
+const UNIXFS_RAW: u64 = 0;
+const UNIXFS_FILE: u64 = 2;
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Splits a protobuf message into `(field number, value)` pairs.
+fn fields(mut buf: &[u8]) -> Result<Vec<(u64, Field<'_>)>, StoreError> {
+    let eof = || StoreError::car_err("unexpected end of protobuf");
+    let mut out = vec![];
+    while !buf.is_empty() {
+        let (key, n) = varint::decode(buf)?;
+        buf = &buf[n..];
+        let field = match key & 7 {
+            0 => {
+                let (v, n) = varint::decode(buf)?;
+                buf = &buf[n..];
+                Field::Varint(v)
+            }
+            2 => {
+                let (len, n) = varint::decode(buf)?;
+                let end = n.checked_add(len as usize).ok_or_else(eof)?;
+                let bytes = buf.get(n..end).ok_or_else(eof)?;
+                buf = &buf[end..];
+                Field::Bytes(bytes)
+            }
+            _ => return Err(StoreError::car_err("unsupported protobuf wire type")),
+        };
+        out.push((key >> 3, field));
+    }
+    Ok(out)
+}
+
+/// A decoded dag-pb node.
+pub struct PbNode {
+    pub links: Vec<Cid>,
+    pub data: Vec<u8>,
+}
+
+impl PbNode {
+    pub fn decode(buf: &[u8]) -> Result<Self, StoreError> {
+        let mut node = PbNode {
+            links: vec![],
+            data: vec![],
+        };
+        for (num, field) in fields(buf)? {
+            match (num, field) {
+                (1, Field::Bytes(b)) => node.data = b.to_vec(),
+                (2, Field::Bytes(b)) => {
+                    let hash = fields(b)?.into_iter().find_map(|(n, f)| match (n, f) {
+                        (1, Field::Bytes(h)) => Some(h),
+                        _ => None,
+                    });
+                    let hash = hash.ok_or_else(|| StoreError::car_err("link without hash"))?;
+                    node.links.push(Cid::read_bytes(hash)?.0);
+                }
+                _ => return Err(StoreError::car_err("invalid dag-pb node")),
+            }
+        }
+        Ok(node)
+    }
+}
+
+/// Returns the file bytes held directly in a UnixFS node's data.
+pub fn file_data(data: &[u8]) -> Result<Vec<u8>, StoreError> {
+    let mut kind = None;
+    let mut content = vec![];
+    for (num, field) in fields(data)? {
+        match (num, field) {
+            (1, Field::Varint(t)) => kind = Some(t),
+            (2, Field::Bytes(b)) => content = b.to_vec(),
+            _ => {}
+        }
+    }
+    match kind {
+        Some(UNIXFS_FILE) | Some(UNIXFS_RAW) => Ok(content),
+        Some(t) => Err(StoreError::car_err(format!(
+            "unsupported UnixFS node type {}",
+            t
+        ))),
+        None => Err(StoreError::car_err("missing UnixFS node type")),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::varint;
+
+    pub fn bytes_field(num: u64, value: &[u8]) -> Vec<u8> {
+        let mut out = varint::encode(num << 3 | 2);
+        out.extend(varint::encode(value.len() as u64));
+        out.extend(value);
+        out
+    }
+
+    pub fn varint_field(num: u64, value: u64) -> Vec<u8> {
+        let mut out = varint::encode(num << 3);
+        out.extend(varint::encode(value));
+        out
+    }
+
+    /// Encodes a UnixFS file node with the given inline data and links.
+    pub fn file_node(data: &[u8], links: &[Vec<u8>]) -> Vec<u8> {
+        let mut unixfs = varint_field(1, 2);
+        if !data.is_empty() {
+            unixfs.extend(bytes_field(2, data));
+        }
+        let mut out = vec![];
+        for link in links {
+            out.extend(bytes_field(2, &bytes_field(1, link)));
+        }
+        out.extend(bytes_field(1, &unixfs));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+    use crate::car::cid::RAW;
+
+    #[test]
+    fn decode_works() {
+        let child = Cid::new_v1(RAW, b"child");
+        let node = PbNode::decode(&file_node(b"hi", &[child.to_bytes()])).unwrap();
+        assert_eq!(node.links, vec![child]);
+        assert_eq!(file_data(&node.data).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn file_data_rejects_directories() {
+        let dir = varint_field(1, 1);
+        assert!(file_data(&dir).is_err());
+        assert!(file_data(&[]).is_err());
+    }
+}
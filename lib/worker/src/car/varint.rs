@@ -0,0 +1,43 @@
+use crate::errors::StoreError;
+
+/// Decodes an unsigned LEB128 varint, returning the value and the number of
+/// bytes read.
+pub fn decode(buf: &[u8]) -> Result<(u64, usize), StoreError> {
+    let mut value: u64 = 0;
+    for (i, b) in buf.iter().enumerate().take(10) {
+        value |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(StoreError::car_err("invalid varint"))
+}
+
+pub fn encode(mut value: u64) -> Vec<u8> {
+    let mut out = vec![];
+    loop {
+        let b = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(b);
+            return out;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_works() {
+        for v in [0, 1, 127, 128, 300, 0x70, u32::MAX as u64, u64::MAX] {
+            let buf = encode(v);
+            assert_eq!(decode(&buf).unwrap(), (v, buf.len()));
+        }
+        assert_eq!(encode(300), vec![0xac, 0x02]);
+        assert!(decode(&[0x80]).is_err());
+        assert!(decode(&[]).is_err());
+    }
+}
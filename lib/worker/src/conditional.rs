@@ -0,0 +1,169 @@
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+use warp::http::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, LAST_MODIFIED,
+};
+use warp::http::{Method, StatusCode};
+
+/// Validators from a conditional `GET` or `HEAD` request.
+#[derive(Debug, Default)]
+pub struct Conditions {
+    if_none_match: Option<String>,
+    if_modified_since: Option<SystemTime>,
+}
+
+impl Conditions {
+    pub fn from_request(method: &Method, headers: &HeaderMap) -> Self {
+        if method != Method::GET && method != Method::HEAD {
+            return Conditions::default();
+        }
+        let header = |name: HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+        Conditions {
+            if_none_match: header(IF_NONE_MATCH).map(|v| v.to_string()),
+            if_modified_since: header(IF_MODIFIED_SINCE)
+                .and_then(|v| httpdate::parse_http_date(v).ok()),
+        }
+    }
+
+    /// Tags successful responses with an `ETag`, unless the function set one,
+    /// and turns them into `304 Not Modified` responses without a body if the
+    /// client's copy is current.
+    pub fn apply(
+        &self,
+        status: StatusCode,
+        headers: &mut HeaderMap,
+        body: Vec<u8>,
+    ) -> (StatusCode, Vec<u8>) {
+        if status != StatusCode::OK {
+            return (status, body);
+        }
+        if !headers.contains_key(ETAG) {
+            headers.insert(ETAG, etag(&body));
+        }
+
+        let fresh = match &self.if_none_match {
+            // If-Modified-Since is ignored when If-None-Match is present.
+            Some(tags) => {
+                let current = headers.get(ETAG).and_then(|v| v.to_str().ok());
+                current.map_or(false, |c| etag_matches(tags, c))
+            }
+            None => match (self.if_modified_since, headers.get(LAST_MODIFIED)) {
+                (Some(since), Some(modified)) => modified
+                    .to_str()
+                    .ok()
+                    .and_then(|m| httpdate::parse_http_date(m).ok())
+                    .map_or(false, |m| m <= since),
+                _ => false,
+            },
+        };
+        if !fresh {
+            return (status, body);
+        }
+        headers.remove(CONTENT_TYPE);
+        headers.remove(CONTENT_LENGTH);
+        (StatusCode::NOT_MODIFIED, vec![])
+    }
+}
+
+/// Returns a strong ETag derived from a response body.
+fn etag(body: &[u8]) -> HeaderValue {
+    let hash = Sha256::digest(body);
+    HeaderValue::from_str(&format!("\"{}\"", hex::encode(&hash[..16]))).unwrap()
+}
+
+/// Checks an `If-None-Match` list against a tag using weak comparison, which
+/// ignores the `W/` prefix.
+fn etag_matches(tags: &str, current: &str) -> bool {
+    let opaque = |t: &str| t.trim().trim_start_matches("W/").to_string();
+    tags.trim() == "*" || tags.split(',').any(|t| opaque(t) == opaque(current))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditions(headers: &[(&'static str, &str)]) -> Conditions {
+        let mut map = HeaderMap::new();
+        for (k, v) in headers {
+            map.insert(*k, v.parse().unwrap());
+        }
+        Conditions::from_request(&Method::GET, &map)
+    }
+
+    #[test]
+    fn apply_sets_etag() {
+        let mut headers = HeaderMap::new();
+        let (status, body) =
+            Conditions::default().apply(StatusCode::OK, &mut headers, b"hi".to_vec());
+        assert_eq!((status, body), (StatusCode::OK, b"hi".to_vec()));
+        let tag = headers[ETAG].to_str().unwrap().to_string();
+        assert_eq!(tag.len(), 34);
+
+        // The same body gets the same tag, and a matching request gets a 304.
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "text/plain".parse().unwrap());
+        let cond = conditions(&[("if-none-match", &format!("\"x\", W/{}", tag))]);
+        let (status, body) = cond.apply(StatusCode::OK, &mut headers, b"hi".to_vec());
+        assert_eq!((status, body), (StatusCode::NOT_MODIFIED, vec![]));
+        assert_eq!(headers[ETAG], tag.as_str());
+        assert!(headers.get(CONTENT_TYPE).is_none());
+
+        let (status, _) = cond.apply(StatusCode::OK, &mut HeaderMap::new(), b"bye".to_vec());
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[test]
+    fn apply_uses_function_validators() {
+        let function_headers = || {
+            let mut headers = HeaderMap::new();
+            headers.insert(ETAG, "\"v1\"".parse().unwrap());
+            headers.insert(
+                LAST_MODIFIED,
+                "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap(),
+            );
+            headers
+        };
+        let apply = |cond: Conditions| {
+            cond.apply(StatusCode::OK, &mut function_headers(), b"hi".to_vec())
+                .0
+        };
+
+        assert_eq!(
+            apply(conditions(&[("if-none-match", "\"v1\"")])),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            apply(conditions(&[("if-none-match", "*")])),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            apply(conditions(&[(
+                "if-modified-since",
+                "Sun, 06 Nov 1994 08:49:37 GMT"
+            )])),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            apply(conditions(&[(
+                "if-modified-since",
+                "Sat, 05 Nov 1994 08:49:37 GMT"
+            )])),
+            StatusCode::OK
+        );
+        // If-None-Match takes precedence.
+        assert_eq!(
+            apply(conditions(&[
+                ("if-none-match", "\"v0\""),
+                ("if-modified-since", "Sun, 06 Nov 1994 08:49:37 GMT")
+            ])),
+            StatusCode::OK
+        );
+
+        // Only GET and HEAD are conditional.
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, "*".parse().unwrap());
+        let cond = Conditions::from_request(&Method::POST, &headers);
+        assert_eq!(apply(cond), StatusCode::OK);
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env::current_dir;
+use std::net::IpAddr;
 use tableland_client::ChainID;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -8,6 +10,34 @@ pub struct Config {
     pub chain: Chain,
     pub cache: CacheConfig,
     pub ipfs: IpfsConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub db: DbConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub api_keys: ApiKeysConfig,
+    #[serde(default)]
+    pub signature: SignatureConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub links: LinksConfig,
+    #[serde(default)]
+    pub png: PngConfig,
+    #[serde(default)]
+    pub response_cache: ResponseCacheConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub poller: PollerConfig,
+    #[serde(default)]
+    pub moderation: ModerationConfig,
+    #[serde(default)]
+    pub env: EnvConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -26,10 +56,33 @@ impl Default for Config {
             chain: Chain { id: ChainID::Local },
             cache: CacheConfig {
                 directory: dir.to_str().unwrap().to_string(),
+                pinned: vec![],
+                preload_workers: default_preload_workers(),
+                max_memory: default_max_memory(),
+                max_disk: 0,
             },
             ipfs: IpfsConfig {
                 gateway: "http://localhost:8081/ipfs".to_string(),
+                strategy: FetchStrategy::default(),
+                max_size: default_max_module_size(),
+                negative_ttl: default_negative_ttl(),
+                sources: vec![],
+                allow_unverified: false,
             },
+            limits: LimitsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            db: DbConfig::default(),
+            admin: AdminConfig::default(),
+            api_keys: ApiKeysConfig::default(),
+            signature: SignatureConfig::default(),
+            health: HealthConfig::default(),
+            links: LinksConfig::default(),
+            png: PngConfig::default(),
+            response_cache: ResponseCacheConfig::default(),
+            events: EventsConfig::default(),
+            poller: PollerConfig::default(),
+            moderation: ModerationConfig::default(),
+            env: EnvConfig::default(),
         }
     }
 }
@@ -43,9 +96,290 @@ pub struct ServerConfig {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CacheConfig {
     pub directory: String,
+    #[serde(default)]
+    pub pinned: Vec<String>,
+    #[serde(default = "default_preload_workers")]
+    pub preload_workers: usize,
+    #[serde(default = "default_max_memory")]
+    pub max_memory: u64,
+    #[serde(default)]
+    pub max_disk: u64,
+}
+
+fn default_preload_workers() -> usize {
+    4
+}
+
+fn default_max_memory() -> u64 {
+    1024 * 1024 * 1024
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct IpfsConfig {
     pub gateway: String,
+    #[serde(default)]
+    pub strategy: FetchStrategy,
+    #[serde(default = "default_max_module_size")]
+    pub max_size: u64,
+    #[serde(default = "default_negative_ttl")]
+    pub negative_ttl: u64,
+    #[serde(default)]
+    pub sources: Vec<SourceConfig>,
+    /// Accept modules whose CIDs can't be checked against their bytes.
+    #[serde(default)]
+    pub allow_unverified: bool,
+}
+
+fn default_max_module_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_negative_ttl() -> u64 {
+    60
+}
+
+fn default_source_timeout() -> u64 {
+    5
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchStrategy {
+    #[default]
+    Ordered,
+    Race,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SourceConfig {
+    Gateway {
+        url: String,
+        #[serde(default = "default_source_timeout")]
+        timeout: u64,
+    },
+    IpfsApi {
+        url: String,
+        #[serde(default = "default_source_timeout")]
+        timeout: u64,
+    },
+    Directory {
+        path: String,
+    },
+    Url {
+        template: String,
+        #[serde(default = "default_source_timeout")]
+        timeout: u64,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    pub max_concurrent: usize,
+    pub max_concurrent_per_function: usize,
+    pub max_queue: usize,
+    pub retry_after: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_concurrent: 64,
+            max_concurrent_per_function: 16,
+            max_queue: 256,
+            retry_after: 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    Ip,
+    Cid,
+    ApiKey,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub key: RateLimitKey,
+    pub burst: u32,
+    pub per_second: f64,
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    #[serde(default)]
+    pub functions: HashMap<String, RateLimit>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: false,
+            key: RateLimitKey::Ip,
+            burst: 50,
+            per_second: 10.0,
+            trusted_proxies: vec![],
+            functions: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DbConfig {
+    pub path: String,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        let dir = current_dir().expect("unable to get current directory");
+        DbConfig {
+            path: dir.join("worker.db").to_str().unwrap().to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AdminConfig {
+    pub token: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ApiKeysConfig {
+    pub required: bool,
+    #[serde(default)]
+    pub keys: Vec<ApiKey>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    pub gas_quota: u64,
+    pub period: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignatureConfig {
+    pub max_age: u64,
+}
+
+impl Default for SignatureConfig {
+    fn default() -> Self {
+        SignatureConfig { max_age: 300 }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HealthConfig {
+    pub probe_ttl: u64,
+    pub probe_timeout: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            probe_ttl: 10,
+            probe_timeout: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LinksConfig {
+    pub public_url: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PngConfig {
+    pub width: u32,
+    pub max_height: u32,
+    pub gas_per_pixel: u64,
+}
+
+impl Default for PngConfig {
+    fn default() -> Self {
+        PngConfig {
+            width: 1024,
+            max_height: 4096,
+            gas_per_pixel: 10_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResponseCacheConfig {
+    pub enabled: bool,
+    pub ttl: u64,
+    pub max_entries: usize,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        ResponseCacheConfig {
+            enabled: false,
+            ttl: 0,
+            max_entries: 10_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EventsConfig {
+    pub secret: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PollerConfig {
+    pub rpc_url: String,
+    pub contract: String,
+    pub start_block: u64,
+    pub confirmations: u64,
+    pub max_block_range: u64,
+    pub interval: u64,
+    pub timeout: u64,
+}
+
+impl Default for PollerConfig {
+    fn default() -> Self {
+        PollerConfig {
+            rpc_url: String::new(),
+            contract: String::new(),
+            start_block: 0,
+            confirmations: 12,
+            max_block_range: 1000,
+            interval: 15,
+            timeout: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModerationConfig {
+    pub path: String,
+    pub reload_interval: u64,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        ModerationConfig {
+            path: String::new(),
+            reload_interval: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EnvConfig {
+    /// Hex-encoded 32-byte key that encrypts secrets.
+    pub key: String,
+    /// Variables by function CID.
+    #[serde(default)]
+    pub functions: HashMap<String, HashMap<String, String>>,
 }
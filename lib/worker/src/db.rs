@@ -0,0 +1,144 @@
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex};
+
+use crate::errors::StoreError;
+
+/// Schema migrations, applied in order. The index of the last applied
+/// migration is tracked with `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE usage (
+        id INTEGER PRIMARY KEY,
+        ts INTEGER NOT NULL,
+        api_key TEXT,
+        cid TEXT NOT NULL,
+        gas_limit INTEGER NOT NULL,
+        gas_external INTEGER NOT NULL,
+        gas_internal INTEGER NOT NULL
+    );
+    CREATE INDEX usage_api_key_ts ON usage (api_key, ts);
+    CREATE INDEX usage_cid_ts ON usage (cid, ts);",
+    "CREATE TABLE aliases (
+        name TEXT NOT NULL,
+        version INTEGER NOT NULL,
+        cid TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (name, version)
+    );",
+    "ALTER TABLE aliases ADD COLUMN splits TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE usage ADD COLUMN error INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE events (
+        chain_id INTEGER NOT NULL,
+        tx_hash TEXT NOT NULL,
+        log_index INTEGER NOT NULL,
+        block_number INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        owner TEXT NOT NULL,
+        cid TEXT NOT NULL,
+        PRIMARY KEY (chain_id, tx_hash, log_index)
+    );
+    CREATE TABLE functions (
+        cid TEXT PRIMARY KEY,
+        owner TEXT NOT NULL,
+        chain_id INTEGER NOT NULL,
+        block_number INTEGER NOT NULL,
+        tx_hash TEXT NOT NULL
+    );
+    CREATE TABLE checkpoints (
+        chain_id INTEGER PRIMARY KEY,
+        block_number INTEGER NOT NULL
+    );",
+    "CREATE TABLE scanned_blocks (
+        chain_id INTEGER NOT NULL,
+        block_number INTEGER NOT NULL,
+        hash TEXT NOT NULL,
+        PRIMARY KEY (chain_id, block_number)
+    );",
+    "CREATE TABLE functions_new (
+        cid TEXT PRIMARY KEY,
+        owner TEXT NOT NULL,
+        chain_id INTEGER,
+        block_number INTEGER,
+        tx_hash TEXT
+    );
+    INSERT INTO functions_new SELECT cid, owner, chain_id, block_number, tx_hash FROM functions;
+    DROP TABLE functions;
+    ALTER TABLE functions_new RENAME TO functions;
+    CREATE TABLE nonces (
+        address TEXT PRIMARY KEY,
+        nonce INTEGER NOT NULL
+    );",
+    "CREATE TABLE env (
+        cid TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value BLOB NOT NULL,
+        secret INTEGER NOT NULL,
+        PRIMARY KEY (cid, key)
+    );",
+    "ALTER TABLE scanned_blocks ADD COLUMN timestamp INTEGER NOT NULL DEFAULT 0;",
+];
+
+/// Local SQLite database shared by worker components.
+#[derive(Clone)]
+pub struct Db {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Db {
+    pub fn open(path: &str) -> Result<Self, StoreError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self, StoreError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Self, StoreError> {
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        let version: usize = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+        for (i, m) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(m)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
+        Ok(Db {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs `f` with the connection on the blocking thread pool.
+    pub async fn call<T, F>(&self, f: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StoreError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut conn.lock().unwrap())).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn open_works() {
+        let dir = std::env::temp_dir().join(format!("tbl-db-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("worker.db");
+        let path = path.to_str().unwrap();
+
+        let db = Db::open(path).unwrap();
+        let version: usize = db
+            .call(|c| Ok(c.pragma_query_value(None, "user_version", |r| r.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        drop(db);
+
+        // Reopening doesn't re-apply migrations.
+        Db::open(path).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::config::EnvConfig;
+use crate::db::Db;
+use crate::errors::StoreError;
+
+/// Length of the random nonce stored in front of each encrypted secret.
+const NONCE_LENGTH: usize = 12;
+/// Max length of a variable name, matching what the VM accepts.
+const MAX_KEY_LENGTH: usize = 256;
+/// Max length of a variable value.
+pub const MAX_VALUE_LENGTH: usize = 64 * 1024;
+
+/// A function's variables by name.
+pub type Vars = Arc<HashMap<String, String>>;
+
+/// A variable to set for a function.
+#[derive(Debug, Deserialize)]
+pub struct SetVar {
+    pub value: String,
+    /// Secrets are encrypted at rest and their values are never returned.
+    #[serde(default)]
+    pub secret: bool,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VarSource {
+    Config,
+    Api,
+}
+
+/// A function's variable as listed to operators.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct VarInfo {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    pub secret: bool,
+    pub source: VarSource,
+}
+
+/// Per-function variables and secrets. Variables come from `env.functions`
+/// in config, or are set through the admin API, which takes precedence.
+/// Secrets are encrypted with `env.key`.
+#[derive(Clone)]
+pub struct Env {
+    functions: Arc<HashMap<String, HashMap<String, String>>>,
+    cipher: Option<Arc<ChaCha20Poly1305>>,
+    db: Db,
+    /// Decrypted variables by CID, loaded on first use.
+    cache: Arc<RwLock<HashMap<String, Vars>>>,
+    /// Bumped on every change, so loads that raced with one aren't cached.
+    generation: Arc<AtomicU64>,
+}
+
+impl Env {
+    pub fn new(config: EnvConfig, db: Db) -> Result<Self, StoreError> {
+        let cipher = if config.key.is_empty() {
+            None
+        } else {
+            let key = hex::decode(config.key.trim_start_matches("0x"))
+                .ok()
+                .filter(|k| k.len() == 32)
+                .ok_or_else(|| StoreError::secret_err("env.key must be 32 hex-encoded bytes"))?;
+            Some(Arc::new(ChaCha20Poly1305::new(Key::from_slice(&key))))
+        };
+        Ok(Env {
+            functions: Arc::new(config.functions),
+            cipher,
+            db,
+            cache: Arc::new(RwLock::new(HashMap::new())),
+            generation: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Returns a function's variables, with secrets decrypted.
+    pub async fn vars(&self, cid: &str) -> Result<Vars, StoreError> {
+        if let Some(vars) = self.cache.read().unwrap().get(cid) {
+            return Ok(vars.clone());
+        }
+        let generation = self.generation.load(Ordering::SeqCst);
+        let vars = self.load(cid).await?;
+        self.remember(cid, generation, vars.clone());
+        Ok(vars)
+    }
+
+    async fn load(&self, cid: &str) -> Result<Vars, StoreError> {
+        let mut vars = self.functions.get(cid).cloned().unwrap_or_default();
+        for (key, value, secret) in self.rows(cid.to_string()).await? {
+            let value = if secret {
+                self.decrypt(cid, key.as_str(), &value)?
+            } else {
+                String::from_utf8(value).map_err(|e| StoreError::secret_err(e.to_string()))?
+            };
+            vars.insert(key, value);
+        }
+        Ok(Arc::new(vars))
+    }
+
+    /// Caches variables loaded as of `generation`, unless they've changed
+    /// since.
+    fn remember(&self, cid: &str, generation: u64, vars: Vars) {
+        let mut cache = self.cache.write().unwrap();
+        if self.generation.load(Ordering::SeqCst) == generation {
+            cache.insert(cid.to_string(), vars);
+        }
+    }
+
+    /// Drops a function's cached variables after a change.
+    fn invalidate(&self, cid: &str) {
+        let mut cache = self.cache.write().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        cache.remove(cid);
+    }
+
+    /// Lists a function's variables. Secret values are left out.
+    pub async fn list(&self, cid: String) -> Result<Vec<VarInfo>, StoreError> {
+        let mut vars: HashMap<String, VarInfo> = HashMap::new();
+        for (key, value) in self.functions.get(&cid).into_iter().flatten() {
+            let info = VarInfo {
+                key: key.clone(),
+                value: Some(value.clone()),
+                secret: false,
+                source: VarSource::Config,
+            };
+            vars.insert(key.clone(), info);
+        }
+        for (key, value, secret) in self.rows(cid).await? {
+            let info = VarInfo {
+                key: key.clone(),
+                value: match secret {
+                    true => None,
+                    false => Some(String::from_utf8_lossy(&value).to_string()),
+                },
+                secret,
+                source: VarSource::Api,
+            };
+            vars.insert(key, info);
+        }
+        let mut vars: Vec<VarInfo> = vars.into_values().collect();
+        vars.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(vars)
+    }
+
+    pub async fn set(&self, cid: String, key: String, var: SetVar) -> Result<(), StoreError> {
+        check_key(key.as_str())?;
+        if var.value.len() > MAX_VALUE_LENGTH {
+            return Err(StoreError::env_err(format!(
+                "value exceeds {} bytes",
+                MAX_VALUE_LENGTH
+            )));
+        }
+        let value = match var.secret {
+            true => self.encrypt(cid.as_str(), key.as_str(), var.value.as_str())?,
+            false => var.value.into_bytes(),
+        };
+        let secret = var.secret;
+        let c = cid.clone();
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO env (cid, key, value, secret) VALUES (?1, ?2, ?3, ?4)",
+                    params![c, key, value, secret],
+                )?;
+                Ok(())
+            })
+            .await?;
+        self.invalidate(cid.as_str());
+        Ok(())
+    }
+
+    /// Deletes a variable set through the API. Variables from config can't be
+    /// deleted.
+    pub async fn delete(&self, cid: String, key: String) -> Result<(), StoreError> {
+        let c = cid.clone();
+        let k = key.clone();
+        let deleted = self
+            .db
+            .call(move |conn| {
+                Ok(conn.execute("DELETE FROM env WHERE cid = ?1 AND key = ?2", params![c, k])?)
+            })
+            .await?;
+        self.invalidate(cid.as_str());
+        if deleted == 0 {
+            return Err(StoreError::NotFound(format!(
+                "{} isn't set for {}",
+                key, cid
+            )));
+        }
+        Ok(())
+    }
+
+    async fn rows(&self, cid: String) -> Result<Vec<(String, Vec<u8>, bool)>, StoreError> {
+        self.db
+            .call(move |c| {
+                let mut stmt = c.prepare("SELECT key, value, secret FROM env WHERE cid = ?1")?;
+                let rows = stmt
+                    .query_map(params![cid], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await
+    }
+
+    fn cipher(&self) -> Result<&ChaCha20Poly1305, StoreError> {
+        self.cipher
+            .as_deref()
+            .ok_or_else(|| StoreError::secret_err("env.key isn't set"))
+    }
+
+    /// Encrypts a secret, binding it to its function and name so it can't be
+    /// moved to another.
+    fn encrypt(&self, cid: &str, key: &str, value: &str) -> Result<Vec<u8>, StoreError> {
+        let nonce: [u8; NONCE_LENGTH] = rand::random();
+        let aad = format!("{}\n{}", cid, key);
+        let mut out = nonce.to_vec();
+        out.extend(
+            self.cipher()?
+                .encrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: value.as_bytes(),
+                        aad: aad.as_bytes(),
+                    },
+                )
+                .map_err(|_| StoreError::secret_err(format!("unable to encrypt {}", key)))?,
+        );
+        Ok(out)
+    }
+
+    fn decrypt(&self, cid: &str, key: &str, value: &[u8]) -> Result<String, StoreError> {
+        let err = || StoreError::secret_err(format!("unable to decrypt {} for {}", key, cid));
+        if value.len() < NONCE_LENGTH {
+            return Err(err());
+        }
+        let (nonce, ciphertext) = value.split_at(NONCE_LENGTH);
+        let aad = format!("{}\n{}", cid, key);
+        let plaintext = self
+            .cipher()?
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| err())?;
+        String::from_utf8(plaintext).map_err(|_| err())
+    }
+}
+
+/// Variable names are letters, digits, and underscores.
+fn check_key(key: &str) -> Result<(), StoreError> {
+    if key.is_empty()
+        || key.len() > MAX_KEY_LENGTH
+        || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(StoreError::env_err(format!(
+            "invalid variable name {}",
+            key
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(key: &str) -> EnvConfig {
+        EnvConfig {
+            key: key.to_string(),
+            functions: HashMap::from([(
+                "a".to_string(),
+                HashMap::from([
+                    ("TABLE".to_string(), "players_31337_7".to_string()),
+                    ("BASE".to_string(), "/api".to_string()),
+                ]),
+            )]),
+        }
+    }
+
+    #[tokio::test]
+    async fn env_works() {
+        let db = Db::open_in_memory().unwrap();
+        let env = Env::new(config(&"11".repeat(32)), db.clone()).unwrap();
+        assert_eq!(env.vars("a").await.unwrap()["TABLE"], "players_31337_7");
+
+        let set = |value: &str, secret| SetVar {
+            value: value.to_string(),
+            secret,
+        };
+        env.set("a".into(), "BASE".into(), set("/v2", false))
+            .await
+            .unwrap();
+        env.set("a".into(), "TOKEN".into(), set("hunter2", true))
+            .await
+            .unwrap();
+        assert!(env
+            .set("a".into(), "BAD KEY".into(), set("", false))
+            .await
+            .is_err());
+        let vars = env.vars("a").await.unwrap();
+        assert_eq!(vars["BASE"], "/v2");
+        assert_eq!(vars["TOKEN"], "hunter2");
+        assert!(env.vars("b").await.unwrap().is_empty());
+
+        // Secrets are encrypted at rest and never listed.
+        let stored: Vec<u8> = db
+            .call(|c| {
+                Ok(
+                    c.query_row("SELECT value FROM env WHERE key = 'TOKEN'", [], |r| {
+                        r.get(0)
+                    })?,
+                )
+            })
+            .await
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&stored).contains("hunter2"));
+        let list = env.list("a".into()).await.unwrap();
+        let keys: Vec<&str> = list.iter().map(|v| v.key.as_str()).collect();
+        assert_eq!(keys, vec!["BASE", "TABLE", "TOKEN"]);
+        assert_eq!(list[0].source, VarSource::Api);
+        assert_eq!(list[2].value, None);
+
+        // Secrets can't be read with another key.
+        let other = Env::new(config(&"22".repeat(32)), db.clone()).unwrap();
+        assert!(other.vars("a").await.is_err());
+        let none = Env::new(config(""), db).unwrap();
+        assert!(none
+            .set("a".into(), "TOKEN".into(), set("x", true))
+            .await
+            .is_err());
+
+        env.delete("a".into(), "BASE".into()).await.unwrap();
+        assert!(env.delete("a".into(), "TABLE".into()).await.is_err());
+        assert_eq!(env.vars("a").await.unwrap()["BASE"], "/api");
+    }
+
+    #[tokio::test]
+    async fn vars_skips_caching_stale_loads() {
+        let env = Env::new(config(&"11".repeat(32)), Db::open_in_memory().unwrap()).unwrap();
+        let secret = |value: &str| SetVar {
+            value: value.to_string(),
+            secret: true,
+        };
+        env.set("a".into(), "TOKEN".into(), secret("hunter2"))
+            .await
+            .unwrap();
+
+        // Loads that read the rows before a change aren't cached after it.
+        let generation = env.generation.load(Ordering::SeqCst);
+        let stale = env.load("a").await.unwrap();
+        env.set("a".into(), "TOKEN".into(), secret("hunter3"))
+            .await
+            .unwrap();
+        env.remember("a", generation, stale);
+        assert_eq!(env.vars("a").await.unwrap()["TOKEN"], "hunter3");
+
+        let generation = env.generation.load(Ordering::SeqCst);
+        let stale = env.load("a").await.unwrap();
+        env.delete("a".into(), "TOKEN".into()).await.unwrap();
+        env.remember("a", generation, stale);
+        assert!(!env.vars("a").await.unwrap().contains_key("TOKEN"));
+    }
+}
@@ -1,3 +1,4 @@
+use tableland_vm::ValidationReport;
 use thiserror::Error;
 
 #[derive(Error, Clone, Debug)]
@@ -14,6 +15,36 @@ pub enum StoreError {
     Cache(String),
     #[error("Tokie task join error: {0}")]
     TaskJoin(String),
+    #[error("Too many requests, retry after {0}s")]
+    Overloaded(u64),
+    #[error("Rate limit exceeded, retry after {reset}s")]
+    RateLimited { limit: u32, reset: u64 },
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Gas quota exceeded for API key {0}")]
+    QuotaExceeded(String),
+    #[error("Database error: {0}")]
+    Db(String),
+    #[error("CAR error: {0}")]
+    Car(String),
+    #[error("Alias error: {0}")]
+    Alias(String),
+    #[error("Event error: {0}")]
+    Event(String),
+    #[error("Moderation error: {0}")]
+    Moderation(String),
+    #[error("Env error: {0}")]
+    Env(String),
+    #[error("Secret error: {0}")]
+    Secret(String),
+    #[error("Forbidden: {cid} is not allowed on this worker ({reason})")]
+    Forbidden { reason: &'static str, cid: String },
+    #[error("{0}")]
+    NotFound(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Invalid module: {}", .0.errors.join("; "))]
+    Invalid(Box<ValidationReport>),
 }
 
 impl StoreError {
@@ -24,6 +55,34 @@ impl StoreError {
     pub(crate) fn cache_err(msg: impl Into<String>) -> Self {
         StoreError::Cache(msg.into())
     }
+
+    pub(crate) fn unauthorized_err(msg: impl Into<String>) -> Self {
+        StoreError::Unauthorized(msg.into())
+    }
+
+    pub(crate) fn alias_err(msg: impl Into<String>) -> Self {
+        StoreError::Alias(msg.into())
+    }
+
+    pub(crate) fn car_err(msg: impl Into<String>) -> Self {
+        StoreError::Car(msg.into())
+    }
+
+    pub(crate) fn event_err(msg: impl Into<String>) -> Self {
+        StoreError::Event(msg.into())
+    }
+
+    pub(crate) fn moderation_err(msg: impl Into<String>) -> Self {
+        StoreError::Moderation(msg.into())
+    }
+
+    pub(crate) fn env_err(msg: impl Into<String>) -> Self {
+        StoreError::Env(msg.into())
+    }
+
+    pub(crate) fn secret_err(msg: impl Into<String>) -> Self {
+        StoreError::Secret(msg.into())
+    }
 }
 
 impl From<std::io::Error> for StoreError {
@@ -49,3 +108,9 @@ impl From<tokio::task::JoinError> for StoreError {
         StoreError::TaskJoin(e.to_string())
     }
 }
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Db(e.to_string())
+    }
+}
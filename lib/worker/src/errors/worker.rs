@@ -5,12 +5,23 @@ use tableland_vm::GasReport;
 pub struct WorkerError {
     pub(crate) error: StoreError,
     pub(crate) report: Option<GasReport>,
+    /// The function that handled the request, if one was picked.
+    pub(crate) cid: Option<String>,
 }
 
 impl warp::reject::Reject for WorkerError {}
 
 impl WorkerError {
     pub(crate) fn new(error: StoreError, report: Option<GasReport>) -> Self {
-        WorkerError { error, report }
+        WorkerError {
+            error,
+            report,
+            cid: None,
+        }
+    }
+
+    pub(crate) fn with_cid(mut self, cid: String) -> Self {
+        self.cid = Some(cid);
+        self
     }
 }
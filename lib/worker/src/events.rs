@@ -0,0 +1,638 @@
+use hmac::{Hmac, Mac};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::db::Db;
+use crate::errors::StoreError;
+use crate::store::Store;
+
+/// Header carrying the hex-encoded HMAC-SHA256 of an event batch, keyed with
+/// `events.secret`.
+pub const EVENTS_SIGNATURE_HEADER: &str = "x-tbl-hmac";
+
+/// Number of times an event may fail for a reason that may go away, e.g., its
+/// module can't be fetched, before it's given up on.
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// Counts failed attempts at something that's retried, by key.
+#[derive(Clone, Default)]
+pub struct Attempts {
+    failed: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl Attempts {
+    /// Counts a failed attempt, returning whether there have been
+    /// [`MAX_ATTEMPTS`], in which case the count starts over.
+    pub fn fail(&self, key: &str) -> bool {
+        let mut failed = self.failed.lock().unwrap();
+        let count = failed.entry(key.to_string()).or_insert(0);
+        *count += 1;
+        if *count < MAX_ATTEMPTS {
+            return false;
+        }
+        failed.remove(key);
+        true
+    }
+
+    pub fn succeed(&self, key: &str) {
+        self.failed.lock().unwrap().remove(key);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A function was registered, so its module should be hosted.
+    Register,
+    /// A function's record changed, e.g., its owner. The module is added
+    /// again if it's missing.
+    Update,
+    /// A function was removed, so its module should be dropped.
+    Remove,
+}
+
+impl EventKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Register => "register",
+            EventKind::Update => "update",
+            EventKind::Remove => "remove",
+        }
+    }
+}
+
+/// A function registry event emitted by `evm-tableland`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub chain_id: u64,
+    pub block_number: u64,
+    pub tx_hash: String,
+    /// Position of the event's log in its block.
+    #[serde(default)]
+    pub log_index: u64,
+    pub owner: String,
+    pub cid: String,
+}
+
+/// Outcome of applying a batch of events.
+#[derive(Debug, Default, Serialize, PartialEq, Eq)]
+pub struct Applied {
+    pub applied: usize,
+    /// Events that were already applied, or are older than the latest event
+    /// applied for their function.
+    pub skipped: usize,
+    /// Events for modules that can never be added, e.g., invalid ones, or
+    /// that kept failing for [`MAX_ATTEMPTS`] attempts.
+    pub failed: usize,
+    pub last_block: Option<u64>,
+}
+
+/// Applies function registry events to a [`Store`]. Events are recorded once
+/// applied, so delivering them again has no effect. Events that arrive after
+/// a later event for the same function are recorded but not applied.
+#[derive(Clone)]
+pub struct Events {
+    db: Db,
+    store: Store,
+    chain_id: u64,
+    secret: Arc<String>,
+    /// Failed attempts at applying events, by [`attempt_key`].
+    attempts: Attempts,
+}
+
+impl Events {
+    pub fn new(db: Db, store: Store, chain_id: u64, secret: String) -> Self {
+        Events {
+            db,
+            store,
+            chain_id,
+            secret: Arc::new(secret),
+            attempts: Attempts::default(),
+        }
+    }
+
+    /// Checks a batch's signature. Ingestion is disabled when no secret is
+    /// configured.
+    pub fn verify(&self, signature: Option<&str>, body: &[u8]) -> Result<(), StoreError> {
+        let invalid = || StoreError::unauthorized_err("invalid event signature");
+        if self.secret.is_empty() {
+            return Err(invalid());
+        }
+        let signature = signature
+            .and_then(|s| hex::decode(s.trim_start_matches("0x")).ok())
+            .ok_or_else(invalid)?;
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).map_err(|_| invalid())?;
+        mac.update(body);
+        mac.verify_slice(&signature).map_err(|_| invalid())
+    }
+
+    /// Returns the last block events were applied from.
+    pub async fn last_block(&self) -> Result<Option<u64>, StoreError> {
+        let chain_id = self.chain_id;
+        self.db
+            .call(move |c| {
+                Ok(c.query_row(
+                    "SELECT block_number FROM checkpoints WHERE chain_id = ?1",
+                    params![chain_id],
+                    |r| r.get(0),
+                )
+                .optional()?)
+            })
+            .await
+    }
+
+    /// Applies events in block order, stopping at the first one that fails
+    /// for a reason that may go away, e.g., the module can't be fetched yet.
+    /// Events before it stay applied, so the batch can be retried. After
+    /// [`MAX_ATTEMPTS`] failures, the event is recorded as failed instead, so
+    /// one bad event can't hold up the rest forever.
+    pub async fn apply(&self, mut events: Vec<Event>) -> Result<Applied, StoreError> {
+        if let Some(e) = events.iter().find(|e| e.chain_id != self.chain_id) {
+            return Err(StoreError::event_err(format!(
+                "event for chain {} in {}, expected chain {}",
+                e.chain_id, e.tx_hash, self.chain_id
+            )));
+        }
+        events.sort_by_key(|e| (e.block_number, e.log_index));
+
+        let mut out = Applied::default();
+        for event in events {
+            if self.seen(&event).await? {
+                out.skipped += 1;
+                continue;
+            }
+            // Recording stale events keeps them around in case a rollback
+            // makes them the latest again.
+            if self.superseded(&event).await? {
+                out.skipped += 1;
+                self.record(event, false).await?;
+                continue;
+            }
+            match self.run(&event).await {
+                Ok(()) => {
+                    self.attempts.succeed(&attempt_key(&event));
+                    out.applied += 1;
+                }
+                Err(e @ StoreError::Invalid(_))
+                | Err(e @ StoreError::PayloadTooLarge)
+                | Err(e @ StoreError::Forbidden { .. }) => {
+                    eprintln!("error applying {:?} for {}: {}", event.kind, event.cid, e);
+                    out.failed += 1;
+                }
+                Err(e) => {
+                    if !self.attempts.fail(&attempt_key(&event)) {
+                        return Err(e);
+                    }
+                    eprintln!(
+                        "giving up on {:?} for {} after {} attempts: {}",
+                        event.kind, event.cid, MAX_ATTEMPTS, e
+                    );
+                    out.failed += 1;
+                }
+            }
+            self.record(event, true).await?;
+        }
+        out.last_block = self.last_block().await?;
+        Ok(out)
+    }
+
+    /// Undoes events after `block_number`, e.g., because of a reorg. Each
+    /// affected function goes back to its state as of its last remaining
+    /// event: its module is added if it's registered, and dropped if not.
+    pub async fn rollback(&self, block_number: u64) -> Result<(), StoreError> {
+        let chain_id = self.chain_id;
+        // Each function's owner, if it's still registered.
+        let registered: Vec<(String, Option<String>)> = self
+            .db
+            .call(move |c| {
+                let tx = c.transaction()?;
+                let cids = tx
+                    .prepare(
+                        "SELECT DISTINCT cid FROM events
+                        WHERE chain_id = ?1 AND block_number > ?2",
+                    )?
+                    .query_map(params![chain_id, block_number], |r| r.get(0))?
+                    .collect::<Result<Vec<String>, _>>()?;
+                tx.execute(
+                    "DELETE FROM events WHERE chain_id = ?1 AND block_number > ?2",
+                    params![chain_id, block_number],
+                )?;
+
+                let mut out = vec![];
+                for cid in cids {
+                    let last: Option<(String, String, u64, String)> = tx
+                        .query_row(
+                            "SELECT kind, owner, block_number, tx_hash FROM events
+                            WHERE cid = ?1
+                            ORDER BY block_number DESC, log_index DESC LIMIT 1",
+                            params![cid],
+                            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+                        )
+                        .optional()?;
+                    tx.execute("DELETE FROM functions WHERE cid = ?1", params![cid])?;
+                    match last {
+                        Some((kind, owner, block, tx_hash)) if kind != "remove" => {
+                            tx.execute(
+                                "INSERT INTO functions
+                                    (cid, owner, chain_id, block_number, tx_hash)
+                                VALUES (?1, ?2, ?3, ?4, ?5)",
+                                params![cid, owner, chain_id, block, tx_hash],
+                            )?;
+                            out.push((cid, Some(owner)));
+                        }
+                        _ => out.push((cid, None)),
+                    }
+                }
+                tx.execute(
+                    "UPDATE checkpoints SET block_number = ?2
+                    WHERE chain_id = ?1 AND block_number > ?2",
+                    params![chain_id, block_number],
+                )?;
+                tx.commit()?;
+                Ok(out)
+            })
+            .await?;
+
+        for (cid, owner) in registered {
+            self.store.forget_owner(cid.as_str());
+            let res = match owner {
+                Some(owner) => self.host(cid.clone(), owner).await,
+                None => self.store.remove(cid.clone()).await,
+            };
+            if let Err(e) = res {
+                eprintln!("error rolling back {}: {}", cid, e);
+            }
+        }
+        println!("rolled events back to block {}", block_number);
+        Ok(())
+    }
+
+    async fn run(&self, event: &Event) -> Result<(), StoreError> {
+        match event.kind {
+            EventKind::Register | EventKind::Update => {
+                self.host(event.cid.clone(), event.owner.clone()).await?;
+                println!("registered {} for {}", event.cid, event.owner);
+            }
+            EventKind::Remove => {
+                self.store.remove(event.cid.clone()).await?;
+                println!("removed {}", event.cid);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a module for `owner` unless it's already stored.
+    async fn host(&self, cid: String, owner: String) -> Result<(), StoreError> {
+        if self.store.module(cid.clone()).await.is_err() {
+            self.store.add(cid, Some(owner)).await?;
+        }
+        Ok(())
+    }
+
+    async fn seen(&self, event: &Event) -> Result<bool, StoreError> {
+        let (chain_id, tx_hash, log_index) =
+            (event.chain_id, event.tx_hash.clone(), event.log_index);
+        self.db
+            .call(move |c| {
+                Ok(c.query_row(
+                    "SELECT 1 FROM events WHERE chain_id = ?1 AND tx_hash = ?2 AND log_index = ?3",
+                    params![chain_id, tx_hash, log_index],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some())
+            })
+            .await
+    }
+
+    /// Returns whether a later event for the same function has been recorded.
+    async fn superseded(&self, event: &Event) -> Result<bool, StoreError> {
+        let (chain_id, cid, block_number, log_index) = (
+            event.chain_id,
+            event.cid.clone(),
+            event.block_number,
+            event.log_index,
+        );
+        self.db
+            .call(move |c| {
+                Ok(c.query_row(
+                    "SELECT 1 FROM events WHERE chain_id = ?1 AND cid = ?2
+                    AND (block_number > ?3 OR (block_number = ?3 AND log_index > ?4))
+                    LIMIT 1",
+                    params![chain_id, cid, block_number, log_index],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some())
+            })
+            .await
+    }
+
+    /// Records an event and the chain's checkpoint in one transaction, along
+    /// with the function it describes if the event was applied.
+    async fn record(&self, event: Event, applied: bool) -> Result<(), StoreError> {
+        let cid = event.cid.clone();
+        self.db
+            .call(move |c| {
+                let tx = c.transaction()?;
+                tx.execute(
+                    "INSERT INTO events
+                        (chain_id, tx_hash, log_index, block_number, kind, owner, cid)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        event.chain_id,
+                        event.tx_hash,
+                        event.log_index,
+                        event.block_number,
+                        event.kind.as_str(),
+                        event.owner,
+                        event.cid
+                    ],
+                )?;
+                match event.kind {
+                    _ if !applied => 0,
+                    EventKind::Register | EventKind::Update => tx.execute(
+                        "INSERT INTO functions (cid, owner, chain_id, block_number, tx_hash)
+                        VALUES (?1, ?2, ?3, ?4, ?5)
+                        ON CONFLICT (cid) DO UPDATE SET owner = ?2, chain_id = ?3,
+                            block_number = ?4, tx_hash = ?5",
+                        params![
+                            event.cid,
+                            event.owner,
+                            event.chain_id,
+                            event.block_number,
+                            event.tx_hash
+                        ],
+                    )?,
+                    EventKind::Remove => {
+                        tx.execute("DELETE FROM functions WHERE cid = ?1", params![event.cid])?
+                    }
+                };
+                tx.execute(
+                    "INSERT INTO checkpoints (chain_id, block_number) VALUES (?1, ?2)
+                    ON CONFLICT (chain_id) DO UPDATE
+                    SET block_number = MAX(block_number, ?2)",
+                    params![event.chain_id, event.block_number],
+                )?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await?;
+        if applied {
+            self.store.forget_owner(cid.as_str());
+        }
+        Ok(())
+    }
+}
+
+fn attempt_key(event: &Event) -> String {
+    format!("{}:{}", event.tx_hash, event.log_index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ModerationConfig, ResponseCacheConfig, SourceConfig};
+    use crate::moderation::Moderation;
+    use crate::owners::Owners;
+    use crate::response_cache::{CachedResponse, ResponseCache};
+    use std::collections::HashSet;
+    use warp::http::{HeaderMap, StatusCode};
+
+    const EXAMPLE_JSON_WASM: &[u8] = include_bytes!("../testdata/json.wasm");
+
+    fn event(kind: EventKind, block_number: u64, tx_hash: &str, cid: &str) -> Event {
+        Event {
+            kind,
+            chain_id: 31337,
+            block_number,
+            tx_hash: tx_hash.to_string(),
+            log_index: 0,
+            owner: "0xabc".to_string(),
+            cid: cid.to_string(),
+        }
+    }
+
+    async fn owner(db: &Db, cid: &str) -> Option<String> {
+        let cid = cid.to_string();
+        db.call(move |c| {
+            Ok(c.query_row(
+                "SELECT owner FROM functions WHERE cid = ?1",
+                params![cid],
+                |r| r.get(0),
+            )
+            .optional()?)
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn verify_works() {
+        let events = |secret: &str| {
+            let store = Store::new(Config::default());
+            Events::new(Db::open_in_memory().unwrap(), store, 31337, secret.into())
+        };
+        let sign = |secret: &[u8], body: &[u8]| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+            mac.update(body);
+            hex::encode(mac.finalize().into_bytes())
+        };
+
+        let e = events("secret");
+        let sig = sign(b"secret", b"[]");
+        e.verify(Some(&sig), b"[]").unwrap();
+        e.verify(Some(&format!("0x{}", sig)), b"[]").unwrap();
+        assert!(e.verify(Some(&sig), b"[{}]").is_err());
+        assert!(e.verify(Some(&sign(b"other", b"[]")), b"[]").is_err());
+        assert!(e.verify(None, b"[]").is_err());
+        assert!(events("").verify(Some(&sign(b"", b"[]")), b"[]").is_err());
+    }
+
+    #[tokio::test]
+    async fn apply_works() {
+        let dir = std::env::temp_dir().join(format!("tbl-events-{}", std::process::id()));
+        let (cache, source) = (dir.join("cache"), dir.join("source"));
+        std::fs::create_dir_all(&cache).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.wasm"), EXAMPLE_JSON_WASM).unwrap();
+        std::fs::write(source.join("bad.wasm"), b"not wasm").unwrap();
+
+        let mut config = Config::default();
+        config.cache.directory = cache.to_str().unwrap().to_string();
+        config.ipfs.sources = vec![SourceConfig::Directory {
+            path: source.to_str().unwrap().to_string(),
+        }];
+        config.ipfs.allow_unverified = true;
+        let store = Store::new(config);
+        let db = Db::open_in_memory().unwrap();
+        let events = Events::new(db.clone(), store.clone(), 31337, "secret".into());
+        assert_eq!(events.last_block().await.unwrap(), None);
+
+        // Applied in block order, so the removal comes last.
+        let batch = vec![
+            event(EventKind::Remove, 12, "0x3", "a"),
+            event(EventKind::Register, 10, "0x1", "a"),
+            event(EventKind::Register, 11, "0x2", "bad"),
+        ];
+        let applied = events.apply(batch.clone()).await.unwrap();
+        assert_eq!(
+            applied,
+            Applied {
+                applied: 2,
+                skipped: 0,
+                failed: 1,
+                last_block: Some(12),
+            }
+        );
+        assert!(store.module("a".to_string()).await.is_err());
+        assert_eq!(owner(&db, "a").await, None);
+
+        // Redelivery is a no-op.
+        let applied = events.apply(batch).await.unwrap();
+        assert_eq!((applied.applied, applied.skipped), (0, 3));
+
+        let mut update = event(EventKind::Update, 13, "0x4", "a");
+        update.owner = "0xdef".to_string();
+        events.apply(vec![update]).await.unwrap();
+        assert!(store.module("a".to_string()).await.is_ok());
+        assert_eq!(owner(&db, "a").await.as_deref(), Some("0xdef"));
+
+        // Modules that can't be fetched stop the batch without recording it,
+        // until the event has failed too many times.
+        let missing = event(EventKind::Register, 14, "0x5", "missing");
+        for _ in 1..MAX_ATTEMPTS {
+            assert!(events.apply(vec![missing.clone()]).await.is_err());
+            assert!(!events.seen(&missing).await.unwrap());
+            assert_eq!(events.last_block().await.unwrap(), Some(13));
+        }
+        let applied = events.apply(vec![missing.clone()]).await.unwrap();
+        assert_eq!((applied.applied, applied.failed), (0, 1));
+        assert!(events.seen(&missing).await.unwrap());
+        assert_eq!(events.last_block().await.unwrap(), Some(14));
+
+        let mut other = event(EventKind::Register, 15, "0x6", "a");
+        other.chain_id = 1;
+        assert!(matches!(
+            events.apply(vec![other]).await,
+            Err(StoreError::Event(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn apply_enforces_owner_deny_list() {
+        let dir =
+            std::env::temp_dir().join(format!("tbl-events-moderation-{}", std::process::id()));
+        let (cache, source) = (dir.join("cache"), dir.join("source"));
+        std::fs::create_dir_all(&cache).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.wasm"), EXAMPLE_JSON_WASM).unwrap();
+        let lists = dir.join("lists.json");
+        std::fs::write(&lists, r#"{"deny": {"owners": ["0xABC"]}}"#).unwrap();
+
+        let mut config = Config::default();
+        config.cache.directory = cache.to_str().unwrap().to_string();
+        config.ipfs.sources = vec![SourceConfig::Directory {
+            path: source.to_str().unwrap().to_string(),
+        }];
+        config.ipfs.allow_unverified = true;
+        let db = Db::open_in_memory().unwrap();
+        let moderation = Moderation::open(
+            ModerationConfig {
+                path: lists.to_str().unwrap().to_string(),
+                reload_interval: 0,
+            },
+            Owners::new(db.clone()),
+        )
+        .unwrap();
+        let store = Store::new(config).with_moderation(moderation);
+        let events = Events::new(db.clone(), store.clone(), 31337, "secret".into());
+
+        // The event's owner is checked before the module is stored.
+        let applied = events
+            .apply(vec![event(EventKind::Register, 10, "0x1", "a")])
+            .await
+            .unwrap();
+        assert_eq!((applied.applied, applied.failed), (0, 1));
+        assert!(store.module("a".to_string()).await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn apply_skips_superseded_events() {
+        let dir = std::env::temp_dir().join(format!("tbl-events-order-{}", std::process::id()));
+        let (cache, source) = (dir.join("cache"), dir.join("source"));
+        std::fs::create_dir_all(&cache).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.wasm"), EXAMPLE_JSON_WASM).unwrap();
+
+        let mut config = Config::default();
+        config.cache.directory = cache.to_str().unwrap().to_string();
+        config.ipfs.sources = vec![SourceConfig::Directory {
+            path: source.to_str().unwrap().to_string(),
+        }];
+        config.ipfs.allow_unverified = true;
+        let responses = ResponseCache::new(ResponseCacheConfig {
+            enabled: true,
+            ..Default::default()
+        });
+        let store = Store::new(config).with_response_cache(responses.clone());
+        let db = Db::open_in_memory().unwrap();
+        let events = Events::new(db.clone(), store.clone(), 31337, "secret".into());
+
+        events
+            .apply(vec![event(EventKind::Register, 10, "0x1", "a")])
+            .await
+            .unwrap();
+        let none = HeaderMap::new();
+        let res = CachedResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: b"a".to_vec(),
+        };
+        responses.insert("a GET /".into(), &none, res, HashSet::new());
+
+        // Removing a function drops its cached responses.
+        events
+            .apply(vec![event(EventKind::Remove, 12, "0x3", "a")])
+            .await
+            .unwrap();
+        assert!(responses.get("a GET /", &none).is_none());
+
+        // A registration from before the removal, delivered late, is ignored.
+        let applied = events
+            .apply(vec![event(EventKind::Register, 5, "0x0", "a")])
+            .await
+            .unwrap();
+        assert_eq!((applied.applied, applied.skipped), (0, 1));
+        assert!(store.module("a".to_string()).await.is_err());
+        assert_eq!(owner(&db, "a").await, None);
+
+        // So is an old update that would move ownership.
+        events
+            .apply(vec![event(EventKind::Register, 13, "0x4", "a")])
+            .await
+            .unwrap();
+        let mut update = event(EventKind::Update, 11, "0x2", "a");
+        update.owner = "0xdef".to_string();
+        let applied = events.apply(vec![update]).await.unwrap();
+        assert_eq!((applied.applied, applied.skipped), (0, 1));
+        assert_eq!(owner(&db, "a").await.as_deref(), Some("0xabc"));
+
+        // Rolling back to before the re-registration restores the function's
+        // state as of the latest remaining event, the removal.
+        events.rollback(12).await.unwrap();
+        assert!(store.module("a".to_string()).await.is_err());
+        assert_eq!(owner(&db, "a").await, None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+/// Drops the least recently used half of `map` once it holds `max` entries,
+/// returning the latest `used` value dropped. Running this before every insert
+/// keeps the map bounded at an amortized constant cost per insert.
+pub fn evict_oldest<V, T: Ord + Copy>(
+    map: &mut HashMap<String, V>,
+    max: usize,
+    used: impl Fn(&V) -> T,
+) -> Option<T> {
+    if map.len() < max {
+        return None;
+    }
+    let mut times: Vec<T> = map.values().map(&used).collect();
+    let keep = max / 2;
+    let (_, cutoff, _) = times.select_nth_unstable(map.len() - keep - 1);
+    let cutoff = *cutoff;
+    map.retain(|_, v| used(v) > cutoff);
+    Some(cutoff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn evict_oldest_works() {
+        let start = Instant::now();
+        let mut map: HashMap<String, Instant> = (0..10)
+            .map(|i| (i.to_string(), start + Duration::from_secs(i)))
+            .collect();
+        assert_eq!(evict_oldest(&mut map, 11, |t| *t), None);
+        assert_eq!(map.len(), 10);
+        assert_eq!(
+            evict_oldest(&mut map, 10, |t| *t),
+            Some(start + Duration::from_secs(4))
+        );
+        let mut keys: Vec<&String> = map.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["5", "6", "7", "8", "9"]);
+    }
+}
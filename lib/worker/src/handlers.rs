@@ -1,37 +1,183 @@
 use bytes::Bytes;
 use serde_bytes::ByteBuf;
-use tableland_std::Request;
+use tableland_std::{Meta, Request, Response};
 use tableland_vm::GasReport;
 use warp::{
+    http::header::{CONTENT_LENGTH, CONTENT_TYPE, VARY},
     http::Response as WarpResponse,
     http::{HeaderMap, HeaderValue, Method, StatusCode, Uri},
     path::FullPath,
     Rejection, Reply,
 };
 
+use crate::conditional::Conditions;
+use crate::config::LinksConfig;
+use crate::env::{Env, SetVar};
 use crate::errors::{StoreError, WorkerError};
+use crate::events::{Event, Events};
+use crate::ledger::{Ledger, Reservation, UsageEntry, UsageQuery};
+use crate::limits::QueueReport;
+use crate::links::rewrite_response;
+use crate::meta::REQUEST_ID_HEADER;
+use crate::owners::{Action, FunctionInfo, OwnerAuth, Owners};
+use crate::registry::{check_alias, Registry, Rollback, SetAlias, Target};
+use crate::render::{wants_png, Renderer};
+use crate::response_cache::{CachedResponse, ResponseCache, TableMutations};
 use crate::store::Store;
 
 const MAX_BODY_LENGTH: usize = 1024 * 1024;
 
-pub async fn add_runtime(cid: String, store: Store) -> Result<impl Reply, Rejection> {
-    store.add(cid.clone()).await.map_err(|e| {
-        eprint!("error saving {}: {}", cid, e);
+/// Response header reporting the CID that handled a request.
+pub const FUNCTION_CID_HEADER: &str = "x-function-cid";
+/// Response header reporting whether a response came from the response cache.
+pub const CACHE_HEADER: &str = "x-tbl-cache";
+
+/// Adds a function on behalf of its owner, who must sign the request. The
+/// signer becomes the owner of functions that aren't stored yet.
+pub async fn add_runtime(
+    cid: String,
+    headers: HeaderMap,
+    store: Store,
+    owners: Owners,
+) -> Result<impl Reply, Rejection> {
+    let reject = |e: StoreError| warp::reject::custom(WorkerError::new(e, None));
+    let auth = OwnerAuth::from_headers(&headers).map_err(reject)?;
+    let stored = store.module(cid.clone()).await.is_ok();
+    let owner = owners
+        .authorize(Action::Add, cid.clone(), auth, stored)
+        .await
+        .map_err(reject)?;
+    let report = store
+        .add(cid.clone(), Some(owner.clone()))
+        .await
+        .map_err(|e| {
+            eprintln!("error saving {}: {}", cid, e);
+            reject(e)
+        })?;
+    owners.claim(cid.clone(), owner).await.map_err(reject)?;
+    store.forget_owner(cid.as_str());
+
+    println!("added {}", cid);
+    Ok(warp::reply::json(&report))
+}
+
+pub async fn import_car(
+    headers: HeaderMap,
+    store: Store,
+    owners: Owners,
+    body: Bytes,
+) -> Result<impl Reply, Rejection> {
+    let reject = |e: StoreError| {
+        eprintln!("error importing CAR: {}", e);
         warp::reject::custom(WorkerError::new(e, None))
+    };
+    let auth = OwnerAuth::from_headers(&headers).map_err(reject)?;
+    let (cid, module) = store.unpack(body.to_vec()).await.map_err(reject)?;
+    let stored = store.module(cid.clone()).await.is_ok();
+    let owner = owners
+        .authorize(Action::Add, cid.clone(), auth, stored)
+        .await
+        .map_err(reject)?;
+    let report = store
+        .persist(cid.clone(), module, Some(owner.clone()))
+        .await
+        .map_err(reject)?;
+    owners.claim(cid.clone(), owner).await.map_err(reject)?;
+    store.forget_owner(cid.as_str());
+
+    println!("imported {}", cid);
+    Ok(warp::reply::json(
+        &serde_json::json!({ "cid": cid, "report": report }),
+    ))
+}
+
+/// Removes a function on behalf of its owner, who must sign the request.
+pub async fn remove_runtime(
+    cid: String,
+    headers: HeaderMap,
+    store: Store,
+    owners: Owners,
+) -> Result<impl Reply, Rejection> {
+    let reject = |e: StoreError| warp::reject::custom(WorkerError::new(e, None));
+    let auth = OwnerAuth::from_headers(&headers).map_err(reject)?;
+    owners
+        .authorize(Action::Remove, cid.clone(), auth, true)
+        .await
+        .map_err(reject)?;
+    store.remove(cid.clone()).await.map_err(reject)?;
+    owners.remove(cid.clone()).await.map_err(reject)?;
+
+    println!("removed {}", cid);
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        StatusCode::NO_CONTENT,
+    ))
+}
+
+/// Returns a function's owner and registration details.
+pub async fn get_info(cid: String, store: Store, owners: Owners) -> Result<impl Reply, Rejection> {
+    let reject = |e: StoreError| warp::reject::custom(WorkerError::new(e, None));
+    let info = match owners.info(cid.clone()).await.map_err(reject)? {
+        Some(info) => info,
+        None => {
+            store.module(cid.clone()).await.map_err(reject)?;
+            FunctionInfo {
+                cid,
+                owner: None,
+                chain_id: None,
+                block_number: None,
+                tx_hash: None,
+            }
+        }
+    };
+    Ok(warp::reply::json(&info))
+}
+
+/// Applies a batch of function registry events forwarded by a validator.
+pub async fn ingest_events(
+    signature: Option<String>,
+    body: Bytes,
+    events: Events,
+) -> Result<impl Reply, Rejection> {
+    let reject = |e: StoreError| warp::reject::custom(WorkerError::new(e, None));
+    events.verify(signature.as_deref(), &body).map_err(reject)?;
+    let batch: Vec<Event> =
+        serde_json::from_slice(&body).map_err(|e| reject(StoreError::event_err(e.to_string())))?;
+    let applied = events.apply(batch).await.map_err(|e| {
+        eprintln!("error applying events: {}", e);
+        reject(e)
     })?;
+    Ok(warp::reply::json(&applied))
+}
 
-    println!("added {}", cid);
-    Ok("success")
+pub async fn get_module(cid: String, store: Store) -> Result<impl Reply, Rejection> {
+    let module = store
+        .module(cid)
+        .await
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None)))?;
+    Ok(warp::reply::with_header(
+        module,
+        "content-type",
+        "application/wasm",
+    ))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn invoke_runtime(
-    cid: String,
+    target: Target,
+    reservation: Option<Reservation>,
     store: Store,
+    ledger: Ledger,
     method: Method,
     full_path: FullPath,
     query: String,
     headers: HeaderMap,
+    caller: Option<String>,
     body: Bytes,
+    meta: Meta,
+    links: LinksConfig,
+    renderer: Renderer,
+    cache: ResponseCache,
 ) -> Result<impl Reply, Rejection> {
     if !body_allowed(method.clone(), body.len()) {
         return Err(warp::reject::custom(WorkerError::new(
@@ -40,9 +186,22 @@ pub async fn invoke_runtime(
         )));
     }
 
+    let cid = target.cid;
+    let cid_header = HeaderValue::from_str(cid.as_str()).map_err(|_| {
+        warp::reject::custom(WorkerError::new(
+            StoreError::Ipfs(format!("invalid CID {}", cid)),
+            None,
+        ))
+    })?;
+    let api_key = reservation.as_ref().map(|r| r.name().to_string());
+    let meta = Meta {
+        cid: cid.clone(),
+        ..meta
+    };
+    let request_id = HeaderValue::from_str(meta.request_id.as_str()).unwrap();
     let mut path = full_path
         .as_str()
-        .trim_start_matches(format!("/v1/functions/{}", cid).as_str())
+        .trim_start_matches(target.base.as_str())
         .to_string();
     if !query.is_empty() {
         path = format!("{}?{}", path, query);
@@ -50,31 +209,145 @@ pub async fn invoke_runtime(
     if path.is_empty() {
         path = "/".to_string();
     }
+    let png = wants_png(query.as_str(), &headers);
+    let conditions = Conditions::from_request(&method, &headers);
+    store
+        .moderate(cid.as_str())
+        .await
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None).with_cid(cid.clone())))?;
+    let cache_key = cache.base_key(
+        cid.as_str(),
+        &method,
+        path.as_str(),
+        caller.is_some(),
+        meta.client_ip.as_deref(),
+    );
+    if let Some(hit) = cache_key.as_deref().and_then(|k| cache.get(k, &headers)) {
+        println!("{} {}{} (cached)", method, cid, path);
+        let report = GasReport::default();
+        ledger.record(api_key, cid.clone(), report, false);
+        let mut headers = hit.headers;
+        headers.insert(CACHE_HEADER, HeaderValue::from_static("hit"));
+        headers.insert(REQUEST_ID_HEADER, request_id);
+        let (status, body) = conditions.apply(hit.status, &mut headers, hit.body);
+        return Ok(build_response(
+            cid_header,
+            status,
+            headers,
+            report,
+            QueueReport::default(),
+            body,
+        ));
+    }
+    let req_headers = cache_key.as_ref().map(|_| headers.clone());
+
     let uri = path.parse::<Uri>().unwrap();
     let bbody = match body.is_empty() {
         false => Some(ByteBuf::from(body.to_vec())),
         true => None,
     };
-    let req = Request::new(cid.clone(), uri, method, headers, bbody);
+    let req = Request::new(cid.clone(), uri, method, headers, bbody)
+        .with_caller(caller)
+        .with_meta(meta);
 
     println!("{} {}{}", req.method(), cid, path);
     let out = store.run(cid.clone(), req).await;
-    let report = out.1;
-    let mut res = match out.0 {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(warp::reject::custom(WorkerError::new(e, Some(report))));
+    // Coalesced requests report the ID the function actually saw.
+    let request_id = match out.request_id.as_deref().map(HeaderValue::from_str) {
+        Some(Ok(id)) => id,
+        _ => request_id,
+    };
+    let mut report = out.report;
+    let result = match out.result {
+        Ok(r) => {
+            finish_response(&store, cid.as_str(), r, &links, &renderer, png, &mut report).await
         }
+        Err(e) => Err(e),
     };
+    let error = match &result {
+        Ok((status, _, _)) => status.is_server_error(),
+        Err(_) => true,
+    };
+    // Requests rejected before the function ran, e.g., by the limiter, aren't
+    // usage.
+    if result.is_ok() || report.limit > 0 {
+        ledger.record(api_key, cid.clone(), report, error);
+    }
+    let (status, mut headers, body) = result.map_err(|e| {
+        warp::reject::custom(WorkerError::new(e, Some(report)).with_cid(cid.clone()))
+    })?;
+    if let (Some(key), Some(req_headers)) = (cache_key, req_headers) {
+        let res = CachedResponse {
+            status,
+            headers: headers.clone(),
+            body: body.clone(),
+        };
+        cache.insert(key, &req_headers, res, out.tables);
+        headers.insert(CACHE_HEADER, HeaderValue::from_static("miss"));
+    }
+    headers.insert(REQUEST_ID_HEADER, request_id);
+    let (status, body) = conditions.apply(status, &mut headers, body);
 
     Ok(build_response(
-        StatusCode::from_u16(res.status_code()).unwrap(),
-        res.headers().clone(),
-        report,
-        res.bytes().unwrap(),
+        cid_header, status, headers, report, out.queue, body,
     ))
 }
 
+/// Applies link rewriting and, if the client asked for it, PNG rendering to a
+/// function response. Rendering is charged to `report`.
+async fn finish_response(
+    store: &Store,
+    cid: &str,
+    mut res: Response,
+    links: &LinksConfig,
+    renderer: &Renderer,
+    png: bool,
+    report: &mut GasReport,
+) -> Result<(StatusCode, HeaderMap, Vec<u8>), StoreError> {
+    let status = StatusCode::from_u16(res.status_code()).unwrap();
+    let mut headers = res.headers().clone();
+    let body = rewrite_response(
+        links.public_url.as_str(),
+        &mut headers,
+        res.bytes().unwrap(),
+    );
+
+    let svg = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |ct| ct.starts_with("image/svg+xml"));
+    if !svg {
+        return Ok((status, headers, body));
+    }
+    headers.append(VARY, HeaderValue::from_static("accept"));
+    if !png || !status.is_success() {
+        return Ok((status, headers, body));
+    }
+
+    // Rendering takes an execution slot, like the function itself.
+    let _permit = store.acquire(cid).await?;
+    let r = renderer.clone();
+    let mut charged = *report;
+    let (png, charged) = tokio::task::spawn_blocking(move || {
+        let png = r.render(&body, &mut charged);
+        (png, charged)
+    })
+    .await?;
+    *report = charged;
+    let png = png?;
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+    headers.remove(CONTENT_LENGTH);
+    Ok((status, headers, png))
+}
+
+pub async fn get_usage(query: UsageQuery, ledger: Ledger) -> Result<impl Reply, Rejection> {
+    let usage = ledger
+        .usage(query)
+        .await
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None)))?;
+    Ok(warp::reply::json(&usage))
+}
+
 fn body_allowed(method: Method, body_length: usize) -> bool {
     match method {
         Method::GET | Method::DELETE | Method::TRACE | Method::OPTIONS | Method::HEAD => {
@@ -85,19 +358,272 @@ fn body_allowed(method: Method, body_length: usize) -> bool {
 }
 
 fn build_response(
+    cid: HeaderValue,
     status: StatusCode,
     mut headers: HeaderMap,
     report: GasReport,
+    queue: QueueReport,
     body: Vec<u8>,
 ) -> WarpResponse<Vec<u8>> {
     let wres = WarpResponse::builder().status(status).body(body).unwrap();
     let (mut parts, body) = wres.into_parts();
 
+    headers.insert(FUNCTION_CID_HEADER, cid);
     headers.append("x-gas-limit", HeaderValue::from(report.limit));
     headers.append("x-gas-remaining", HeaderValue::from(report.remaining));
     headers.append("x-gas-external", HeaderValue::from(report.used_externally));
     headers.append("x-gas-internal", HeaderValue::from(report.used_internally));
+    headers.append("x-queue-depth", HeaderValue::from(queue.depth));
+    headers.append(
+        "x-queue-wait-ms",
+        HeaderValue::from(queue.wait.as_millis() as u64),
+    );
     parts.headers = headers;
 
     WarpResponse::from_parts(parts, body)
 }
+
+/// Drops cached responses that read any of the mutated tables.
+pub async fn table_mutations(
+    req: TableMutations,
+    cache: ResponseCache,
+) -> Result<impl Reply, Rejection> {
+    let invalidated = cache.invalidate(&req.tables);
+    if invalidated > 0 {
+        println!(
+            "invalidated {} cached responses for {}",
+            invalidated,
+            req.tables.join(", ")
+        );
+    }
+    Ok(warp::reply::json(
+        &serde_json::json!({ "invalidated": invalidated }),
+    ))
+}
+
+pub async fn list_aliases(registry: Registry) -> Result<impl Reply, Rejection> {
+    let aliases = registry
+        .list()
+        .await
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None)))?;
+    Ok(warp::reply::json(&aliases))
+}
+
+pub async fn get_alias(name: String, registry: Registry) -> Result<impl Reply, Rejection> {
+    let history = registry
+        .history(name.clone())
+        .await
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None)))?;
+    if history.is_empty() {
+        return Err(warp::reject::custom(WorkerError::new(
+            StoreError::NotFound(format!("alias {} not found", name)),
+            None,
+        )));
+    }
+    Ok(warp::reply::json(&history))
+}
+
+/// Points a name at a CID. The function is added first if this worker doesn't
+/// have it, so names never point at invalid modules.
+pub async fn set_alias(
+    name: String,
+    req: SetAlias,
+    store: Store,
+    registry: Registry,
+) -> Result<impl Reply, Rejection> {
+    let reject = |e: StoreError| warp::reject::custom(WorkerError::new(e, None));
+    // Bad requests are turned away before any module is fetched.
+    check_alias(name.as_str(), &req).map_err(reject)?;
+    let cids = std::iter::once(&req.cid).chain(req.splits.iter().map(|s| &s.cid));
+    for cid in cids {
+        if store.module(cid.clone()).await.is_err() {
+            store.add(cid.clone(), None).await.map_err(reject)?;
+        }
+    }
+    let alias = registry.set(name, req).await.map_err(reject)?;
+
+    println!("set {} to {} (v{})", alias.name, alias.cid, alias.version);
+    Ok(warp::reply::json(&alias))
+}
+
+pub async fn rollback_alias(
+    name: String,
+    req: Rollback,
+    registry: Registry,
+) -> Result<impl Reply, Rejection> {
+    let alias = registry
+        .rollback(name, req)
+        .await
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None)))?;
+
+    println!(
+        "rolled {} back to {} (v{})",
+        alias.name, alias.cid, alias.version
+    );
+    Ok(warp::reply::json(&alias))
+}
+
+/// Returns usage for each CID a name currently routes to, so versions can be
+/// compared before one is promoted.
+pub async fn get_alias_stats(
+    name: String,
+    query: UsageQuery,
+    registry: Registry,
+    ledger: Ledger,
+) -> Result<impl Reply, Rejection> {
+    let reject = |e: StoreError| warp::reject::custom(WorkerError::new(e, None));
+    let route = registry
+        .resolve(name.as_str())
+        .ok_or_else(|| reject(StoreError::NotFound(format!("alias {} not found", name))))?;
+    let usage = ledger.usage(query).await.map_err(reject)?;
+
+    let stats: Vec<UsageEntry> = route
+        .cids()
+        .into_iter()
+        .map(|cid| {
+            usage
+                .functions
+                .iter()
+                .find(|e| e.id == cid)
+                .cloned()
+                .unwrap_or(UsageEntry {
+                    id: cid,
+                    requests: 0,
+                    errors: 0,
+                    gas: 0,
+                })
+        })
+        .collect();
+    Ok(warp::reply::json(&serde_json::json!({
+        "since": usage.since,
+        "until": usage.until,
+        "functions": stats,
+    })))
+}
+
+pub async fn list_env(cid: String, env: Env) -> Result<impl Reply, Rejection> {
+    let vars = env
+        .list(cid)
+        .await
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None)))?;
+    Ok(warp::reply::json(&vars))
+}
+
+/// Sets a function variable. Values are never logged.
+pub async fn set_env(
+    cid: String,
+    key: String,
+    var: SetVar,
+    env: Env,
+    cache: ResponseCache,
+) -> Result<impl Reply, Rejection> {
+    let secret = var.secret;
+    env.set(cid.clone(), key.clone(), var)
+        .await
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None)))?;
+    cache.invalidate_function(cid.as_str());
+
+    match secret {
+        true => println!("set secret {} for {}", key, cid),
+        false => println!("set variable {} for {}", key, cid),
+    }
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        StatusCode::NO_CONTENT,
+    ))
+}
+
+pub async fn delete_env(
+    cid: String,
+    key: String,
+    env: Env,
+    cache: ResponseCache,
+) -> Result<impl Reply, Rejection> {
+    env.delete(cid.clone(), key.clone())
+        .await
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None)))?;
+    cache.invalidate_function(cid.as_str());
+
+    println!("deleted {} for {}", key, cid);
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        StatusCode::NO_CONTENT,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ModerationConfig, SourceConfig};
+    use crate::db::Db;
+    use crate::moderation::Moderation;
+    use crate::owners::tests::{address, auth};
+    use crate::signature::{ADDRESS_HEADER, NONCE_HEADER, SIGNATURE_HEADER};
+    use k256::ecdsa::SigningKey;
+
+    const EXAMPLE_JSON_WASM: &[u8] = include_bytes!("../testdata/json.wasm");
+
+    #[tokio::test]
+    async fn add_runtime_enforces_owner_deny_list() {
+        let dir = std::env::temp_dir().join(format!("tbl-handlers-add-{}", std::process::id()));
+        let (cache, source) = (dir.join("cache"), dir.join("source"));
+        std::fs::create_dir_all(&cache).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.wasm"), EXAMPLE_JSON_WASM).unwrap();
+
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        let bob = SigningKey::from_bytes(&[2u8; 32].into()).unwrap();
+        let lists = dir.join("lists.json");
+        std::fs::write(
+            &lists,
+            format!(r#"{{"deny": {{"owners": ["{}"]}}}}"#, address(&alice)),
+        )
+        .unwrap();
+
+        let mut config = Config::default();
+        config.cache.directory = cache.to_str().unwrap().to_string();
+        config.ipfs.sources = vec![SourceConfig::Directory {
+            path: source.to_str().unwrap().to_string(),
+        }];
+        config.ipfs.allow_unverified = true;
+        let owners = Owners::new(Db::open_in_memory().unwrap());
+        let moderation = Moderation::open(
+            ModerationConfig {
+                path: lists.to_str().unwrap().to_string(),
+                reload_interval: 0,
+            },
+            owners.clone(),
+        )
+        .unwrap();
+        let store = Store::new(config).with_moderation(moderation);
+        let headers = |key: &SigningKey| {
+            let auth = auth(key, Action::Add, "a", 1);
+            let mut headers = HeaderMap::new();
+            headers.insert(ADDRESS_HEADER, auth.address.parse().unwrap());
+            headers.insert(SIGNATURE_HEADER, auth.signature.parse().unwrap());
+            headers.insert(NONCE_HEADER, auth.nonce.into());
+            headers
+        };
+
+        // The signer is checked before the module is stored or claimed.
+        assert!(
+            add_runtime("a".into(), headers(&alice), store.clone(), owners.clone())
+                .await
+                .is_err()
+        );
+        assert!(store.module("a".to_string()).await.is_err());
+        assert!(owners.info("a".into()).await.unwrap().is_none());
+
+        assert!(
+            add_runtime("a".into(), headers(&bob), store.clone(), owners.clone())
+                .await
+                .is_ok()
+        );
+        assert_eq!(
+            owners.info("a".into()).await.unwrap().unwrap().owner,
+            Some(address(&bob))
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+use reqwest::Client;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tableland_client::get_chain;
+use tokio::sync::Mutex;
+use warp::{http::StatusCode, Reply};
+
+use crate::config::Config;
+use crate::sources::Sources;
+
+/// Tracks whether the worker is able to serve requests.
+#[derive(Clone)]
+pub struct Health {
+    targets: Arc<Targets>,
+    sources: Arc<Sources>,
+    http_client: Client,
+    probe_ttl: Duration,
+    probe_timeout: Duration,
+    started: Arc<AtomicBool>,
+    last: Arc<Mutex<Option<(Instant, Probes)>>>,
+}
+
+struct Targets {
+    cache_dir: String,
+    tableland: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn from_result<E: ToString>(res: Result<(), E>) -> Self {
+        match res {
+            Ok(()) => Check {
+                ok: true,
+                error: None,
+            },
+            Err(e) => Check {
+                ok: false,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Probes {
+    pub cache: Check,
+    pub ipfs: Check,
+    pub tableland: Check,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub started: bool,
+    #[serde(flatten)]
+    pub probes: Probes,
+}
+
+impl Health {
+    pub fn new(config: &Config) -> Self {
+        Self::with_targets(
+            config.cache.directory.clone(),
+            Sources::from_config(&config.ipfs),
+            get_chain(config.chain.id.clone()).endpoint,
+            Duration::from_secs(config.health.probe_ttl),
+            Duration::from_secs(config.health.probe_timeout),
+        )
+    }
+
+    fn with_targets(
+        cache_dir: String,
+        sources: Sources,
+        tableland: String,
+        probe_ttl: Duration,
+        probe_timeout: Duration,
+    ) -> Self {
+        Health {
+            targets: Arc::new(Targets {
+                cache_dir,
+                tableland,
+            }),
+            sources: Arc::new(sources),
+            probe_timeout,
+            http_client: Client::builder().timeout(probe_timeout).build().unwrap(),
+            probe_ttl,
+            started: Arc::new(AtomicBool::new(false)),
+            last: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Marks startup work as finished.
+    pub fn set_started(&self) {
+        self.started.store(true, Ordering::SeqCst);
+    }
+
+    pub async fn readiness(&self) -> Readiness {
+        let probes = {
+            let mut last = self.last.lock().await;
+            match last.as_ref() {
+                Some((at, probes)) if at.elapsed() < self.probe_ttl => probes.clone(),
+                _ => {
+                    let probes = self.probe().await;
+                    *last = Some((Instant::now(), probes.clone()));
+                    probes
+                }
+            }
+        };
+        let started = self.started.load(Ordering::SeqCst);
+        Readiness {
+            ready: started && probes.cache.ok && probes.ipfs.ok && probes.tableland.ok,
+            started,
+            probes,
+        }
+    }
+
+    async fn probe(&self) -> Probes {
+        let (cache, ipfs, tableland) = tokio::join!(
+            self.probe_cache(),
+            self.probe_sources(),
+            self.probe_url(format!("{}/healthz", self.targets.tableland)),
+        );
+        Probes {
+            cache,
+            ipfs,
+            tableland,
+        }
+    }
+
+    async fn probe_cache(&self) -> Check {
+        let file_name = format!("{}/.readyz", self.targets.cache_dir);
+        let res = async {
+            tokio::fs::write(&file_name, b"ok").await?;
+            tokio::fs::remove_file(&file_name).await
+        }
+        .await;
+        Check::from_result(res)
+    }
+
+    /// Checks that modules can be fetched from at least one source.
+    async fn probe_sources(&self) -> Check {
+        let res = tokio::time::timeout(self.probe_timeout, self.sources.probe()).await;
+        Check::from_result(match res {
+            Ok(r) => r.map_err(|e| e.to_string()),
+            Err(_) => Err("timed out".to_string()),
+        })
+    }
+
+    async fn probe_url(&self, url: String) -> Check {
+        let res = self.http_client.get(url).send().await;
+        Check::from_result(res.and_then(|r| r.error_for_status()).map(|_| ()))
+    }
+}
+
+pub async fn healthz() -> Result<impl Reply, warp::Rejection> {
+    Ok("ok")
+}
+
+pub async fn readyz(health: Health) -> Result<impl Reply, warp::Rejection> {
+    let readiness = health.readiness().await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&readiness),
+        status,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FetchStrategy;
+    use crate::sources::GatewaySource;
+    use std::net::SocketAddr;
+    use std::sync::atomic::AtomicUsize;
+    use warp::Filter;
+
+    /// Serves `/healthz` and counts requests to it.
+    fn serve(hits: Arc<AtomicUsize>) -> SocketAddr {
+        let route = warp::path!("healthz").map(move || {
+            hits.fetch_add(1, Ordering::SeqCst);
+            "ok"
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        addr
+    }
+
+    fn health(addr: SocketAddr, ttl: Duration) -> Health {
+        Health::with_targets(
+            std::env::temp_dir().to_str().unwrap().to_string(),
+            Sources::new(
+                vec![Arc::new(GatewaySource::new(
+                    format!("http://{}/ipfs", addr),
+                    Duration::from_secs(1),
+                ))],
+                FetchStrategy::Ordered,
+                1024,
+                Duration::default(),
+                false,
+            ),
+            format!("http://{}", addr),
+            ttl,
+            Duration::from_secs(1),
+        )
+    }
+
+    #[tokio::test]
+    async fn readiness_works() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let health = health(serve(hits.clone()), Duration::from_secs(60));
+
+        let r = health.readiness().await;
+        assert!(r.probes.cache.ok && r.probes.ipfs.ok && r.probes.tableland.ok);
+        assert!(!r.ready, "not ready until started");
+
+        health.set_started();
+        assert!(health.readiness().await.ready);
+        // Probe results are cached.
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn readiness_fails_when_unreachable() {
+        // Nothing listens on the discard port.
+        let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let health = health(addr, Duration::default());
+        health.set_started();
+
+        let r = health.readiness().await;
+        assert!(!r.ready);
+        assert!(!r.probes.ipfs.ok);
+        assert!(!r.probes.tableland.ok);
+        assert!(r.probes.tableland.error.is_some());
+    }
+}
@@ -1,10 +1,12 @@
 use std::collections::HashSet;
 use tableland_client::TablelandClient;
-use tableland_vm::{capabilities_from_csv, check_wasm, Backend, Instance, InstanceOptions, Size};
+use tableland_vm::{
+    capabilities_from_csv, check_wasm, Backend, Instance, InstanceOptions, Size, VmResult,
+};
 
 use crate::backend::Api;
 
-const DEFAULT_GAS_LIMIT: u64 = 2_000_000_000_000;
+pub(crate) const DEFAULT_GAS_LIMIT: u64 = 2_000_000_000_000;
 const DEFAULT_MEMORY_LIMIT: Option<Size> = Some(Size::mebi(16));
 const DEFAULT_PRINT_DEBUG: bool = true;
 
@@ -20,9 +22,7 @@ pub struct ApiInstanceOptions {
 
 impl ApiInstanceOptions {
     fn default_capabilities() -> HashSet<String> {
-        #[allow(unused_mut)]
-        let mut out = capabilities_from_csv("");
-        out
+        capabilities_from_csv("crypto")
     }
 }
 
@@ -41,8 +41,8 @@ pub fn instance_with_options(
     wasm: &[u8],
     options: ApiInstanceOptions,
     client: TablelandClient,
-) -> Instance<Api<TablelandClient>> {
-    check_wasm(wasm, &options.available_capabilities).unwrap();
+) -> VmResult<Instance<Api<TablelandClient>>> {
+    check_wasm(wasm, &options.available_capabilities)?;
 
     let backend = Backend {
         api: Api::new(client),
@@ -52,6 +52,5 @@ pub fn instance_with_options(
         gas_limit: options.gas_limit,
         print_debug: options.print_debug,
     };
-    // todo: catch error, could be bad wasm
-    Instance::from_code(wasm, backend, options, memory_limit).unwrap()
+    Instance::from_code(wasm, backend, options, memory_limit)
 }
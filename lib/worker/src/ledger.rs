@@ -0,0 +1,360 @@
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tableland_vm::GasReport;
+
+use crate::config::ApiKey;
+use crate::db::Db;
+use crate::errors::StoreError;
+
+/// How often recorded usage is written to the database.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Records the gas used by every function invocation. Usage is buffered and
+/// written in batches by [`Ledger::watch`].
+#[derive(Clone)]
+pub struct Ledger {
+    db: Db,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Usage that hasn't been written yet.
+    pending: Vec<Row>,
+    /// Gas reserved by in-flight requests, per API key.
+    reserved: HashMap<String, u64>,
+}
+
+struct Row {
+    ts: u64,
+    api_key: Option<String>,
+    cid: String,
+    report: GasReport,
+    error: bool,
+}
+
+/// Gas reserved against an API key's quota, released when dropped.
+pub struct Reservation {
+    name: String,
+    gas: u64,
+    state: Arc<Mutex<State>>,
+}
+
+impl Reservation {
+    /// Returns the API key's name.
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(gas) = state.reserved.get_mut(&self.name) {
+            *gas -= self.gas;
+            if *gas == 0 {
+                state.reserved.remove(&self.name);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UsageQuery {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct UsageEntry {
+    pub id: String,
+    pub requests: u64,
+    /// Requests that failed or returned a 5xx status.
+    pub errors: u64,
+    pub gas: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    pub since: u64,
+    pub until: u64,
+    pub keys: Vec<UsageEntry>,
+    pub functions: Vec<UsageEntry>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl Ledger {
+    pub fn new(db: Db) -> Self {
+        Ledger {
+            db,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Buffers the gas used by an invocation.
+    pub fn record(&self, api_key: Option<String>, cid: String, report: GasReport, error: bool) {
+        self.state.lock().unwrap().pending.push(Row {
+            ts: now(),
+            api_key,
+            cid,
+            report,
+            error,
+        });
+    }
+
+    /// Writes buffered usage every second.
+    pub async fn watch(self) {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.flush().await {
+                eprintln!("error recording usage: {}", e);
+            }
+        }
+    }
+
+    /// Writes buffered usage.
+    pub async fn flush(&self) -> Result<(), StoreError> {
+        let state = self.state.clone();
+        self.db.call(move |c| write_pending(c, &state)).await
+    }
+
+    /// Reserves `gas` for a request with an API key, failing if the key has
+    /// used up its quota for the current period. Used, buffered and reserved
+    /// gas all count against the quota, so concurrent requests can overshoot
+    /// it by at most one reservation.
+    pub async fn reserve(&self, key: &ApiKey, gas: u64) -> Result<Reservation, StoreError> {
+        let reservation = Reservation {
+            name: key.name.clone(),
+            gas: if key.gas_quota > 0 { gas } else { 0 },
+            state: self.state.clone(),
+        };
+        if key.gas_quota == 0 {
+            return Ok(reservation);
+        }
+        let (name, quota) = (key.name.clone(), key.gas_quota);
+        let since = period_start(now(), key.period);
+        let state = self.state.clone();
+        // Checking and reserving with the connection held keeps flushes from
+        // moving rows between the table and the buffer mid-check.
+        self.db
+            .call(move |c| {
+                let stored: i64 = c.query_row(
+                    "SELECT COALESCE(SUM(gas_external + gas_internal), 0) FROM usage
+                    WHERE api_key = ?1 AND ts >= ?2",
+                    params![name, since as i64],
+                    |r| r.get(0),
+                )?;
+                let mut state = state.lock().unwrap();
+                let buffered: u64 = state
+                    .pending
+                    .iter()
+                    .filter(|r| r.api_key.as_ref() == Some(&name) && r.ts >= since)
+                    .map(|r| r.report.used_externally + r.report.used_internally)
+                    .sum();
+                let reserved = state.reserved.get(&name).copied().unwrap_or_default();
+                if stored as u64 + buffered + reserved >= quota {
+                    return Err(StoreError::QuotaExceeded(name));
+                }
+                *state.reserved.entry(name).or_default() += reservation.gas;
+                Ok(reservation)
+            })
+            .await
+    }
+
+    /// Returns usage per API key and per CID within a time range.
+    pub async fn usage(&self, query: UsageQuery) -> Result<Usage, StoreError> {
+        let since = query.since.unwrap_or(0);
+        let until = query.until.unwrap_or_else(now);
+        let state = self.state.clone();
+        self.db
+            .call(move |c| {
+                write_pending(c, &state)?;
+                let entries = |column: &str| -> Result<Vec<UsageEntry>, StoreError> {
+                    let mut stmt = c.prepare(&format!(
+                        "SELECT {0}, COUNT(*), SUM(error), SUM(gas_external + gas_internal)
+                        FROM usage
+                        WHERE {0} IS NOT NULL AND ts >= ?1 AND ts <= ?2
+                        GROUP BY {0} ORDER BY {0}",
+                        column
+                    ))?;
+                    let rows = stmt.query_map(params![since as i64, until as i64], |r| {
+                        Ok(UsageEntry {
+                            id: r.get(0)?,
+                            requests: r.get::<_, i64>(1)? as u64,
+                            errors: r.get::<_, i64>(2)? as u64,
+                            gas: r.get::<_, i64>(3)? as u64,
+                        })
+                    })?;
+                    Ok(rows.collect::<Result<_, _>>()?)
+                };
+                Ok(Usage {
+                    since,
+                    until,
+                    keys: entries("api_key")?,
+                    functions: entries("cid")?,
+                })
+            })
+            .await
+    }
+}
+
+/// Returns the start of the quota period containing `ts`.
+fn period_start(ts: u64, period: u64) -> u64 {
+    if period == 0 {
+        return 0;
+    }
+    ts - ts % period
+}
+
+/// Writes buffered usage in one transaction.
+fn write_pending(c: &mut Connection, state: &Mutex<State>) -> Result<(), StoreError> {
+    let mut state = state.lock().unwrap();
+    if state.pending.is_empty() {
+        return Ok(());
+    }
+    let tx = c.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO usage
+            (ts, api_key, cid, gas_limit, gas_external, gas_internal, error)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for row in state.pending.iter() {
+            stmt.execute(params![
+                row.ts as i64,
+                row.api_key,
+                row.cid,
+                row.report.limit as i64,
+                row.report.used_externally as i64,
+                row.report.used_internally as i64,
+                row.error
+            ])?;
+        }
+    }
+    tx.commit()?;
+    state.pending.clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(external: u64, internal: u64) -> GasReport {
+        GasReport {
+            limit: 100,
+            remaining: 100 - external - internal,
+            used_externally: external,
+            used_internally: internal,
+        }
+    }
+
+    fn key(gas_quota: u64) -> ApiKey {
+        ApiKey {
+            name: "a".to_string(),
+            key: "secret".to_string(),
+            gas_quota,
+            period: 3600,
+        }
+    }
+
+    #[tokio::test]
+    async fn record_works() {
+        let ledger = Ledger::new(Db::open_in_memory().unwrap());
+        ledger.record(
+            Some("a".to_string()),
+            "cid1".to_string(),
+            report(1, 2),
+            false,
+        );
+        ledger.flush().await.unwrap();
+        ledger.record(
+            Some("a".to_string()),
+            "cid2".to_string(),
+            report(3, 4),
+            true,
+        );
+        ledger.record(None, "cid1".to_string(), report(5, 5), true);
+
+        let usage = ledger.usage(UsageQuery::default()).await.unwrap();
+        assert_eq!(
+            usage.keys,
+            vec![UsageEntry {
+                id: "a".to_string(),
+                requests: 2,
+                errors: 1,
+                gas: 10
+            }]
+        );
+        assert_eq!(
+            usage.functions,
+            vec![
+                UsageEntry {
+                    id: "cid1".to_string(),
+                    requests: 2,
+                    errors: 1,
+                    gas: 13
+                },
+                UsageEntry {
+                    id: "cid2".to_string(),
+                    requests: 1,
+                    errors: 1,
+                    gas: 7
+                }
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reserve_works() {
+        let ledger = Ledger::new(Db::open_in_memory().unwrap());
+
+        // Reservations count until they're dropped.
+        let r = ledger.reserve(&key(10), 6).await.unwrap();
+        assert_eq!(r.name(), "a");
+        let r2 = ledger.reserve(&key(10), 6).await.unwrap();
+        assert!(matches!(
+            ledger.reserve(&key(10), 6).await,
+            Err(StoreError::QuotaExceeded(_))
+        ));
+        drop((r, r2));
+        assert!(ledger.reserve(&key(10), 6).await.is_ok());
+
+        // Buffered and written usage both count.
+        ledger.record(
+            Some("a".to_string()),
+            "cid".to_string(),
+            report(4, 4),
+            false,
+        );
+        assert!(ledger.reserve(&key(10), 6).await.is_ok());
+        ledger.record(
+            Some("a".to_string()),
+            "cid".to_string(),
+            report(1, 1),
+            false,
+        );
+        assert!(ledger.reserve(&key(10), 6).await.is_err());
+        ledger.flush().await.unwrap();
+        assert!(ledger.reserve(&key(10), 6).await.is_err());
+
+        // Keys without a quota are never rejected.
+        assert!(ledger.reserve(&key(0), 6).await.is_ok());
+    }
+
+    #[test]
+    fn period_start_works() {
+        assert_eq!(period_start(7250, 3600), 7200);
+        assert_eq!(period_start(7250, 0), 0);
+    }
+}
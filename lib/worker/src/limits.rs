@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::LimitsConfig;
+use crate::errors::StoreError;
+
+/// Bounds how many functions can execute at once, globally and per CID.
+/// Requests that can't run right away wait in a bounded queue; once the queue
+/// is full, new requests are rejected with [`StoreError::Overloaded`].
+#[derive(Clone)]
+pub struct Limiter {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: LimitsConfig,
+    global: Arc<Semaphore>,
+    functions: Mutex<HashMap<String, Arc<Semaphore>>>,
+    queued: AtomicUsize,
+}
+
+/// Queue stats for a single execution.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueueReport {
+    /// Number of requests waiting ahead of this one when it arrived.
+    pub depth: usize,
+    /// Time spent waiting for an execution slot.
+    pub wait: Duration,
+}
+
+/// Execution slot held for the duration of a function call.
+pub struct Permit {
+    limiter: Arc<Inner>,
+    cid: String,
+    permits: Option<(OwnedSemaphorePermit, OwnedSemaphorePermit)>,
+    pub report: QueueReport,
+}
+
+struct QueueGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Checks that the limits let functions run at all. A zero limit would leave
+/// every request waiting forever.
+pub fn check_config(config: &LimitsConfig) -> Result<(), &'static str> {
+    if config.max_concurrent == 0 {
+        return Err("limits.max_concurrent must be greater than 0");
+    }
+    if config.max_concurrent_per_function == 0 {
+        return Err("limits.max_concurrent_per_function must be greater than 0");
+    }
+    Ok(())
+}
+
+impl Limiter {
+    pub fn new(config: LimitsConfig) -> Self {
+        Limiter {
+            inner: Arc::new(Inner {
+                global: Arc::new(Semaphore::new(config.max_concurrent)),
+                functions: Mutex::new(HashMap::new()),
+                queued: AtomicUsize::new(0),
+                config,
+            }),
+        }
+    }
+
+    pub async fn acquire(&self, cid: &str) -> Result<Permit, StoreError> {
+        let start = Instant::now();
+        let function = self.function_semaphore(cid);
+
+        // Fast path: don't count requests that never have to wait.
+        if let Ok(fp) = function.clone().try_acquire_owned() {
+            if let Ok(gp) = self.inner.global.clone().try_acquire_owned() {
+                return Ok(self.permit(cid, fp, gp, QueueReport::default()));
+            }
+        }
+
+        let depth = self.inner.queued.fetch_add(1, Ordering::SeqCst);
+        let _guard = QueueGuard(&self.inner.queued);
+        if depth >= self.inner.config.max_queue {
+            return Err(StoreError::Overloaded(self.inner.config.retry_after));
+        }
+
+        // Take the function slot first so a single hot function can't hold
+        // global slots while it waits on itself.
+        let fp = function
+            .acquire_owned()
+            .await
+            .map_err(|e| StoreError::cache_err(e.to_string()))?;
+        let gp = self
+            .inner
+            .global
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| StoreError::cache_err(e.to_string()))?;
+
+        let report = QueueReport {
+            depth,
+            wait: start.elapsed(),
+        };
+        Ok(self.permit(cid, fp, gp, report))
+    }
+
+    fn function_semaphore(&self, cid: &str) -> Arc<Semaphore> {
+        let mut functions = self.inner.functions.lock().unwrap();
+        functions
+            .entry(cid.to_string())
+            .or_insert_with(|| {
+                Arc::new(Semaphore::new(
+                    self.inner.config.max_concurrent_per_function,
+                ))
+            })
+            .clone()
+    }
+
+    fn permit(
+        &self,
+        cid: &str,
+        function: OwnedSemaphorePermit,
+        global: OwnedSemaphorePermit,
+        report: QueueReport,
+    ) -> Permit {
+        Permit {
+            limiter: self.inner.clone(),
+            cid: cid.to_string(),
+            permits: Some((function, global)),
+            report,
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.permits.take();
+
+        // Forget the function's semaphore once nobody is using it.
+        let mut functions = self.limiter.functions.lock().unwrap();
+        if let Some(s) = functions.get(&self.cid) {
+            if Arc::strong_count(s) == 1 {
+                functions.remove(&self.cid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_concurrent: usize, per_function: usize, max_queue: usize) -> Limiter {
+        Limiter::new(LimitsConfig {
+            max_concurrent,
+            max_concurrent_per_function: per_function,
+            max_queue,
+            retry_after: 3,
+        })
+    }
+
+    #[test]
+    fn check_config_works() {
+        assert!(check_config(&LimitsConfig::default()).is_ok());
+        assert!(check_config(&limiter(0, 1, 1).inner.config).is_err());
+        assert!(check_config(&limiter(1, 0, 1).inner.config).is_err());
+    }
+
+    #[tokio::test]
+    async fn acquire_works() {
+        let limiter = limiter(2, 2, 1);
+        let p = limiter.acquire("a").await.unwrap();
+        assert_eq!(p.report.depth, 0);
+        drop(p);
+        assert!(limiter.inner.functions.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn acquire_rejects_when_queue_full() {
+        let limiter = limiter(1, 1, 1);
+        let held = limiter.acquire("a").await.unwrap();
+
+        let waiter = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move { limiter.acquire("a").await.map(|p| p.report) })
+        };
+        while limiter.inner.queued.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        match limiter.acquire("b").await {
+            Err(StoreError::Overloaded(retry_after)) => assert_eq!(retry_after, 3),
+            _ => panic!("expected overloaded error"),
+        }
+
+        drop(held);
+        let report = waiter.await.unwrap().unwrap();
+        assert_eq!(report.depth, 0);
+        assert_eq!(limiter.inner.queued.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn acquire_limits_per_function() {
+        let limiter = limiter(4, 1, 4);
+        let _a = limiter.acquire("a").await.unwrap();
+        // Another function still runs without waiting.
+        let b = limiter.acquire("b").await.unwrap();
+        assert_eq!(b.report.wait, Duration::default());
+        assert_eq!(limiter.inner.queued.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,114 @@
+use warp::http::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE};
+
+/// Response header functions can set to `off` to leave their links alone.
+pub const REWRITE_HEADER: &str = "x-tbl-rewrite";
+
+const SCHEME: &str = "tbl://";
+
+/// Content types that may contain `tbl://` links.
+const REWRITE_TYPES: &[&str] = &["json", "html", "svg"];
+
+/// Rewrites `tbl://<cid>/<path>` links in a function response to
+/// `<public_url>/v1/functions/<cid>/<path>` so clients can follow them.
+/// Does nothing if `public_url` is empty.
+pub fn rewrite_response(public_url: &str, headers: &mut HeaderMap, body: Vec<u8>) -> Vec<u8> {
+    let opt_out = headers
+        .remove(REWRITE_HEADER)
+        .map_or(false, |v| v.as_bytes().eq_ignore_ascii_case(b"off"));
+    let rewritable = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |ct| {
+            let mime = ct
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            REWRITE_TYPES.iter().any(|t| mime.contains(t))
+        });
+    if public_url.is_empty() || opt_out || !rewritable {
+        return body;
+    }
+    match rewrite(&body, public_url) {
+        Some(rewritten) => {
+            headers.remove(CONTENT_LENGTH);
+            rewritten
+        }
+        None => body,
+    }
+}
+
+/// Returns the rewritten body, or `None` if it has no links or isn't UTF-8.
+fn rewrite(body: &[u8], public_url: &str) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(body).ok()?;
+    if !text.contains(SCHEME) {
+        return None;
+    }
+    let prefix = format!("{}/v1/functions/", public_url.trim_end_matches('/'));
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find(SCHEME) {
+        let after = &rest[i + SCHEME.len()..];
+        out.push_str(&rest[..i]);
+        // Only rewrite links that name a CID.
+        if after.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+            out.push_str(&prefix);
+        } else {
+            out.push_str(SCHEME);
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    Some(out.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(content_type: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+        headers.insert(CONTENT_LENGTH, "10".parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn rewrite_response_works() {
+        let body = br#"{"image":"tbl://bafy123/1/image","other":"tbl://"}"#.to_vec();
+
+        let mut h = headers("application/json; charset=utf-8");
+        assert_eq!(
+            rewrite_response("https://fn.example.com/", &mut h, body),
+            br#"{"image":"https://fn.example.com/v1/functions/bafy123/1/image","other":"tbl://"}"#
+        );
+        assert!(h.get(CONTENT_LENGTH).is_none());
+
+        let mut h = headers("image/svg+xml");
+        let svg = b"<image href=\"tbl://bafy123/a\"/>".to_vec();
+        assert_eq!(
+            rewrite_response("http://w", &mut h, svg),
+            b"<image href=\"http://w/v1/functions/bafy123/a\"/>"
+        );
+    }
+
+    #[test]
+    fn rewrite_response_skips() {
+        let body = b"tbl://bafy123/1".to_vec();
+
+        // Disabled.
+        let mut h = headers("text/html");
+        assert_eq!(rewrite_response("", &mut h, body.clone()), body);
+
+        // Other content types.
+        let mut h = headers("text/plain");
+        assert_eq!(rewrite_response("http://w", &mut h, body.clone()), body);
+        assert!(h.get(CONTENT_LENGTH).is_some());
+
+        // Opted out. The header isn't passed on.
+        let mut h = headers("text/html");
+        h.insert(REWRITE_HEADER, "off".parse().unwrap());
+        assert_eq!(rewrite_response("http://w", &mut h, body.clone()), body);
+        assert!(h.get(REWRITE_HEADER).is_none());
+    }
+}
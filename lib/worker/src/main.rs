@@ -1,33 +1,173 @@
+mod auth;
 mod backend;
+mod blocks;
+mod cache;
+mod car;
+mod conditional;
 mod config;
+mod db;
+mod env;
 mod errors;
+mod events;
+mod evict;
 mod handlers;
+mod health;
 mod instance;
+mod ledger;
+mod limits;
+mod links;
+mod meta;
+mod moderation;
+mod owners;
+mod poller;
+mod ratelimit;
+mod registry;
+mod render;
+mod response_cache;
+mod signature;
+mod singleflight;
+mod sources;
 mod store;
 #[cfg(test)]
 mod test;
 
 use serde::Serialize;
 use std::{convert::Infallible, net::SocketAddr};
-use tableland_vm::GasReport;
-use warp::{http::StatusCode, Filter, Rejection, Reply};
+use tableland_vm::{GasReport, ValidationReport};
+use warp::{
+    http::header::{HeaderMap, HeaderValue, RETRY_AFTER},
+    http::StatusCode,
+    Filter, Rejection, Reply,
+};
 
-use crate::config::Config;
+use crate::auth::{with_admin, with_api_key};
+use crate::blocks::BlockTracker;
+use crate::config::{Config, LinksConfig};
+use crate::db::Db;
+use crate::env::{Env, MAX_VALUE_LENGTH};
 use crate::errors::{StoreError, WorkerError};
-use crate::handlers::{add_runtime, invoke_runtime};
+use crate::events::{Events, EVENTS_SIGNATURE_HEADER};
+use crate::handlers::{
+    add_runtime, delete_env, get_alias, get_alias_stats, get_info, get_module, get_usage,
+    import_car, ingest_events, invoke_runtime, list_aliases, list_env, remove_runtime,
+    rollback_alias, set_alias, set_env, table_mutations, FUNCTION_CID_HEADER,
+};
+use crate::health::{healthz, readyz, Health};
+use crate::ledger::{Ledger, UsageQuery};
+use crate::meta::with_meta;
+use crate::moderation::Moderation;
+use crate::owners::Owners;
+use crate::poller::Poller;
+use crate::ratelimit::{with_rate_limit, RateLimiter};
+use crate::registry::{with_registry, with_target, Registry};
+use crate::render::Renderer;
+use crate::response_cache::ResponseCache;
+use crate::signature::{with_caller, Verifier};
 use crate::store::Store;
 
+const MAX_EVENTS_BODY_LENGTH: u64 = 1024 * 1024;
+
 #[tokio::main]
 async fn main() {
     let config: Config = confy::load("tableland_worker", Some("config")).unwrap();
-    let store = Store::new(config.clone());
+    limits::check_config(&config.limits).expect("Invalid limits config");
+    ratelimit::check_config(&config.rate_limit).expect("Invalid rate limit config");
+    let db = Db::open(config.db.path.as_str()).expect("Unable to open database");
+    let owners = Owners::new(db.clone());
+    let moderation = Moderation::open(config.moderation.clone(), owners.clone())
+        .expect("Unable to load moderation lists");
+    let env = Env::new(config.env.clone(), db.clone()).expect("Unable to load environment");
+    let response_cache = ResponseCache::new(config.response_cache.clone());
+    let blocks = BlockTracker::new(response_cache.clone());
+    let store = Store::new(config.clone())
+        .with_moderation(moderation.clone())
+        .with_env(env.clone())
+        .with_blocks(blocks.clone())
+        .with_response_cache(response_cache.clone());
+
+    // `tableland_worker import <file.car>` adds a module without starting the server.
+    if let (Some("import"), Some(path)) =
+        (std::env::args().nth(1).as_deref(), std::env::args().nth(2))
+    {
+        let car = std::fs::read(&path).expect("Unable to read CAR file");
+        match store.import(car).await {
+            Ok((cid, _)) => println!("{}", cid),
+            Err(e) => {
+                eprintln!("error importing {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    tokio::spawn(moderation.watch());
+    let rate_limiter = RateLimiter::new(config.rate_limit.clone(), config.api_keys.keys.clone());
+    let ledger = Ledger::new(db.clone());
+    tokio::spawn(ledger.clone().watch());
+    let events = Events::new(
+        db.clone(),
+        store.clone(),
+        config.chain.id.clone() as u64,
+        config.events.secret.clone(),
+    );
+    if !config.poller.rpc_url.is_empty() {
+        let poller = Poller::new(
+            config.poller.clone(),
+            config.chain.id.clone() as u64,
+            db.clone(),
+            events.clone(),
+            blocks,
+        );
+        tokio::spawn(poller.run());
+    }
+    let registry = Registry::open(db)
+        .await
+        .expect("Unable to open function registry");
+    let verifier = Verifier::new(config.signature.clone());
+    let health = Health::new(&config);
 
     let add_runtime_route = warp::path!("v1" / "add" / String)
         .and(warp::post())
+        .and(warp::header::headers_cloned())
         .and(with_store(store.clone()))
+        .and(with_owners(owners.clone()))
         .and_then(add_runtime);
-    let invoke_runtime_route = warp::path!("v1" / "functions" / String / ..)
+    let remove_runtime_route = warp::path!("v1" / "remove" / String)
+        .and(warp::post())
+        .and(warp::header::headers_cloned())
+        .and(with_store(store.clone()))
+        .and(with_owners(owners.clone()))
+        .and_then(remove_runtime);
+    let import_car_route = warp::path!("v1" / "car")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(
+            config.ipfs.max_size.saturating_mul(2),
+        ))
+        .and(warp::header::headers_cloned())
+        .and(with_store(store.clone()))
+        .and(with_owners(owners.clone()))
+        .and(warp::body::bytes())
+        .and_then(import_car);
+    let ingest_events_route = warp::path!("v1" / "events")
+        .and(warp::post())
+        .and(warp::header::optional::<String>(EVENTS_SIGNATURE_HEADER))
+        .and(warp::body::content_length_limit(MAX_EVENTS_BODY_LENGTH))
+        .and(warp::body::bytes())
+        .and(with_events(events))
+        .and_then(ingest_events);
+    let get_module_route = warp::path!("v1" / "modules" / String)
+        .and(warp::get())
+        .and(with_store(store.clone()))
+        .and_then(get_module);
+    let get_info_route = warp::path!("v1" / "modules" / String / "info")
+        .and(warp::get())
+        .and(with_store(store.clone()))
+        .and(with_owners(owners))
+        .and_then(get_info);
+    let invoke_runtime_route = with_rate_limit(with_target(registry.clone()), rate_limiter)
+        .and(with_api_key(config.api_keys.clone(), ledger.clone()))
         .and(with_store(store.clone()))
+        .and(with_ledger(ledger.clone()))
         .and(warp::method())
         .and(warp::path::full())
         .and(
@@ -36,39 +176,194 @@ async fn main() {
                 .unify(),
         )
         .and(warp::header::headers_cloned())
-        .and(warp::body::bytes())
+        .and(with_caller(verifier))
+        .and(with_meta(
+            config.chain.id.clone(),
+            config.links.public_url.clone(),
+            config.rate_limit.trusted_proxies.clone(),
+        ))
+        .and(with_links(config.links.clone()))
+        .and(with_renderer(Renderer::new(config.png.clone())))
+        .and(with_response_cache(response_cache.clone()))
         .and_then(invoke_runtime);
 
-    let router = add_runtime_route
+    let usage_route = warp::path!("v1" / "admin" / "usage")
+        .and(warp::get())
+        .and(with_admin(config.admin.token.clone()))
+        .and(warp::query::<UsageQuery>())
+        .and(with_ledger(ledger.clone()))
+        .and_then(get_usage);
+
+    let list_aliases_route = warp::path!("v1" / "admin" / "aliases")
+        .and(warp::get())
+        .and(with_admin(config.admin.token.clone()))
+        .and(with_registry(registry.clone()))
+        .and_then(list_aliases);
+    let get_alias_route = warp::path!("v1" / "admin" / "aliases" / String)
+        .and(warp::get())
+        .and(with_admin(config.admin.token.clone()))
+        .and(with_registry(registry.clone()))
+        .and_then(get_alias);
+    let set_alias_route = warp::path!("v1" / "admin" / "aliases" / String)
+        .and(warp::put())
+        .and(with_admin(config.admin.token.clone()))
+        .and(warp::body::json())
+        .and(with_store(store.clone()))
+        .and(with_registry(registry.clone()))
+        .and_then(set_alias);
+    let alias_stats_route = warp::path!("v1" / "admin" / "aliases" / String / "stats")
+        .and(warp::get())
+        .and(with_admin(config.admin.token.clone()))
+        .and(warp::query::<UsageQuery>())
+        .and(with_registry(registry.clone()))
+        .and(with_ledger(ledger.clone()))
+        .and_then(get_alias_stats);
+    let rollback_alias_route = warp::path!("v1" / "admin" / "aliases" / String / "rollback")
+        .and(warp::post())
+        .and(with_admin(config.admin.token.clone()))
+        .and(warp::body::json())
+        .and(with_registry(registry.clone()))
+        .and_then(rollback_alias);
+
+    let table_mutations_route = warp::path!("v1" / "admin" / "tables" / "mutations")
+        .and(warp::post())
+        .and(with_admin(config.admin.token.clone()))
+        .and(warp::body::json())
+        .and(with_response_cache(response_cache.clone()))
+        .and_then(table_mutations);
+
+    let list_env_route = warp::path!("v1" / "admin" / "env" / String)
+        .and(warp::get())
+        .and(with_admin(config.admin.token.clone()))
+        .and(with_env(env.clone()))
+        .and_then(list_env);
+    let set_env_route = warp::path!("v1" / "admin" / "env" / String / String)
+        .and(warp::put())
+        .and(with_admin(config.admin.token.clone()))
+        .and(warp::body::content_length_limit(
+            MAX_VALUE_LENGTH as u64 * 2,
+        ))
+        .and(warp::body::json())
+        .and(with_env(env.clone()))
+        .and(with_response_cache(response_cache.clone()))
+        .and_then(set_env);
+    let delete_env_route = warp::path!("v1" / "admin" / "env" / String / String)
+        .and(warp::delete())
+        .and(with_admin(config.admin.token.clone()))
+        .and(with_env(env))
+        .and(with_response_cache(response_cache))
+        .and_then(delete_env);
+
+    let healthz_route = warp::path!("healthz").and(warp::get()).and_then(healthz);
+    let readyz_route = warp::path!("readyz")
+        .and(warp::get())
+        .and(with_health(health.clone()))
+        .and_then(readyz);
+
+    let router = healthz_route
+        .or(readyz_route)
+        .or(add_runtime_route)
+        .or(remove_runtime_route)
+        .or(import_car_route)
+        .or(ingest_events_route)
+        .or(get_module_route)
+        .or(get_info_route)
         .or(invoke_runtime_route)
+        .or(usage_route)
+        .or(list_aliases_route)
+        .or(get_alias_route)
+        .or(set_alias_route)
+        .or(rollback_alias_route)
+        .or(alias_stats_route)
+        .or(table_mutations_route)
+        .or(list_env_route)
+        .or(set_env_route)
+        .or(delete_env_route)
         .with(warp::cors().allow_any_origin())
         .recover(handle_rejection);
 
     let addr = format!("{}:{}", config.server.host, config.server.port);
     let saddr: SocketAddr = addr.parse().expect("Unable to parse server address");
+    tokio::spawn(async move {
+        match store.preload().await {
+            Ok(n) => println!("preloaded {} functions", n),
+            Err(e) => eprintln!("error preloading functions: {}", e),
+        }
+        health.set_started();
+    });
     println!("Server started at {}", addr);
     warp::serve(router).run(saddr).await;
 }
 
+fn with_env(env: Env) -> impl Filter<Extract = (Env,), Error = Infallible> + Clone {
+    warp::any().map(move || env.clone())
+}
+
+fn with_events(events: Events) -> impl Filter<Extract = (Events,), Error = Infallible> + Clone {
+    warp::any().map(move || events.clone())
+}
+
+fn with_owners(owners: Owners) -> impl Filter<Extract = (Owners,), Error = Infallible> + Clone {
+    warp::any().map(move || owners.clone())
+}
+
 fn with_store(store: Store) -> impl Filter<Extract = (Store,), Error = Infallible> + Clone {
     warp::any().map(move || store.clone())
 }
 
+fn with_health(health: Health) -> impl Filter<Extract = (Health,), Error = Infallible> + Clone {
+    warp::any().map(move || health.clone())
+}
+
+fn with_links(
+    links: LinksConfig,
+) -> impl Filter<Extract = (LinksConfig,), Error = Infallible> + Clone {
+    warp::any().map(move || links.clone())
+}
+
+fn with_renderer(
+    renderer: Renderer,
+) -> impl Filter<Extract = (Renderer,), Error = Infallible> + Clone {
+    warp::any().map(move || renderer.clone())
+}
+
+fn with_response_cache(
+    cache: ResponseCache,
+) -> impl Filter<Extract = (ResponseCache,), Error = Infallible> + Clone {
+    warp::any().map(move || cache.clone())
+}
+
+fn with_ledger(ledger: Ledger) -> impl Filter<Extract = (Ledger,), Error = Infallible> + Clone {
+    warp::any().map(move || ledger.clone())
+}
+
 #[derive(Serialize)]
 struct ErrorMessage {
     code: u16,
     message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     gas: Option<GasReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation: Option<ValidationReport>,
 }
 
 async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     let mut report: Option<GasReport> = None;
+    let mut validation: Option<ValidationReport> = None;
+    let mut reason: Option<&'static str> = None;
+    let mut headers = HeaderMap::new();
     let (code, message) = if err.is_not_found() {
         (StatusCode::NOT_FOUND, "Not Found".to_string())
     } else if let Some(e) = err.find::<WorkerError>() {
-        report = e.report.clone();
-        match e.error.clone() {
+        report = e.report;
+        if let Some(cid) = &e.cid {
+            if let Ok(v) = HeaderValue::from_str(cid) {
+                headers.insert(FUNCTION_CID_HEADER, v);
+            }
+        }
+        match &e.error {
             StoreError::Vm(e) => {
                 if e == "Ran out of gas during function execution" {
                     (StatusCode::PAYMENT_REQUIRED, e.to_string())
@@ -77,15 +372,46 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
                 }
             }
             StoreError::Func(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            StoreError::Car(_)
+            | StoreError::Alias(_)
+            | StoreError::Event(_)
+            | StoreError::Env(_) => (StatusCode::BAD_REQUEST, e.error.to_string()),
+            StoreError::NotFound(e) => (StatusCode::NOT_FOUND, e.to_string()),
+            StoreError::Conflict(_) => (StatusCode::CONFLICT, e.error.to_string()),
+            StoreError::Invalid(r) => {
+                validation = Some(r.as_ref().clone());
+                (StatusCode::UNPROCESSABLE_ENTITY, e.error.to_string())
+            }
             StoreError::PayloadTooLarge => (
                 StatusCode::PAYLOAD_TOO_LARGE,
                 "Payload too large".to_string(),
             ),
             StoreError::Ipfs(e) => (StatusCode::NOT_FOUND, e.to_string()),
-            StoreError::Cache(e) | StoreError::TaskJoin(e) => {
+            StoreError::Unauthorized(_) => (StatusCode::UNAUTHORIZED, e.error.to_string()),
+            StoreError::QuotaExceeded(_) => (StatusCode::PAYMENT_REQUIRED, e.error.to_string()),
+            StoreError::Forbidden { reason: r, .. } => {
+                reason = Some(*r);
+                (StatusCode::FORBIDDEN, e.error.to_string())
+            }
+            StoreError::Cache(e)
+            | StoreError::TaskJoin(e)
+            | StoreError::Db(e)
+            | StoreError::Moderation(e)
+            | StoreError::Secret(e) => {
                 eprintln!("internal error: {:?}", err);
                 (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
             }
+            StoreError::Overloaded(secs) => {
+                headers.insert(RETRY_AFTER, HeaderValue::from(*secs));
+                (StatusCode::SERVICE_UNAVAILABLE, e.error.to_string())
+            }
+            StoreError::RateLimited { limit, reset } => {
+                headers.insert("ratelimit-limit", HeaderValue::from(*limit));
+                headers.insert("ratelimit-remaining", HeaderValue::from(0));
+                headers.insert("ratelimit-reset", HeaderValue::from(*reset));
+                headers.insert(RETRY_AFTER, HeaderValue::from(*reset));
+                (StatusCode::TOO_MANY_REQUESTS, e.error.to_string())
+            }
         }
     } else {
         eprintln!("unhandled error: {:?}", err);
@@ -97,9 +423,13 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
 
     let json = warp::reply::json(&ErrorMessage {
         code: code.as_u16(),
-        message: message.into(),
+        message,
+        reason,
         gas: report,
+        validation,
     });
 
-    Ok(warp::reply::with_status(json, code))
+    let mut res = warp::reply::with_status(json, code).into_response();
+    res.headers_mut().extend(headers);
+    Ok(res)
 }
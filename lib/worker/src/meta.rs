@@ -0,0 +1,68 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tableland_client::{get_chain, ChainID};
+use tableland_std::Meta;
+use warp::{http::HeaderMap, Filter};
+
+use crate::ratelimit::client_ip;
+
+/// Response header carrying the ID the worker assigned to a request.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Extracts the details functions receive in `ctx.meta`. The CID is left
+/// empty until the target function is known.
+pub fn with_meta(
+    chain: ChainID,
+    public_url: String,
+    trusted_proxies: Vec<IpAddr>,
+) -> impl Filter<Extract = (Meta,), Error = Infallible> + Clone {
+    let chain_name = get_chain(chain.clone()).name;
+    let template = Arc::new(Meta {
+        chain_id: chain as u64,
+        chain_name,
+        base_url: Some(public_url).filter(|u| !u.is_empty()),
+        ..Meta::default()
+    });
+    let trusted_proxies = Arc::new(trusted_proxies);
+    warp::addr::remote()
+        .and(warp::header::headers_cloned())
+        .map(move |remote: Option<SocketAddr>, headers: HeaderMap| Meta {
+            request_id: hex::encode(rand::random::<[u8; 16]>()),
+            client_ip: client_ip(&trusted_proxies, remote, &headers).map(|ip| ip.to_string()),
+            ..template.as_ref().clone()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn with_meta_works() {
+        let filter = with_meta(
+            ChainID::Local,
+            "https://functions.example.com".to_string(),
+            vec!["10.0.0.1".parse().unwrap()],
+        );
+        let req = |remote: &str| {
+            warp::test::request()
+                .remote_addr(remote.parse().unwrap())
+                .header("x-forwarded-for", "3.3.3.3")
+        };
+
+        let meta = req("10.0.0.1:1234").filter(&filter).await.unwrap();
+        assert_eq!(meta.chain_id, 31337);
+        assert_eq!(meta.chain_name, "Local");
+        assert_eq!(meta.client_ip.as_deref(), Some("3.3.3.3"));
+        assert_eq!(
+            meta.base_url.as_deref(),
+            Some("https://functions.example.com")
+        );
+        assert_eq!(meta.request_id.len(), 32);
+
+        let other = req("5.5.5.5:1234").filter(&filter).await.unwrap();
+        assert_eq!(other.client_ip.as_deref(), Some("5.5.5.5"));
+        assert_ne!(other.request_id, meta.request_id);
+    }
+}
@@ -0,0 +1,342 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::config::ModerationConfig;
+use crate::errors::StoreError;
+use crate::owners::Owners;
+
+/// Why a function was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    DeniedCid,
+    DeniedChecksum,
+    DeniedOwner,
+    NotAllowlisted,
+}
+
+impl Reason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reason::DeniedCid => "denied_cid",
+            Reason::DeniedChecksum => "denied_checksum",
+            Reason::DeniedOwner => "denied_owner",
+            Reason::NotAllowlisted => "not_allowlisted",
+        }
+    }
+}
+
+/// Functions matched by CID, by the SHA-256 checksum of their Wasm bytes, or
+/// by owner address.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Lists {
+    pub cids: HashSet<String>,
+    pub checksums: HashSet<String>,
+    pub owners: HashSet<String>,
+}
+
+impl Lists {
+    fn normalize(mut self) -> Self {
+        self.checksums = self.checksums.iter().map(|c| c.to_lowercase()).collect();
+        self.owners = self.owners.iter().map(|o| o.to_lowercase()).collect();
+        self
+    }
+
+    fn matches(&self, cid: &str, checksum: Option<&str>, owner: Option<&str>) -> bool {
+        self.cids.contains(cid)
+            || checksum.map_or(false, |c| self.checksums.contains(c))
+            || owner.map_or(false, |o| self.owners.contains(o))
+    }
+}
+
+/// The operator's moderation lists. Denials always win. In allowlist-only
+/// mode, functions must also match the allowlist.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    pub allowlist_only: bool,
+    pub allow: Lists,
+    pub deny: Lists,
+}
+
+impl Policy {
+    pub fn parse(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        let policy: Policy = serde_json::from_slice(bytes)?;
+        Ok(Policy {
+            allow: policy.allow.normalize(),
+            deny: policy.deny.normalize(),
+            ..policy
+        })
+    }
+
+    pub fn check(
+        &self,
+        cid: &str,
+        checksum: Option<&str>,
+        owner: Option<&str>,
+    ) -> Result<(), Reason> {
+        if self.deny.cids.contains(cid) {
+            return Err(Reason::DeniedCid);
+        }
+        if checksum.map_or(false, |c| self.deny.checksums.contains(c)) {
+            return Err(Reason::DeniedChecksum);
+        }
+        if owner.map_or(false, |o| self.deny.owners.contains(o)) {
+            return Err(Reason::DeniedOwner);
+        }
+        if self.allowlist_only && !self.allow.matches(cid, checksum, owner) {
+            return Err(Reason::NotAllowlisted);
+        }
+        Ok(())
+    }
+
+    /// Whether checks depend on who owns the function.
+    fn uses_owners(&self) -> bool {
+        !self.deny.owners.is_empty() || (self.allowlist_only && !self.allow.owners.is_empty())
+    }
+}
+
+/// What's known about a function that has been checked.
+#[derive(Clone, Default)]
+struct Checked {
+    checksum: Option<String>,
+    /// `None` until the owner has been looked up.
+    owner: Option<Option<String>>,
+}
+
+/// Enforces the moderation lists in `moderation.path`, reloading them when the
+/// file changes. Without a file, every function is allowed.
+#[derive(Clone, Default)]
+pub struct Moderation {
+    config: ModerationConfig,
+    owners: Option<Owners>,
+    policy: Arc<RwLock<Arc<Policy>>>,
+    modified: Arc<RwLock<Option<SystemTime>>>,
+    /// Functions that have been checked, by CID.
+    checked: Arc<RwLock<HashMap<String, Checked>>>,
+}
+
+impl Moderation {
+    pub fn open(config: ModerationConfig, owners: Owners) -> Result<Self, StoreError> {
+        let moderation = Moderation {
+            config,
+            owners: Some(owners),
+            ..Moderation::default()
+        };
+        moderation.reload()?;
+        Ok(moderation)
+    }
+
+    /// Reloads the lists if the file changed since the last load. A file that
+    /// fails to parse leaves the current lists in place.
+    pub fn reload(&self) -> Result<bool, StoreError> {
+        if self.config.path.is_empty() {
+            return Ok(false);
+        }
+        let modified = std::fs::metadata(&self.config.path)
+            .and_then(|m| m.modified())
+            .map_err(|e| StoreError::moderation_err(format!("{}: {}", self.config.path, e)))?;
+        if *self.modified.read().unwrap() == Some(modified) {
+            return Ok(false);
+        }
+        let bytes = std::fs::read(&self.config.path)?;
+        let policy = Policy::parse(&bytes)
+            .map_err(|e| StoreError::moderation_err(format!("{}: {}", self.config.path, e)))?;
+        *self.policy.write().unwrap() = Arc::new(policy);
+        *self.modified.write().unwrap() = Some(modified);
+        Ok(true)
+    }
+
+    /// Polls the file for changes every `moderation.reload_interval` seconds.
+    pub async fn watch(self) {
+        if self.config.path.is_empty() || self.config.reload_interval == 0 {
+            return;
+        }
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.reload_interval));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            match self.reload() {
+                Ok(true) => println!("reloaded moderation lists"),
+                Ok(false) => {}
+                Err(e) => eprintln!("error reloading moderation lists: {}", e),
+            }
+        }
+    }
+
+    /// Checks whether a function may be stored or run. Pass the module when
+    /// it's at hand; otherwise the checksum from the last check is used. Pass
+    /// the owner when it's known but may not be recorded yet, e.g., while the
+    /// function is being added; otherwise it's looked up once and remembered
+    /// until [`Moderation::forget_owner`].
+    pub async fn check(
+        &self,
+        cid: &str,
+        module: Option<&[u8]>,
+        owner: Option<&str>,
+    ) -> Result<(), StoreError> {
+        let policy = self.policy.read().unwrap().clone();
+        let mut checked = self
+            .checked
+            .read()
+            .unwrap()
+            .get(cid)
+            .cloned()
+            .unwrap_or_default();
+        let mut changed = false;
+        if let Some(m) = module {
+            checked.checksum = Some(hex::encode(Sha256::digest(m)));
+            changed = true;
+        }
+        let lookup = owner.is_none() && policy.uses_owners() && checked.owner.is_none();
+        if let (Some(owners), true) = (&self.owners, lookup) {
+            checked.owner = Some(
+                owners
+                    .info(cid.to_string())
+                    .await?
+                    .and_then(|i| i.owner)
+                    .map(|o| o.to_lowercase()),
+            );
+            changed = true;
+        }
+        if changed {
+            self.checked
+                .write()
+                .unwrap()
+                .insert(cid.to_string(), checked.clone());
+        }
+        let owner = owner
+            .map(|o| o.to_lowercase())
+            .or_else(|| checked.owner.flatten());
+        let checksum = checked.checksum;
+        policy
+            .check(cid, checksum.as_deref(), owner.as_deref())
+            .map_err(|reason| {
+                println!("refused {}: {}", cid, reason.as_str());
+                StoreError::Forbidden {
+                    reason: reason.as_str(),
+                    cid: cid.to_string(),
+                }
+            })
+    }
+
+    pub fn has_checksum(&self, cid: &str) -> bool {
+        self.checked
+            .read()
+            .unwrap()
+            .get(cid)
+            .map_or(false, |c| c.checksum.is_some())
+    }
+
+    /// Drops what was recorded for a removed module.
+    pub fn forget(&self, cid: &str) {
+        self.checked.write().unwrap().remove(cid);
+    }
+
+    /// Drops the owner recorded for a function whose owner may have changed.
+    pub fn forget_owner(&self, cid: &str) {
+        if let Some(c) = self.checked.write().unwrap().get_mut(cid) {
+            c.owner = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+
+    #[test]
+    fn policy_works() {
+        let policy = Policy::parse(
+            br#"{
+                "deny": {"cids": ["bad"], "checksums": ["AB"], "owners": ["0xBAD"]},
+                "allow": {"cids": ["good"], "owners": ["0xabc"]}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(policy.check("bad", None, None), Err(Reason::DeniedCid));
+        assert_eq!(
+            policy.check("a", Some("ab"), None),
+            Err(Reason::DeniedChecksum)
+        );
+        assert_eq!(
+            policy.check("a", None, Some("0xbad")),
+            Err(Reason::DeniedOwner)
+        );
+        assert_eq!(policy.check("a", Some("cd"), Some("0xdef")), Ok(()));
+
+        let policy = Policy {
+            allowlist_only: true,
+            ..policy
+        };
+        assert_eq!(policy.check("a", None, None), Err(Reason::NotAllowlisted));
+        assert_eq!(policy.check("good", None, None), Ok(()));
+        assert_eq!(policy.check("a", None, Some("0xabc")), Ok(()));
+        // Denials win over the allowlist.
+        assert_eq!(
+            policy.check("good", Some("ab"), None),
+            Err(Reason::DeniedChecksum)
+        );
+    }
+
+    #[tokio::test]
+    async fn reload_works() {
+        let dir = std::env::temp_dir().join(format!("tbl-moderation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lists.json");
+        std::fs::write(&path, r#"{"deny": {"checksums": []}}"#).unwrap();
+
+        let owners = Owners::new(Db::open_in_memory().unwrap());
+        let moderation = Moderation::open(
+            ModerationConfig {
+                path: path.to_str().unwrap().to_string(),
+                reload_interval: 0,
+            },
+            owners.clone(),
+        )
+        .unwrap();
+        let checksum = hex::encode(Sha256::digest(b"module"));
+        moderation.check("a", Some(b"module"), None).await.unwrap();
+
+        std::fs::write(
+            &path,
+            format!(r#"{{"deny": {{"checksums": ["{}"]}}}}"#, checksum),
+        )
+        .unwrap();
+        filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(1, 0)).unwrap();
+        assert!(moderation.reload().unwrap());
+        assert!(!moderation.reload().unwrap());
+        // The checksum is remembered from the first check.
+        match moderation.check("a", None, None).await {
+            Err(StoreError::Forbidden { reason, .. }) => assert_eq!(reason, "denied_checksum"),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // Broken files keep the current lists.
+        std::fs::write(&path, "{").unwrap();
+        filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(2, 0)).unwrap();
+        assert!(moderation.reload().is_err());
+        assert!(moderation.check("a", None, None).await.is_err());
+        moderation.forget("a");
+        assert!(moderation.check("a", None, None).await.is_ok());
+
+        // Owners are remembered until they're forgotten.
+        std::fs::write(&path, r#"{"deny": {"owners": ["0xbad"]}}"#).unwrap();
+        filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(3, 0)).unwrap();
+        assert!(moderation.reload().unwrap());
+        assert!(moderation.check("b", None, None).await.is_ok());
+        owners.claim("b".into(), "0xBAD".into()).await.unwrap();
+        assert!(moderation.check("b", None, None).await.is_ok());
+        moderation.forget_owner("b");
+        match moderation.check("b", None, None).await {
+            Err(StoreError::Forbidden { reason, .. }) => assert_eq!(reason, "denied_owner"),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
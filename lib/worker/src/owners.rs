@@ -0,0 +1,335 @@
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use warp::http::HeaderMap;
+
+use crate::db::Db;
+use crate::errors::StoreError;
+use crate::signature::{recover_address, ADDRESS_HEADER, NONCE_HEADER, SIGNATURE_HEADER};
+
+/// Changes to a function that only its owner may make.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Add,
+    Remove,
+}
+
+impl Action {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Action::Add => "add",
+            Action::Remove => "remove",
+        }
+    }
+}
+
+/// An owner's EIP-191 signature over an action on a CID. Nonces are per
+/// address and must increase with every action.
+#[derive(Debug)]
+pub struct OwnerAuth {
+    pub address: String,
+    pub signature: String,
+    pub nonce: u64,
+}
+
+impl OwnerAuth {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, StoreError> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+                .ok_or_else(|| StoreError::unauthorized_err(format!("missing {} header", name)))
+        };
+        Ok(OwnerAuth {
+            address: header(ADDRESS_HEADER)?,
+            signature: header(SIGNATURE_HEADER)?,
+            nonce: header(NONCE_HEADER)?
+                .parse()
+                .map_err(|_| StoreError::unauthorized_err("invalid nonce"))?,
+        })
+    }
+}
+
+/// Returns the message an owner signs to perform `action` on `cid`.
+pub fn ownership_message(action: Action, cid: &str, nonce: u64) -> String {
+    format!("{}\n{}\n{}", action.as_str(), cid, nonce)
+}
+
+/// Who owns a function, and the chain event that registered it, if any.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct FunctionInfo {
+    pub cid: String,
+    pub owner: Option<String>,
+    pub chain_id: Option<u64>,
+    pub block_number: Option<u64>,
+    pub tx_hash: Option<String>,
+}
+
+/// Tracks function owners. Owners are set by registry events, or by the
+/// signed add that first stores a function.
+#[derive(Clone)]
+pub struct Owners {
+    db: Db,
+}
+
+impl Owners {
+    pub fn new(db: Db) -> Self {
+        Owners { db }
+    }
+
+    /// Checks that `auth` is a valid signature for `action` by the function's
+    /// owner, and uses up its nonce. Anyone may add a function that has no
+    /// owner and isn't `stored` yet, but functions without an owner can't
+    /// otherwise be changed. Returns the signer.
+    pub async fn authorize(
+        &self,
+        action: Action,
+        cid: String,
+        auth: OwnerAuth,
+        stored: bool,
+    ) -> Result<String, StoreError> {
+        let message = ownership_message(action, cid.as_str(), auth.nonce);
+        let signer = recover_address(message.as_bytes(), auth.signature.as_str())?;
+        if !signer.eq_ignore_ascii_case(&auth.address) {
+            return Err(StoreError::unauthorized_err("signer mismatch"));
+        }
+
+        let nonce = auth.nonce;
+        let address = signer.clone();
+        self.db
+            .call(move |c| {
+                let tx = c.transaction()?;
+                let owner: Option<String> = tx
+                    .query_row(
+                        "SELECT owner FROM functions WHERE cid = ?1",
+                        params![cid],
+                        |r| r.get(0),
+                    )
+                    .optional()?;
+                match (owner, action) {
+                    (Some(o), _) if !o.eq_ignore_ascii_case(&address) => {
+                        return Err(StoreError::unauthorized_err(format!(
+                            "{} isn't owned by {}",
+                            cid, address
+                        )))
+                    }
+                    (None, Action::Remove) => {
+                        return Err(StoreError::unauthorized_err(format!(
+                            "{} has no owner",
+                            cid
+                        )))
+                    }
+                    (None, Action::Add) if stored => {
+                        return Err(StoreError::unauthorized_err(format!(
+                            "{} has no owner and is already stored",
+                            cid
+                        )))
+                    }
+                    _ => {}
+                }
+
+                let last: Option<u64> = tx
+                    .query_row(
+                        "SELECT nonce FROM nonces WHERE address = ?1",
+                        params![address],
+                        |r| r.get(0),
+                    )
+                    .optional()?;
+                if last.map_or(false, |l| nonce <= l) {
+                    return Err(StoreError::unauthorized_err("nonce already used"));
+                }
+                tx.execute(
+                    "INSERT OR REPLACE INTO nonces (address, nonce) VALUES (?1, ?2)",
+                    params![address, nonce],
+                )?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await?;
+        Ok(signer)
+    }
+
+    /// Records the owner of a function that doesn't have one. Fails if someone
+    /// else got there first, e.g., with a concurrent add.
+    pub async fn claim(&self, cid: String, owner: String) -> Result<(), StoreError> {
+        self.db
+            .call(move |c| {
+                let tx = c.transaction()?;
+                let inserted = tx.execute(
+                    "INSERT OR IGNORE INTO functions (cid, owner) VALUES (?1, ?2)",
+                    params![cid, owner.to_lowercase()],
+                )?;
+                if inserted == 0 {
+                    let current: Option<String> = tx.query_row(
+                        "SELECT owner FROM functions WHERE cid = ?1",
+                        params![cid],
+                        |r| r.get(0),
+                    )?;
+                    if !current.map_or(false, |o| o.eq_ignore_ascii_case(&owner)) {
+                        return Err(StoreError::unauthorized_err(format!(
+                            "{} isn't owned by {}",
+                            cid, owner
+                        )));
+                    }
+                }
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn remove(&self, cid: String) -> Result<(), StoreError> {
+        self.db
+            .call(move |c| {
+                c.execute("DELETE FROM functions WHERE cid = ?1", params![cid])?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn info(&self, cid: String) -> Result<Option<FunctionInfo>, StoreError> {
+        self.db
+            .call(move |c| {
+                Ok(c.query_row(
+                    "SELECT cid, owner, chain_id, block_number, tx_hash
+                    FROM functions WHERE cid = ?1",
+                    params![cid],
+                    |r| {
+                        Ok(FunctionInfo {
+                            cid: r.get(0)?,
+                            owner: r.get(1)?,
+                            chain_id: r.get(2)?,
+                            block_number: r.get(3)?,
+                            tx_hash: r.get(4)?,
+                        })
+                    },
+                )
+                .optional()?)
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::signature::eip191_hash;
+    use k256::ecdsa::SigningKey;
+    use sha3::{Digest, Keccak256};
+
+    pub(crate) fn address(key: &SigningKey) -> String {
+        let point = key.verifying_key().to_encoded_point(false);
+        let hash = Keccak256::digest(&point.as_bytes()[1..]);
+        format!("0x{}", hex::encode(&hash[12..]))
+    }
+
+    pub(crate) fn auth(key: &SigningKey, action: Action, cid: &str, nonce: u64) -> OwnerAuth {
+        let message = ownership_message(action, cid, nonce);
+        let (sig, recovery_id) = key
+            .sign_prehash_recoverable(&eip191_hash(message.as_bytes()))
+            .unwrap();
+        let mut bytes = sig.to_vec();
+        bytes.push(recovery_id.to_byte() + 27);
+        OwnerAuth {
+            address: address(key).to_uppercase().replacen("0X", "0x", 1),
+            signature: format!("0x{}", hex::encode(bytes)),
+            nonce,
+        }
+    }
+
+    #[tokio::test]
+    async fn authorize_works() {
+        let owners = Owners::new(Db::open_in_memory().unwrap());
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        let bob = SigningKey::from_bytes(&[2u8; 32].into()).unwrap();
+
+        // Unowned functions can be claimed before they're stored, but not
+        // removed.
+        assert!(owners
+            .authorize(
+                Action::Remove,
+                "a".into(),
+                auth(&alice, Action::Remove, "a", 1),
+                true
+            )
+            .await
+            .is_err());
+        let signer = owners
+            .authorize(
+                Action::Add,
+                "a".into(),
+                auth(&alice, Action::Add, "a", 1),
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(signer, address(&alice));
+        owners.claim("a".into(), signer.clone()).await.unwrap();
+        // Claiming again only works for the owner.
+        owners.claim("a".into(), signer).await.unwrap();
+        assert!(owners.claim("a".into(), address(&bob)).await.is_err());
+        let info = owners.info("a".into()).await.unwrap().unwrap();
+        assert_eq!(info.owner, Some(address(&alice)));
+        assert_eq!(info.chain_id, None);
+        assert!(owners
+            .authorize(
+                Action::Add,
+                "b".into(),
+                auth(&bob, Action::Add, "b", 1),
+                true
+            )
+            .await
+            .is_err());
+
+        // Nonces can't be reused, or signed for another action or CID.
+        assert!(owners
+            .authorize(
+                Action::Add,
+                "a".into(),
+                auth(&alice, Action::Add, "a", 1),
+                true
+            )
+            .await
+            .is_err());
+        assert!(owners
+            .authorize(
+                Action::Remove,
+                "a".into(),
+                auth(&alice, Action::Add, "a", 2),
+                true
+            )
+            .await
+            .is_err());
+        assert!(owners
+            .authorize(
+                Action::Add,
+                "b".into(),
+                auth(&alice, Action::Add, "a", 2),
+                true
+            )
+            .await
+            .is_err());
+
+        // Only the owner can change the function.
+        assert!(owners
+            .authorize(
+                Action::Remove,
+                "a".into(),
+                auth(&bob, Action::Remove, "a", 1),
+                true
+            )
+            .await
+            .is_err());
+        owners
+            .authorize(
+                Action::Remove,
+                "a".into(),
+                auth(&alice, Action::Remove, "a", 5),
+                true,
+            )
+            .await
+            .unwrap();
+        owners.remove("a".into()).await.unwrap();
+        assert!(owners.info("a".into()).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,607 @@
+use reqwest::Client;
+use rusqlite::params;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+use std::time::Duration;
+use tableland_std::Block;
+
+use crate::blocks::BlockTracker;
+use crate::config::PollerConfig;
+use crate::db::Db;
+use crate::errors::StoreError;
+use crate::events::{Attempts, Event, EventKind, Events, MAX_ATTEMPTS};
+
+/// Registry contract events, as `(signature, kind)`. The owner is the first
+/// indexed argument and the CID is the only data argument.
+const EVENT_SIGNATURES: &[(&str, EventKind)] = &[
+    ("FunctionRegistered(address,string)", EventKind::Register),
+    ("FunctionUpdated(address,string)", EventKind::Update),
+    ("FunctionRemoved(address,string)", EventKind::Remove),
+];
+
+/// Number of scanned blocks remembered for finding where a reorg started.
+const MAX_SCANNED_BLOCKS: u64 = 256;
+
+/// Polls an EVM JSON-RPC endpoint for registry contract events and applies
+/// them once they're `poller.confirmations` blocks deep.
+///
+/// The hash of the last block of every scanned range is remembered. If one no
+/// longer matches the chain, events are rolled back to the last remembered
+/// block that still does, and scanning resumes from there.
+///
+/// Functions see the latest confirmed block, which is followed on every poll
+/// whether or not events have caught up with it.
+pub struct Poller {
+    config: PollerConfig,
+    chain_id: u64,
+    db: Db,
+    events: Events,
+    blocks: BlockTracker,
+    rpc: Rpc,
+    topics: Vec<(String, EventKind)>,
+    /// Failed attempts at decoding logs, by `{tx_hash}:{log_index}`.
+    attempts: Attempts,
+}
+
+impl Poller {
+    pub fn new(
+        config: PollerConfig,
+        chain_id: u64,
+        db: Db,
+        events: Events,
+        blocks: BlockTracker,
+    ) -> Self {
+        let topics = EVENT_SIGNATURES
+            .iter()
+            .map(|(sig, kind)| {
+                let hash = Keccak256::digest(sig.as_bytes());
+                (format!("0x{}", hex::encode(hash)), *kind)
+            })
+            .collect();
+        Poller {
+            rpc: Rpc::new(config.rpc_url.clone(), Duration::from_secs(config.timeout)),
+            config,
+            chain_id,
+            db,
+            events,
+            blocks,
+            topics,
+            attempts: Attempts::default(),
+        }
+    }
+
+    /// Polls every `poller.interval` seconds, forever.
+    pub async fn run(self) {
+        let interval = Duration::from_secs(self.config.interval);
+        loop {
+            match self.poll().await {
+                // Keep going while there are blocks to catch up on.
+                Ok(Some(_)) => continue,
+                Ok(None) => {}
+                Err(e) => eprintln!("error polling for events: {}", e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Scans the next range of confirmed blocks, returning the number of
+    /// events applied, or `None` if there were no new blocks to scan.
+    pub async fn poll(&self) -> Result<Option<usize>, StoreError> {
+        let head = self.rpc.block_number().await?;
+        let safe = head.saturating_sub(self.config.confirmations);
+        self.follow(safe).await?;
+
+        let from = match self.cursor().await? {
+            Some((number, hash)) => {
+                if self.rpc.block(number).await?.map(|b| b.hash) != Some(hash) {
+                    self.reorg().await?;
+                    return Ok(Some(0));
+                }
+                number + 1
+            }
+            None => self.config.start_block,
+        };
+        if from > safe {
+            return Ok(None);
+        }
+        let to = safe.min(from + self.config.max_block_range.max(1) - 1);
+        let block = self
+            .rpc
+            .block(to)
+            .await?
+            .ok_or_else(|| StoreError::event_err(format!("block {} not found", to)))?;
+
+        let filter = json!({
+            "fromBlock": format!("{:#x}", from),
+            "toBlock": format!("{:#x}", to),
+            "address": self.config.contract,
+            "topics": [self.topics.iter().map(|(t, _)| t).collect::<Vec<_>>()],
+        });
+        let logs: Vec<Log> = self.rpc.call("eth_getLogs", json!([filter])).await?;
+        // A log that can't be decoded fails the whole range rather than being
+        // checkpointed past and lost, until it's failed too many times.
+        let mut events = vec![];
+        for log in logs.iter().filter(|l| !l.removed) {
+            match self.decode(log) {
+                Ok(e) => events.push(e),
+                Err(e) => {
+                    let key = format!("{}:{}", log.transaction_hash, log.log_index);
+                    if !self.attempts.fail(key.as_str()) {
+                        return Err(StoreError::event_err(format!(
+                            "error decoding log in {}: {}",
+                            log.transaction_hash, e
+                        )));
+                    }
+                    eprintln!(
+                        "giving up on log in {} after {} attempts: {}",
+                        log.transaction_hash, MAX_ATTEMPTS, e
+                    );
+                }
+            }
+        }
+        let applied = self.events.apply(events).await?;
+        self.save(to, block.hash, quantity(&block.timestamp)?)
+            .await?;
+        Ok(Some(applied.applied))
+    }
+
+    /// Rolls events back to the last scanned block that's still on the chain.
+    async fn reorg(&self) -> Result<(), StoreError> {
+        let mut ancestor = self.config.start_block.saturating_sub(1);
+        for (number, hash) in self.scanned().await? {
+            if self.rpc.block(number).await?.map(|b| b.hash) == Some(hash) {
+                ancestor = number;
+                break;
+            }
+        }
+        println!("reorg detected, resuming from block {}", ancestor + 1);
+        self.events.rollback(ancestor).await?;
+
+        let chain_id = self.chain_id;
+        self.db
+            .call(move |c| {
+                c.execute(
+                    "DELETE FROM scanned_blocks WHERE chain_id = ?1 AND block_number > ?2",
+                    params![chain_id, ancestor],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Points the block tracker at the latest confirmed block.
+    async fn follow(&self, number: u64) -> Result<(), StoreError> {
+        if self.blocks.latest().map(|b| b.number) == Some(number) {
+            return Ok(());
+        }
+        if let Some(block) = self.rpc.block(number).await? {
+            self.blocks.set(Some(Block {
+                number,
+                timestamp: quantity(&block.timestamp)?,
+            }));
+        }
+        Ok(())
+    }
+
+    fn decode(&self, log: &Log) -> Result<Event, StoreError> {
+        let invalid = |msg: &str| StoreError::event_err(msg.to_string());
+        let kind = log
+            .topics
+            .first()
+            .and_then(|t| self.topics.iter().find(|(topic, _)| topic == t))
+            .map(|(_, k)| *k)
+            .ok_or_else(|| invalid("unknown event"))?;
+        let owner = log
+            .topics
+            .get(1)
+            .filter(|t| t.len() == 66)
+            .map(|t| format!("0x{}", &t[26..]))
+            .ok_or_else(|| invalid("missing owner"))?;
+        let data =
+            hex::decode(log.data.trim_start_matches("0x")).map_err(|_| invalid("invalid data"))?;
+        Ok(Event {
+            kind,
+            chain_id: self.chain_id,
+            block_number: quantity(&log.block_number)?,
+            tx_hash: log.transaction_hash.clone(),
+            log_index: quantity(&log.log_index)?,
+            owner,
+            cid: abi_string(&data).ok_or_else(|| invalid("invalid CID"))?,
+        })
+    }
+
+    /// Returns the last scanned block.
+    async fn cursor(&self) -> Result<Option<(u64, String)>, StoreError> {
+        Ok(self.scanned().await?.into_iter().next())
+    }
+
+    /// Returns remembered blocks, newest first.
+    async fn scanned(&self) -> Result<Vec<(u64, String)>, StoreError> {
+        let chain_id = self.chain_id;
+        self.db
+            .call(move |c| {
+                let rows = c
+                    .prepare(
+                        "SELECT block_number, hash FROM scanned_blocks
+                        WHERE chain_id = ?1 ORDER BY block_number DESC",
+                    )?
+                    .query_map(params![chain_id], |r| Ok((r.get(0)?, r.get(1)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await
+    }
+
+    async fn save(&self, number: u64, hash: String, timestamp: u64) -> Result<(), StoreError> {
+        let chain_id = self.chain_id;
+        self.db
+            .call(move |c| {
+                c.execute(
+                    "INSERT OR REPLACE INTO scanned_blocks (chain_id, block_number, hash, timestamp)
+                    VALUES (?1, ?2, ?3, ?4)",
+                    params![chain_id, number, hash, timestamp],
+                )?;
+                c.execute(
+                    "DELETE FROM scanned_blocks WHERE chain_id = ?1 AND block_number NOT IN (
+                        SELECT block_number FROM scanned_blocks WHERE chain_id = ?1
+                        ORDER BY block_number DESC LIMIT ?2
+                    )",
+                    params![chain_id, MAX_SCANNED_BLOCKS],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Log {
+    topics: Vec<String>,
+    data: String,
+    block_number: String,
+    transaction_hash: String,
+    log_index: String,
+    #[serde(default)]
+    removed: bool,
+}
+
+#[derive(Deserialize)]
+struct RpcBlock {
+    hash: String,
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    #[serde(default)]
+    result: Value,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    message: String,
+}
+
+/// A minimal EVM JSON-RPC client.
+struct Rpc {
+    client: Client,
+    url: String,
+}
+
+impl Rpc {
+    fn new(url: String, timeout: Duration) -> Self {
+        Rpc {
+            client: Client::builder().timeout(timeout).build().unwrap(),
+            url,
+        }
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, StoreError> {
+        let failed = |e: String| StoreError::event_err(format!("{} failed: {}", method, e));
+        let body = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        let res: RpcResponse = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| failed(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| failed(e.to_string()))?;
+        if let Some(e) = res.error {
+            return Err(failed(e.message));
+        }
+        serde_json::from_value(res.result).map_err(|e| failed(e.to_string()))
+    }
+
+    async fn block_number(&self) -> Result<u64, StoreError> {
+        quantity(&self.call::<String>("eth_blockNumber", json!([])).await?)
+    }
+
+    /// Returns a block, or `None` if the node doesn't have it.
+    async fn block(&self, number: u64) -> Result<Option<RpcBlock>, StoreError> {
+        self.call(
+            "eth_getBlockByNumber",
+            json!([format!("{:#x}", number), false]),
+        )
+        .await
+    }
+}
+
+/// Parses a hex-encoded JSON-RPC quantity, e.g., `0x1b`.
+fn quantity(s: &str) -> Result<u64, StoreError> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|_| StoreError::event_err(format!("invalid quantity {}", s)))
+}
+
+/// Decodes ABI-encoded data holding a single `string`.
+fn abi_string(data: &[u8]) -> Option<String> {
+    let word = |at: usize| -> Option<usize> {
+        let w = data.get(at..at.checked_add(32)?)?;
+        if w[..24].iter().any(|b| *b != 0) {
+            return None;
+        }
+        Some(u64::from_be_bytes(w[24..].try_into().ok()?) as usize)
+    };
+    let offset = word(0)?;
+    let len = word(offset)?;
+    let start = offset.checked_add(32)?;
+    let bytes = data.get(start..start.checked_add(len)?)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, SourceConfig};
+    use crate::store::Store;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use warp::Filter;
+
+    const EXAMPLE_JSON_WASM: &[u8] = include_bytes!("../testdata/json.wasm");
+    const OWNER: &str = "0x00000000000000000000000000000000000000000000000000000000000000aa";
+
+    /// A chain served by the stand-in node. Blocks from `fork_from` on have
+    /// hashes that change every time the chain forks.
+    #[derive(Default)]
+    struct Chain {
+        head: u64,
+        fork_from: u64,
+        forks: u64,
+        /// `(block, topic, cid)`
+        logs: Vec<(u64, String, String)>,
+    }
+
+    impl Chain {
+        fn hash(&self, n: u64) -> String {
+            let fork = if n >= self.fork_from { self.forks } else { 0 };
+            format!("0x{:032x}{:032x}", fork, n)
+        }
+    }
+
+    fn abi_encode(s: &str) -> String {
+        let mut data = vec![0u8; 64];
+        data[31] = 32;
+        data[63] = s.len() as u8;
+        data.extend(s.as_bytes());
+        data.resize(64 + (s.len() + 31) / 32 * 32, 0);
+        format!("0x{}", hex::encode(data))
+    }
+
+    fn quantity_param(v: &Value) -> u64 {
+        quantity(v.as_str().unwrap()).unwrap()
+    }
+
+    /// Serves `eth_blockNumber`, `eth_getBlockByNumber` and `eth_getLogs`.
+    fn serve(chain: Arc<Mutex<Chain>>) -> SocketAddr {
+        let rpc = warp::post().and(warp::body::json()).map(move |req: Value| {
+            let chain = chain.lock().unwrap();
+            let params = &req["params"];
+            let result = match req["method"].as_str().unwrap() {
+                "eth_blockNumber" => json!(format!("{:#x}", chain.head)),
+                "eth_getBlockByNumber" => {
+                    let n = quantity_param(&params[0]);
+                    match n <= chain.head {
+                        true => json!({
+                            "hash": chain.hash(n),
+                            "timestamp": format!("{:#x}", 1_700_000_000 + n * 12),
+                        }),
+                        false => Value::Null,
+                    }
+                }
+                "eth_getLogs" => {
+                    let from = quantity_param(&params[0]["fromBlock"]);
+                    let to = quantity_param(&params[0]["toBlock"]);
+                    let logs: Vec<Value> = chain
+                        .logs
+                        .iter()
+                        .filter(|(n, _, _)| *n >= from && *n <= to)
+                        .map(|(n, topic, cid)| {
+                            json!({
+                                "topics": [topic, OWNER],
+                                "data": abi_encode(cid),
+                                "blockNumber": format!("{:#x}", n),
+                                "transactionHash": format!("0x{}{}", chain.hash(*n), cid),
+                                "logIndex": "0x0",
+                            })
+                        })
+                        .collect();
+                    json!(logs)
+                }
+                m => panic!("unexpected method {}", m),
+            };
+            warp::reply::json(&json!({"jsonrpc": "2.0", "id": 1, "result": result}))
+        });
+        let (addr, server) = warp::serve(rpc).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn owners(db: &Db) -> Vec<(String, String)> {
+        db.call(|c| {
+            let rows = c
+                .prepare("SELECT cid, owner FROM functions ORDER BY cid")?
+                .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn abi_string_works() {
+        let data = hex::decode(abi_encode("bafkreiabc").trim_start_matches("0x")).unwrap();
+        assert_eq!(abi_string(&data).as_deref(), Some("bafkreiabc"));
+        assert_eq!(abi_string(&data[..70]), None);
+        assert_eq!(abi_string(&[0u8; 16]), None);
+    }
+
+    #[tokio::test]
+    async fn poll_follows_chain_through_reorgs() {
+        let dir = std::env::temp_dir().join(format!("tbl-poller-{}", std::process::id()));
+        let (cache, source) = (dir.join("cache"), dir.join("source"));
+        std::fs::create_dir_all(&cache).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        for cid in ["a", "b", "c"] {
+            std::fs::write(source.join(format!("{}.wasm", cid)), EXAMPLE_JSON_WASM).unwrap();
+        }
+        let mut config = Config::default();
+        config.cache.directory = cache.to_str().unwrap().to_string();
+        config.ipfs.sources = vec![SourceConfig::Directory {
+            path: source.to_str().unwrap().to_string(),
+        }];
+        config.ipfs.allow_unverified = true;
+        let store = Store::new(config);
+
+        let chain = Arc::new(Mutex::new(Chain::default()));
+        let addr = serve(chain.clone());
+        let db = Db::open_in_memory().unwrap();
+        let events = Events::new(db.clone(), store.clone(), 31337, String::new());
+        let blocks = BlockTracker::default();
+        let poller = Poller::new(
+            PollerConfig {
+                rpc_url: format!("http://{}", addr),
+                contract: "0x0000000000000000000000000000000000000001".to_string(),
+                start_block: 1,
+                confirmations: 2,
+                max_block_range: 10,
+                ..Default::default()
+            },
+            31337,
+            db.clone(),
+            events.clone(),
+            blocks.clone(),
+        );
+        let (register, remove) = (poller.topics[0].0.clone(), poller.topics[2].0.clone());
+        let has = |cid: &str| {
+            let store = store.clone();
+            let cid = cid.to_string();
+            async move { store.module(cid).await.is_ok() }
+        };
+
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.head = 20;
+            chain.logs = vec![
+                (5, register.clone(), "a".to_string()),
+                (12, register.clone(), "c".to_string()),
+                // Not confirmed yet.
+                (19, remove.clone(), "a".to_string()),
+            ];
+        }
+        assert_eq!(poller.poll().await.unwrap(), Some(1));
+        // Functions see the latest confirmed block while events catch up.
+        assert_eq!(poller.cursor().await.unwrap().unwrap().0, 10);
+        assert_eq!(blocks.latest().unwrap().number, 18);
+        assert_eq!(poller.poll().await.unwrap(), Some(1));
+        assert_eq!(poller.poll().await.unwrap(), None);
+        assert!(has("a").await && has("c").await);
+        assert_eq!(poller.cursor().await.unwrap().unwrap().0, 18);
+        assert_eq!(
+            blocks.latest(),
+            Some(Block {
+                number: 18,
+                timestamp: 1_700_000_216,
+            })
+        );
+        assert_eq!(events.last_block().await.unwrap(), Some(12));
+
+        // Blocks from 11 on are replaced: "c" moves out, "b" moves in.
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.fork_from = 11;
+            chain.forks = 1;
+            chain.logs = vec![
+                (5, register.clone(), "a".to_string()),
+                (13, register.clone(), "b".to_string()),
+            ];
+        }
+        assert_eq!(poller.poll().await.unwrap(), Some(0));
+        assert!(!has("c").await);
+        assert_eq!(poller.cursor().await.unwrap().unwrap().0, 10);
+        assert_eq!(blocks.latest().unwrap().number, 18);
+        assert_eq!(events.last_block().await.unwrap(), Some(10));
+        assert_eq!(poller.poll().await.unwrap(), Some(1));
+        assert!(has("a").await && has("b").await);
+        assert_eq!(
+            owners(&db).await,
+            vec![
+                ("a".to_string(), format!("0x{}", &OWNER[26..])),
+                ("b".to_string(), format!("0x{}", &OWNER[26..])),
+            ]
+        );
+
+        // A reorg past every remembered block starts over.
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.fork_from = 0;
+            chain.forks = 2;
+            chain.logs = vec![];
+        }
+        assert_eq!(poller.poll().await.unwrap(), Some(0));
+        assert!(poller.cursor().await.unwrap().is_none());
+        assert!(!has("a").await && !has("b").await);
+        assert!(owners(&db).await.is_empty());
+
+        // A log that can't be decoded fails the range until it's given up
+        // on, but the latest block still moves.
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.head = 30;
+            chain.logs = vec![(3, "0x01".to_string(), "a".to_string())];
+        }
+        for _ in 1..MAX_ATTEMPTS {
+            assert!(poller.poll().await.is_err());
+            assert!(poller.cursor().await.unwrap().is_none());
+        }
+        assert_eq!(blocks.latest().unwrap().number, 28);
+        assert_eq!(poller.poll().await.unwrap(), Some(0));
+        assert_eq!(poller.cursor().await.unwrap().unwrap().0, 10);
+
+        // So does an event for a module that's never fetchable.
+        chain.lock().unwrap().logs = vec![
+            (12, register.clone(), "never".to_string()),
+            (13, register.clone(), "b".to_string()),
+        ];
+        for _ in 1..MAX_ATTEMPTS {
+            assert!(poller.poll().await.is_err());
+            assert_eq!(poller.cursor().await.unwrap().unwrap().0, 10);
+        }
+        assert_eq!(poller.poll().await.unwrap(), Some(1));
+        assert_eq!(poller.cursor().await.unwrap().unwrap().0, 20);
+        assert!(!has("never").await && has("b").await);
+        assert_eq!(events.last_block().await.unwrap(), Some(13));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
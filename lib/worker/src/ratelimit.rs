@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use warp::{http::HeaderMap, Filter, Rejection};
+
+use crate::auth::{find_key, API_KEY_HEADER};
+use crate::config::{ApiKey, RateLimit, RateLimitConfig, RateLimitKey};
+use crate::errors::{StoreError, WorkerError};
+use crate::evict::evict_oldest;
+use crate::registry::Target;
+
+/// Max number of buckets tracked. Once reached, the least recently used half
+/// is dropped.
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+/// Token-bucket rate limiter keyed by client and function.
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: Arc<RateLimitConfig>,
+    /// Known API keys. Requests are only limited by key when it's one of these.
+    keys: Arc<Vec<ApiKey>>,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.burst as f64,
+            limit,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.updated = now;
+    }
+
+    /// Takes a token, or returns the number of seconds until one is available.
+    /// Limits are checked by [`check_config`] to refill at a positive rate.
+    fn take(&mut self, now: Instant) -> Result<(), u64> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - self.tokens) / self.limit.per_second).ceil() as u64)
+        }
+    }
+}
+
+/// Checks that every limit refills. A bucket that never refills would lock
+/// its clients out for as long as it's tracked.
+pub fn check_config(config: &RateLimitConfig) -> Result<(), String> {
+    let invalid = |limit: &RateLimit| !(limit.per_second > 0.0 && limit.per_second.is_finite());
+    let default = RateLimit {
+        burst: config.burst,
+        per_second: config.per_second,
+    };
+    if invalid(&default) {
+        return Err("rate_limit.per_second must be greater than 0".to_string());
+    }
+    if let Some(cid) = config
+        .functions
+        .iter()
+        .find(|(_, l)| invalid(l))
+        .map(|(c, _)| c)
+    {
+        return Err(format!(
+            "rate_limit.functions.{}.per_second must be greater than 0",
+            cid
+        ));
+    }
+    Ok(())
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, keys: Vec<ApiKey>) -> Self {
+        RateLimiter {
+            config: Arc::new(config),
+            keys: Arc::new(keys),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Counts a request against the limit for `cid`.
+    pub fn check(
+        &self,
+        cid: &str,
+        remote: Option<SocketAddr>,
+        headers: &HeaderMap,
+    ) -> Result<(), StoreError> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+
+        let limit = self
+            .config
+            .functions
+            .get(cid)
+            .copied()
+            .unwrap_or(RateLimit {
+                burst: self.config.burst,
+                per_second: self.config.per_second,
+            });
+        let key = match self.config.key {
+            RateLimitKey::Cid => cid.to_string(),
+            RateLimitKey::Ip => format!("{}:{}", self.client_ip(remote, headers), cid),
+            // Unknown keys are limited by address, so made-up keys don't get
+            // fresh buckets.
+            RateLimitKey::ApiKey => match headers
+                .get(API_KEY_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|k| find_key(&self.keys, k))
+            {
+                Some(k) => format!("key:{}:{}", k.name, cid),
+                None => format!("{}:{}", self.client_ip(remote, headers), cid),
+            },
+        };
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(&key) {
+            evict_oldest(&mut buckets, MAX_TRACKED_BUCKETS, |b| b.updated);
+        }
+        buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::new(limit, now))
+            .take(now)
+            .map_err(|reset| StoreError::RateLimited {
+                limit: limit.burst,
+                reset,
+            })
+    }
+
+    fn client_ip(&self, remote: Option<SocketAddr>, headers: &HeaderMap) -> String {
+        client_ip(&self.config.trusted_proxies, remote, headers)
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+/// Resolves the client's address. `X-Forwarded-For` is only honored when the
+/// request comes from a trusted proxy, in which case the right-most untrusted
+/// hop is used.
+pub fn client_ip(
+    trusted_proxies: &[IpAddr],
+    remote: Option<SocketAddr>,
+    headers: &HeaderMap,
+) -> Option<IpAddr> {
+    let remote = remote?.ip();
+    if !trusted_proxies.contains(&remote) {
+        return Some(remote);
+    }
+
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|v| v.trim().parse().ok())
+        .collect();
+    forwarded
+        .into_iter()
+        .rev()
+        .find(|ip| !trusted_proxies.contains(ip))
+        .or(Some(remote))
+}
+
+/// Rate limits requests to the function extracted by `target`.
+pub fn with_rate_limit<F>(
+    target: F,
+    limiter: RateLimiter,
+) -> impl Filter<Extract = (Target,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (Target,), Error = Rejection> + Clone + Send + Sync + 'static,
+{
+    target
+        .and(warp::addr::remote())
+        .and(warp::header::headers_cloned())
+        .and(with_limiter(limiter))
+        .and_then(check_rate_limit)
+}
+
+fn with_limiter(
+    limiter: RateLimiter,
+) -> impl Filter<Extract = (RateLimiter,), Error = Infallible> + Clone {
+    warp::any().map(move || limiter.clone())
+}
+
+async fn check_rate_limit(
+    target: Target,
+    remote: Option<SocketAddr>,
+    headers: HeaderMap,
+    limiter: RateLimiter,
+) -> Result<Target, Rejection> {
+    limiter
+        .check(target.cid.as_str(), remote, &headers)
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None)))?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(key: RateLimitKey) -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: true,
+            key,
+            burst: 2,
+            per_second: 1.0,
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+            functions: HashMap::from([(
+                "hot".to_string(),
+                RateLimit {
+                    burst: 1,
+                    per_second: 0.5,
+                },
+            )]),
+        }
+    }
+
+    fn addr(ip: &str) -> Option<SocketAddr> {
+        Some(SocketAddr::new(ip.parse().unwrap(), 1234))
+    }
+
+    #[test]
+    fn check_works() {
+        let limiter = RateLimiter::new(config(RateLimitKey::Ip), vec![]);
+        let headers = HeaderMap::new();
+        assert!(limiter.check("a", addr("1.1.1.1"), &headers).is_ok());
+        assert!(limiter.check("a", addr("1.1.1.1"), &headers).is_ok());
+        match limiter.check("a", addr("1.1.1.1"), &headers) {
+            Err(StoreError::RateLimited { limit, reset }) => {
+                assert_eq!(limit, 2);
+                assert_eq!(reset, 1);
+            }
+            _ => panic!("expected rate limit error"),
+        }
+        // Other clients and functions have their own buckets.
+        assert!(limiter.check("a", addr("2.2.2.2"), &headers).is_ok());
+        assert!(limiter.check("b", addr("1.1.1.1"), &headers).is_ok());
+    }
+
+    #[test]
+    fn check_uses_function_overrides() {
+        let limiter = RateLimiter::new(config(RateLimitKey::Cid), vec![]);
+        let headers = HeaderMap::new();
+        assert!(limiter.check("hot", addr("1.1.1.1"), &headers).is_ok());
+        match limiter.check("hot", addr("2.2.2.2"), &headers) {
+            Err(StoreError::RateLimited { limit, reset }) => {
+                assert_eq!(limit, 1);
+                assert_eq!(reset, 2);
+            }
+            _ => panic!("expected rate limit error"),
+        }
+    }
+
+    #[test]
+    fn check_by_api_key() {
+        let key = |name: &str| ApiKey {
+            name: name.to_string(),
+            key: format!("{}-secret", name),
+            gas_quota: 0,
+            period: 0,
+        };
+        let limiter = RateLimiter::new(config(RateLimitKey::ApiKey), vec![key("k1"), key("k2")]);
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, "k1-secret".parse().unwrap());
+        assert!(limiter.check("hot", addr("1.1.1.1"), &headers).is_ok());
+        assert!(limiter.check("hot", addr("2.2.2.2"), &headers).is_err());
+        headers.insert(API_KEY_HEADER, "k2-secret".parse().unwrap());
+        assert!(limiter.check("hot", addr("1.1.1.1"), &headers).is_ok());
+
+        // Made-up keys fall back to the client's address.
+        headers.insert(API_KEY_HEADER, "fake1".parse().unwrap());
+        assert!(limiter.check("hot", addr("3.3.3.3"), &headers).is_ok());
+        headers.insert(API_KEY_HEADER, "fake2".parse().unwrap());
+        assert!(limiter.check("hot", addr("3.3.3.3"), &headers).is_err());
+    }
+
+    #[test]
+    fn check_config_works() {
+        assert!(check_config(&config(RateLimitKey::Ip)).is_ok());
+        let mut c = config(RateLimitKey::Ip);
+        c.per_second = 0.0;
+        assert!(check_config(&c).is_err());
+        let mut c = config(RateLimitKey::Ip);
+        c.functions.get_mut("hot").unwrap().per_second = 0.0;
+        assert!(check_config(&c).is_err());
+    }
+
+    #[test]
+    fn check_disabled() {
+        let limiter = RateLimiter::new(RateLimitConfig::default(), vec![]);
+        for _ in 0..1000 {
+            assert!(limiter.check("a", None, &HeaderMap::new()).is_ok());
+        }
+    }
+
+    #[test]
+    fn client_ip_works() {
+        let limiter = RateLimiter::new(config(RateLimitKey::Ip), vec![]);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "3.3.3.3, 4.4.4.4".parse().unwrap());
+
+        // Untrusted peers can't spoof their address.
+        assert_eq!(limiter.client_ip(addr("5.5.5.5"), &headers), "5.5.5.5");
+        assert_eq!(limiter.client_ip(addr("10.0.0.1"), &headers), "4.4.4.4");
+
+        headers.insert("x-forwarded-for", "3.3.3.3, 10.0.0.1".parse().unwrap());
+        assert_eq!(limiter.client_ip(addr("10.0.0.1"), &headers), "3.3.3.3");
+
+        headers.remove("x-forwarded-for");
+        assert_eq!(limiter.client_ip(addr("10.0.0.1"), &headers), "10.0.0.1");
+        assert_eq!(limiter.client_ip(None, &headers), "unknown");
+    }
+
+    #[tokio::test]
+    async fn with_rate_limit_works() {
+        let limiter = RateLimiter::new(config(RateLimitKey::Ip), vec![]);
+        let target = warp::path!("v1" / "functions" / String / ..).map(|cid: String| Target {
+            base: format!("/v1/functions/{}", cid),
+            cid,
+        });
+        let filter = with_rate_limit(target, limiter);
+        let req = || {
+            warp::test::request()
+                .path("/v1/functions/hot/foo")
+                .remote_addr("1.1.1.1:1234".parse().unwrap())
+        };
+        assert_eq!(req().filter(&filter).await.unwrap().cid, "hot");
+        let err = req().filter(&filter).await.unwrap_err();
+        assert!(err.find::<WorkerError>().is_some());
+    }
+}
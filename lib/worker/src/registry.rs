@@ -0,0 +1,551 @@
+use rand::Rng;
+use rusqlite::{params, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use warp::{http::header::COOKIE, http::HeaderMap, Filter, Rejection};
+
+use crate::db::Db;
+use crate::errors::{StoreError, WorkerError};
+use crate::ledger::now;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Header used to pin a client to the same CID across requests to a name.
+pub const STICKY_HEADER: &str = "x-tbl-sticky";
+/// Cookie used like [`STICKY_HEADER`] when the header isn't set.
+pub const STICKY_COOKIE: &str = "tbl_sticky";
+
+/// Maps human-readable names to function CIDs. Every change adds a version to
+/// the name's history, so old targets can be restored.
+#[derive(Clone)]
+pub struct Registry {
+    db: Db,
+    /// Current route for each name, kept in sync with the database.
+    current: Arc<RwLock<HashMap<String, Route>>>,
+}
+
+/// One version of a name.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Alias {
+    pub name: String,
+    pub cid: String,
+    pub version: u64,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub splits: Vec<Split>,
+}
+
+/// A share of a name's traffic sent to a CID other than its primary one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Split {
+    pub cid: String,
+    /// Percent of requests.
+    pub weight: u8,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetAlias {
+    pub cid: String,
+    /// When set, the update only succeeds if this is the current version.
+    pub version: Option<u64>,
+    /// Traffic to divert from `cid`, e.g., to a canary build.
+    #[serde(default)]
+    pub splits: Vec<Split>,
+}
+
+/// Where a name's requests go. The primary CID gets whatever the splits
+/// don't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub cid: String,
+    pub splits: Vec<Split>,
+}
+
+impl Route {
+    /// Picks a CID for a request. Requests with the same sticky key always get
+    /// the same CID; others are assigned at random.
+    pub fn pick(&self, name: &str, sticky: Option<&str>) -> &str {
+        if self.splits.is_empty() {
+            return self.cid.as_str();
+        }
+        let bucket = match sticky {
+            Some(key) => {
+                // Hash with the name so a client lands in independent buckets
+                // across names.
+                let hash = Sha256::digest(format!("{}/{}", name, key).as_bytes());
+                u64::from_be_bytes(hash[..8].try_into().unwrap()) % 100
+            }
+            None => rand::thread_rng().gen_range(0..100),
+        };
+        let mut acc = 0;
+        for split in self.splits.iter() {
+            acc += split.weight as u64;
+            if bucket < acc {
+                return split.cid.as_str();
+            }
+        }
+        self.cid.as_str()
+    }
+
+    /// Returns every CID the route may pick.
+    pub fn cids(&self) -> Vec<String> {
+        let mut cids = vec![self.cid.clone()];
+        cids.extend(self.splits.iter().map(|s| s.cid.clone()));
+        cids
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Rollback {
+    /// Version to restore. Defaults to the one before the current version.
+    pub version: Option<u64>,
+}
+
+/// The function a request is routed to, and the path prefix that addressed it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub cid: String,
+    pub base: String,
+}
+
+impl Registry {
+    pub async fn open(db: Db) -> Result<Self, StoreError> {
+        let current = db
+            .call(|c| {
+                let mut stmt = c.prepare(
+                    "SELECT name, cid, version, created_at, splits FROM aliases a WHERE version =
+                    (SELECT MAX(version) FROM aliases WHERE name = a.name)",
+                )?;
+                let rows = stmt.query_map([], |r| {
+                    let alias = row_to_alias(r)?;
+                    Ok((alias.name.clone(), alias.route()))
+                })?;
+                Ok(rows.collect::<Result<HashMap<String, Route>, _>>()?)
+            })
+            .await?;
+        Ok(Registry {
+            db,
+            current: Arc::new(RwLock::new(current)),
+        })
+    }
+
+    /// Returns the current route for a name.
+    pub fn resolve(&self, name: &str) -> Option<Route> {
+        self.current.read().unwrap().get(name).cloned()
+    }
+
+    /// Points a name at a CID, creating the name if needed.
+    pub async fn set(&self, name: String, req: SetAlias) -> Result<Alias, StoreError> {
+        check_alias(name.as_str(), &req)?;
+        let current = self.current.clone();
+        self.db
+            .call(move |c| {
+                let tx = c.transaction()?;
+                let latest = latest(&tx, name.as_str())?;
+                if let Some(expected) = req.version {
+                    let version = latest.as_ref().map_or(0, |a| a.version);
+                    if version != expected {
+                        return Err(StoreError::Conflict(format!(
+                            "{} is at version {}, not {}",
+                            name, version, expected
+                        )));
+                    }
+                }
+                let route = Route {
+                    cid: req.cid,
+                    splits: req.splits,
+                };
+                let alias = insert(tx, name, route, latest.map_or(0, |a| a.version))?;
+                current
+                    .write()
+                    .unwrap()
+                    .insert(alias.name.clone(), alias.route());
+                Ok(alias)
+            })
+            .await
+    }
+
+    /// Points a name back at the CID of an earlier version. This adds a new
+    /// version, so rollbacks can be undone.
+    pub async fn rollback(&self, name: String, req: Rollback) -> Result<Alias, StoreError> {
+        let current = self.current.clone();
+        self.db
+            .call(move |c| {
+                let tx = c.transaction()?;
+                let latest = latest(&tx, name.as_str())?
+                    .ok_or_else(|| StoreError::NotFound(format!("alias {} not found", name)))?;
+                let target = req.version.unwrap_or(latest.version.saturating_sub(1));
+                if target == latest.version {
+                    return Err(StoreError::alias_err(format!(
+                        "{} is already at version {}",
+                        name, target
+                    )));
+                }
+                let previous = tx
+                    .query_row(
+                        "SELECT name, cid, version, created_at, splits FROM aliases
+                        WHERE name = ?1 AND version = ?2",
+                        params![name, target as i64],
+                        row_to_alias,
+                    )
+                    .optional()?
+                    .ok_or_else(|| {
+                        StoreError::NotFound(format!("version {} of {} not found", target, name))
+                    })?;
+                let alias = insert(tx, name, previous.route(), latest.version)?;
+                current
+                    .write()
+                    .unwrap()
+                    .insert(alias.name.clone(), alias.route());
+                Ok(alias)
+            })
+            .await
+    }
+
+    /// Returns every version of a name, newest first.
+    pub async fn history(&self, name: String) -> Result<Vec<Alias>, StoreError> {
+        self.db
+            .call(move |c| {
+                let mut stmt = c.prepare(
+                    "SELECT name, cid, version, created_at, splits FROM aliases
+                    WHERE name = ?1 ORDER BY version DESC",
+                )?;
+                let rows = stmt.query_map(params![name], row_to_alias)?;
+                Ok(rows.collect::<Result<Vec<_>, _>>()?)
+            })
+            .await
+    }
+
+    /// Returns the current version of every name.
+    pub async fn list(&self) -> Result<Vec<Alias>, StoreError> {
+        self.db
+            .call(|c| {
+                let mut stmt = c.prepare(
+                    "SELECT name, cid, version, created_at, splits FROM aliases a WHERE version =
+                    (SELECT MAX(version) FROM aliases WHERE name = a.name) ORDER BY name",
+                )?;
+                let rows = stmt.query_map([], row_to_alias)?;
+                Ok(rows.collect::<Result<Vec<_>, _>>()?)
+            })
+            .await
+    }
+}
+
+fn latest(tx: &Transaction, name: &str) -> Result<Option<Alias>, StoreError> {
+    Ok(tx
+        .query_row(
+            "SELECT name, cid, version, created_at, splits FROM aliases
+            WHERE name = ?1 ORDER BY version DESC LIMIT 1",
+            params![name],
+            row_to_alias,
+        )
+        .optional()?)
+}
+
+impl Alias {
+    fn route(&self) -> Route {
+        Route {
+            cid: self.cid.clone(),
+            splits: self.splits.clone(),
+        }
+    }
+}
+
+fn insert(tx: Transaction, name: String, route: Route, after: u64) -> Result<Alias, StoreError> {
+    let alias = Alias {
+        name,
+        cid: route.cid,
+        version: after + 1,
+        created_at: now(),
+        splits: route.splits,
+    };
+    tx.execute(
+        "INSERT INTO aliases (name, version, cid, created_at, splits)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            alias.name,
+            alias.version as i64,
+            alias.cid,
+            alias.created_at as i64,
+            serde_json::to_string(&alias.splits).unwrap()
+        ],
+    )?;
+    tx.commit()?;
+    Ok(alias)
+}
+
+fn row_to_alias(r: &rusqlite::Row) -> rusqlite::Result<Alias> {
+    let splits = r.get::<_, String>(4)?;
+    Ok(Alias {
+        name: r.get(0)?,
+        cid: r.get(1)?,
+        version: r.get::<_, i64>(2)? as u64,
+        created_at: r.get::<_, i64>(3)? as u64,
+        splits: serde_json::from_str(splits.as_str()).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+    })
+}
+
+/// Names are lowercase alphanumerics and dashes, e.g., `players-api`.
+fn check_name(name: &str) -> Result<(), StoreError> {
+    if name.is_empty()
+        || name.len() > MAX_NAME_LENGTH
+        || name.starts_with('-')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(StoreError::alias_err(format!("invalid name {}", name)));
+    }
+    Ok(())
+}
+
+/// Checks a name and its split weights, without touching the registry.
+pub fn check_alias(name: &str, req: &SetAlias) -> Result<(), StoreError> {
+    check_name(name)?;
+    check_splits(&req.splits)
+}
+
+fn check_splits(splits: &[Split]) -> Result<(), StoreError> {
+    if splits.iter().map(|s| s.weight as u32).sum::<u32>() > 100 {
+        return Err(StoreError::alias_err("split weights must not exceed 100"));
+    }
+    Ok(())
+}
+
+/// Returns the request's sticky key from [`STICKY_HEADER`] or [`STICKY_COOKIE`].
+fn sticky_key(headers: &HeaderMap) -> Option<String> {
+    if let Some(v) = headers.get(STICKY_HEADER).and_then(|v| v.to_str().ok()) {
+        return Some(v.to_string());
+    }
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|c| {
+            let (k, v) = c.trim().split_once('=')?;
+            (k == STICKY_COOKIE).then(|| v.to_string())
+        })
+}
+
+/// Extracts the function targeted by `/v1/functions/{cid}/..` or
+/// `/v1/f/{name}/..`, resolving names with the registry.
+pub fn with_target(
+    registry: Registry,
+) -> impl Filter<Extract = (Target,), Error = Rejection> + Clone {
+    let by_cid = warp::path!("v1" / "functions" / String / ..).map(|cid: String| Target {
+        base: format!("/v1/functions/{}", cid),
+        cid,
+    });
+    let by_name = warp::path!("v1" / "f" / String / ..)
+        .and(warp::header::headers_cloned())
+        .and(with_registry(registry))
+        .and_then(resolve_target);
+    by_cid.or(by_name).unify()
+}
+
+pub fn with_registry(
+    registry: Registry,
+) -> impl Filter<Extract = (Registry,), Error = Infallible> + Clone {
+    warp::any().map(move || registry.clone())
+}
+
+async fn resolve_target(
+    name: String,
+    headers: HeaderMap,
+    registry: Registry,
+) -> Result<Target, Rejection> {
+    match registry.resolve(name.as_str()) {
+        Some(route) => Ok(Target {
+            cid: route
+                .pick(name.as_str(), sticky_key(&headers).as_deref())
+                .to_string(),
+            base: format!("/v1/f/{}", name),
+        }),
+        None => Err(warp::reject::custom(WorkerError::new(
+            StoreError::NotFound(format!("alias {} not found", name)),
+            None,
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(cid: &str, version: Option<u64>) -> SetAlias {
+        SetAlias {
+            cid: cid.to_string(),
+            version,
+            splits: vec![],
+        }
+    }
+
+    fn split(cid: &str, weight: u8) -> Split {
+        Split {
+            cid: cid.to_string(),
+            weight,
+        }
+    }
+
+    #[tokio::test]
+    async fn registry_works() {
+        let db = Db::open_in_memory().unwrap();
+        let registry = Registry::open(db.clone()).await.unwrap();
+        let name = || "players-api".to_string();
+
+        assert!(registry.resolve("players-api").is_none());
+        assert_eq!(
+            registry.set(name(), set("a", None)).await.unwrap().version,
+            1
+        );
+        assert_eq!(
+            registry
+                .set(name(), set("b", Some(1)))
+                .await
+                .unwrap()
+                .version,
+            2
+        );
+        assert!(matches!(
+            registry.set(name(), set("c", Some(1))).await,
+            Err(StoreError::Conflict(_))
+        ));
+        assert_eq!(registry.resolve("players-api").unwrap().cid, "b");
+
+        let alias = registry
+            .rollback(name(), Rollback::default())
+            .await
+            .unwrap();
+        assert_eq!((alias.cid.as_str(), alias.version), ("a", 3));
+        let alias = registry
+            .rollback(name(), Rollback { version: Some(2) })
+            .await
+            .unwrap();
+        assert_eq!((alias.cid.as_str(), alias.version), ("b", 4));
+        assert!(registry
+            .rollback(name(), Rollback { version: Some(9) })
+            .await
+            .is_err());
+        assert!(registry
+            .rollback("nope".to_string(), Rollback::default())
+            .await
+            .is_err());
+
+        let history = registry.history(name()).await.unwrap();
+        assert_eq!(
+            history.iter().map(|a| a.cid.as_str()).collect::<Vec<_>>(),
+            vec!["b", "a", "b", "a"]
+        );
+
+        // State survives a reload.
+        let registry = Registry::open(db).await.unwrap();
+        assert_eq!(registry.resolve("players-api").unwrap().cid, "b");
+        assert_eq!(registry.list().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn splits_work() {
+        let db = Db::open_in_memory().unwrap();
+        let registry = Registry::open(db.clone()).await.unwrap();
+        let name = || "api".to_string();
+
+        let mut req = set("a", None);
+        req.splits = vec![split("b", 60), split("c", 41)];
+        assert!(check_alias("api", &req).is_err());
+        assert!(registry.set(name(), req).await.is_err());
+
+        let mut req = set("a", None);
+        req.splits = vec![split("b", 25)];
+        registry.set(name(), req).await.unwrap();
+        registry.set(name(), set("c", None)).await.unwrap();
+        let alias = registry
+            .rollback(name(), Rollback::default())
+            .await
+            .unwrap();
+        assert_eq!(alias.splits, vec![split("b", 25)]);
+
+        let registry = Registry::open(db.clone()).await.unwrap();
+        let route = registry.resolve("api").unwrap();
+        assert_eq!(route.cids(), vec!["a", "b"]);
+
+        let mut picked = HashMap::new();
+        for _ in 0..1000 {
+            *picked.entry(route.pick("api", None)).or_insert(0) += 1;
+        }
+        assert!(picked["b"] > 150 && picked["b"] < 350, "{:?}", picked);
+
+        // Sticky keys always get the same CID.
+        for key in ["x", "y", "z"] {
+            let first = route.pick("api", Some(key));
+            assert!((0..20).all(|_| route.pick("api", Some(key)) == first));
+        }
+
+        // Corrupt splits are an error, not an empty list.
+        db.call(|c| Ok(c.execute("UPDATE aliases SET splits = 'nope'", [])?))
+            .await
+            .unwrap();
+        assert!(matches!(
+            registry.history(name()).await,
+            Err(StoreError::Db(_))
+        ));
+    }
+
+    #[test]
+    fn sticky_key_works() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(sticky_key(&headers), None);
+        headers.insert(COOKIE, "a=1; tbl_sticky=abc".parse().unwrap());
+        assert_eq!(sticky_key(&headers), Some("abc".to_string()));
+        headers.insert(STICKY_HEADER, "def".parse().unwrap());
+        assert_eq!(sticky_key(&headers), Some("def".to_string()));
+    }
+
+    #[tokio::test]
+    async fn set_rejects_invalid_names() {
+        let registry = Registry::open(Db::open_in_memory().unwrap()).await.unwrap();
+        for name in ["", "-a", "Players", "a/b", &"a".repeat(65)] {
+            assert!(registry
+                .set(name.to_string(), set("a", None))
+                .await
+                .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn with_target_works() {
+        let registry = Registry::open(Db::open_in_memory().unwrap()).await.unwrap();
+        registry
+            .set("api".to_string(), set("cid1", None))
+            .await
+            .unwrap();
+        let filter = with_target(registry);
+
+        let target = warp::test::request()
+            .path("/v1/functions/cid2/foo")
+            .filter(&filter)
+            .await
+            .unwrap();
+        assert_eq!(target.cid, "cid2");
+        assert_eq!(target.base, "/v1/functions/cid2");
+
+        let target = warp::test::request()
+            .path("/v1/f/api/foo")
+            .filter(&filter)
+            .await
+            .unwrap();
+        assert_eq!(target.cid, "cid1");
+        assert_eq!(target.base, "/v1/f/api");
+
+        assert!(warp::test::request()
+            .path("/v1/f/nope/foo")
+            .filter(&filter)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use resvg::{tiny_skia, usvg, usvg::fontdb};
+use std::sync::Arc;
+use tableland_vm::GasReport;
+use usvg::{TreeParsing, TreeTextToPath};
+use warp::http::header::{HeaderMap, ACCEPT};
+
+use crate::config::PngConfig;
+use crate::errors::StoreError;
+
+/// The only font available to SVGs, so output doesn't depend on the host.
+const FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+const FONT_FAMILY: &str = "DejaVu Sans";
+
+/// Rasterizes SVG function responses to PNG.
+#[derive(Clone)]
+pub struct Renderer {
+    config: PngConfig,
+    fontdb: Arc<fontdb::Database>,
+}
+
+impl Renderer {
+    pub fn new(config: PngConfig) -> Self {
+        let mut fontdb = fontdb::Database::new();
+        fontdb.load_font_data(FONT.to_vec());
+        fontdb.set_serif_family(FONT_FAMILY);
+        fontdb.set_sans_serif_family(FONT_FAMILY);
+        fontdb.set_monospace_family(FONT_FAMILY);
+        fontdb.set_cursive_family(FONT_FAMILY);
+        fontdb.set_fantasy_family(FONT_FAMILY);
+        Renderer {
+            config,
+            fontdb: Arc::new(fontdb),
+        }
+    }
+
+    /// Renders an SVG to a PNG `png.width` pixels wide, keeping its aspect
+    /// ratio. Rendering is charged to `report` before anything is drawn.
+    pub fn render(&self, svg: &[u8], report: &mut GasReport) -> Result<Vec<u8>, StoreError> {
+        let invalid = |e: String| StoreError::func_err(format!("invalid SVG: {}", e));
+        let mut opt = usvg::Options {
+            font_family: FONT_FAMILY.to_string(),
+            ..Default::default()
+        };
+        // Only embedded images are allowed; never read local files.
+        opt.image_href_resolver.resolve_string = Box::new(|_, _| None);
+
+        let mut tree = usvg::Tree::from_data(svg, &opt).map_err(|e| invalid(e.to_string()))?;
+        tree.convert_text(&self.fontdb);
+        let rtree = resvg::Tree::from_usvg(&tree);
+
+        let scale = self.config.width as f32 / rtree.size.width();
+        let height = (rtree.size.height() * scale).ceil() as u32;
+        if height == 0 || height > self.config.max_height {
+            return Err(invalid(format!("height {} is out of range", height)));
+        }
+        // Every filter primitive is another pass over the image.
+        let mut passes = 1;
+        tree.filters(|f| passes += f.primitives.len() as u64);
+        self.charge(report, self.config.width as u64 * height as u64, passes)?;
+
+        let mut pixmap = tiny_skia::Pixmap::new(self.config.width, height)
+            .ok_or_else(|| invalid("empty image".to_string()))?;
+        rtree.render(
+            tiny_skia::Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+        pixmap
+            .encode_png()
+            .map_err(|e| StoreError::func_err(e.to_string()))
+    }
+
+    /// Charges for drawing `pixels` `passes` times, failing if the function's
+    /// gas limit doesn't cover it.
+    fn charge(&self, report: &mut GasReport, pixels: u64, passes: u64) -> Result<(), StoreError> {
+        let cost = pixels
+            .saturating_mul(passes)
+            .saturating_mul(self.config.gas_per_pixel);
+        if cost > report.remaining {
+            report.used_externally += report.remaining;
+            report.remaining = 0;
+            return Err(StoreError::Vm(
+                "Ran out of gas during function execution".to_string(),
+            ));
+        }
+        report.used_externally += cost;
+        report.remaining -= cost;
+        Ok(())
+    }
+}
+
+/// Returns whether a client asked for PNG with `?format=png` or an `Accept`
+/// header listing `image/png`.
+pub fn wants_png(query: &str, headers: &HeaderMap) -> bool {
+    if query.split('&').any(|p| p == "format=png") {
+        return true;
+    }
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| {
+            t.split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .eq_ignore_ascii_case("image/png")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVG: &[u8] = br#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50">
+        <rect width="100" height="50" fill="red"/>
+        <text x="10" y="30" font-family="sans-serif">hi</text>
+    </svg>"#;
+
+    fn config() -> PngConfig {
+        PngConfig {
+            width: 200,
+            max_height: 200,
+            gas_per_pixel: 1,
+        }
+    }
+
+    fn report(remaining: u64) -> GasReport {
+        GasReport {
+            limit: remaining,
+            remaining,
+            used_externally: 0,
+            used_internally: 0,
+        }
+    }
+
+    #[test]
+    fn render_works() {
+        let renderer = Renderer::new(config());
+        let mut r = report(1_000_000);
+        let png = renderer.render(SVG, &mut r).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(r.used_externally, 200 * 100);
+
+        // Rendering is deterministic.
+        assert_eq!(renderer.render(SVG, &mut report(1_000_000)).unwrap(), png);
+
+        assert!(renderer.render(b"<nope", &mut report(1_000_000)).is_err());
+        let tall = br#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="100"/>"#;
+        assert!(renderer.render(tall, &mut report(1_000_000)).is_err());
+    }
+
+    #[test]
+    fn render_charges_before_drawing() {
+        let renderer = Renderer::new(config());
+        let mut r = report(200 * 100 - 1);
+        assert!(renderer.render(SVG, &mut r).is_err());
+        assert_eq!(r.remaining, 0);
+
+        // Filters are charged per primitive.
+        let filtered = br#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50">
+            <filter id="f"><feGaussianBlur stdDeviation="2"/><feOffset dx="1"/></filter>
+            <rect width="100" height="50" fill="red" filter="url(#f)"/>
+        </svg>"#;
+        let mut r = report(1_000_000);
+        renderer.render(filtered, &mut r).unwrap();
+        assert_eq!(r.used_externally, 3 * 200 * 100);
+    }
+
+    #[test]
+    fn charge_works() {
+        let renderer = Renderer::new(config());
+        let mut report = GasReport {
+            limit: 100,
+            remaining: 90,
+            used_externally: 4,
+            used_internally: 6,
+        };
+        renderer.charge(&mut report, 25, 2).unwrap();
+        assert_eq!((report.remaining, report.used_externally), (40, 54));
+        assert!(renderer.charge(&mut report, 50, 1).is_err());
+        assert_eq!((report.remaining, report.used_externally), (0, 94));
+    }
+
+    #[test]
+    fn wants_png_works() {
+        let mut headers = HeaderMap::new();
+        assert!(!wants_png("", &headers));
+        assert!(wants_png("a=1&format=png", &headers));
+        headers.insert(ACCEPT, "image/apng, image/svg+xml".parse().unwrap());
+        assert!(!wants_png("", &headers));
+        headers.insert(ACCEPT, "image/webp,image/png;q=0.9".parse().unwrap());
+        assert!(wants_png("", &headers));
+    }
+}
@@ -0,0 +1,394 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::header::{HeaderMap, HeaderName, CACHE_CONTROL, VARY};
+use warp::http::{Method, StatusCode};
+
+use crate::backend::{table_tag, BLOCK_TAG};
+use crate::config::ResponseCacheConfig;
+
+/// A finished function response.
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// A notification that tables were written to.
+#[derive(Debug, Deserialize)]
+pub struct TableMutations {
+    pub tables: Vec<String>,
+}
+
+struct Entry {
+    response: CachedResponse,
+    tables: HashSet<String>,
+    inserted: Instant,
+}
+
+#[derive(Default)]
+struct Entries {
+    entries: HashMap<String, Entry>,
+    /// Request headers each response varies on, by base key.
+    vary: HashMap<String, Vec<HeaderName>>,
+    /// Keys of the entries that read each table tag.
+    tables: HashMap<String, HashSet<String>>,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) -> bool {
+        let entry = match self.entries.remove(key) {
+            Some(e) => e,
+            None => return false,
+        };
+        for tag in entry.tables {
+            if let Some(keys) = self.tables.get_mut(&tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.tables.remove(&tag);
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Caches full function responses, tagged with the tables each one read so
+/// they can be dropped when those tables change. Responses that read the
+/// latest block are dropped when it moves.
+#[derive(Clone)]
+pub struct ResponseCache {
+    config: Arc<ResponseCacheConfig>,
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: ResponseCacheConfig) -> Self {
+        ResponseCache {
+            config: Arc::new(config),
+            entries: Arc::new(Mutex::new(Entries::default())),
+        }
+    }
+
+    /// Returns the key for a request, before its vary headers are added, or
+    /// `None` if the request can't be served from the cache. Signed requests
+    /// are never cached, since functions may respond per caller. Responses
+    /// are kept per client IP, since functions can see it in `ctx.meta`.
+    pub fn base_key(
+        &self,
+        cid: &str,
+        method: &Method,
+        path: &str,
+        signed: bool,
+        client_ip: Option<&str>,
+    ) -> Option<String> {
+        if !self.config.enabled || signed || (method != Method::GET && method != Method::HEAD) {
+            return None;
+        }
+        Some(format!(
+            "{} {} {} {}",
+            cid,
+            method,
+            path,
+            client_ip.unwrap_or_default()
+        ))
+    }
+
+    pub fn get(&self, base: &str, headers: &HeaderMap) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = full_key(base, entries.vary.get(base)?, headers);
+        let entry = entries.entries.get(&key)?;
+        if self.expired(entry, Instant::now()) {
+            entries.remove(&key);
+            return None;
+        }
+        Some(entry.response.clone())
+    }
+
+    /// Caches a successful response, unless the function opted out with
+    /// `Cache-Control: no-store` or `private`, or `Vary: *`.
+    pub fn insert(
+        &self,
+        base: String,
+        headers: &HeaderMap,
+        response: CachedResponse,
+        tables: HashSet<String>,
+    ) {
+        if response.status != StatusCode::OK || !cacheable(&response.headers) {
+            return;
+        }
+        let mut vary: Vec<HeaderName> = response
+            .headers
+            .get_all(VARY)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|h| HeaderName::from_bytes(h.trim().as_bytes()).ok())
+            .collect();
+        vary.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        vary.dedup();
+        let key = full_key(base.as_str(), &vary, headers);
+
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.entries.contains_key(&key) && entries.entries.len() >= self.config.max_entries {
+            self.evict(&mut entries);
+        }
+        if entries.entries.len() >= self.config.max_entries {
+            return;
+        }
+        entries.remove(&key);
+        for tag in tables.iter() {
+            entries
+                .tables
+                .entry(tag.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        entries.entries.insert(
+            key,
+            Entry {
+                response,
+                tables,
+                inserted: Instant::now(),
+            },
+        );
+        entries.vary.insert(base, vary);
+    }
+
+    /// Drops every response that read one of `tables`, given by name, e.g.,
+    /// `players_80001_2`. Returns the number of responses dropped.
+    pub fn invalidate(&self, tables: &[String]) -> usize {
+        self.invalidate_tags(tables.iter().filter_map(|t| table_tag(t)))
+    }
+
+    /// Drops every response that read the latest block. Returns the number of
+    /// responses dropped.
+    pub fn invalidate_block(&self) -> usize {
+        self.invalidate_tags(std::iter::once(BLOCK_TAG.to_string()))
+    }
+
+    fn invalidate_tags(&self, tags: impl Iterator<Item = String>) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let keys: HashSet<String> = tags
+            .filter_map(|tag| entries.tables.get(&tag).cloned())
+            .flatten()
+            .collect();
+        keys.iter().filter(|k| entries.remove(k)).count()
+    }
+
+    /// Drops every response from a function. Returns the number of responses
+    /// dropped.
+    pub fn invalidate_function(&self, cid: &str) -> usize {
+        let prefix = format!("{} ", cid);
+        let mut entries = self.entries.lock().unwrap();
+        let keys: Vec<String> = entries
+            .entries
+            .keys()
+            .filter(|k| k.starts_with(&prefix))
+            .cloned()
+            .collect();
+        entries.vary.retain(|k, _| !k.starts_with(&prefix));
+        keys.iter().filter(|k| entries.remove(k)).count()
+    }
+
+    fn expired(&self, entry: &Entry, now: Instant) -> bool {
+        self.config.ttl > 0
+            && now.duration_since(entry.inserted) >= Duration::from_secs(self.config.ttl)
+    }
+
+    /// Makes room by dropping expired entries, or the oldest if none are.
+    fn evict(&self, entries: &mut Entries) {
+        let now = Instant::now();
+        let expired: Vec<String> = entries
+            .entries
+            .iter()
+            .filter(|(_, e)| self.expired(e, now))
+            .map(|(k, _)| k.clone())
+            .collect();
+        if !expired.is_empty() {
+            for key in expired {
+                entries.remove(&key);
+            }
+            return;
+        }
+        let oldest = entries
+            .entries
+            .iter()
+            .min_by_key(|(_, e)| e.inserted)
+            .map(|(k, _)| k.clone());
+        if let Some(key) = oldest {
+            entries.remove(&key);
+        }
+    }
+}
+
+fn cacheable(headers: &HeaderMap) -> bool {
+    let directives = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|d| d.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>()
+    };
+    let no_store = directives(CACHE_CONTROL)
+        .iter()
+        .any(|d| d == "no-store" || d == "private");
+    !no_store && !directives(VARY).iter().any(|d| d == "*")
+}
+
+fn full_key(base: &str, vary: &[HeaderName], headers: &HeaderMap) -> String {
+    let mut key = base.to_string();
+    for name in vary {
+        let values: Vec<&str> = headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect();
+        key.push_str(&format!("\n{}: {}", name, values.join(",")));
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::http::header::ACCEPT;
+
+    fn cache(ttl: u64, max_entries: usize) -> ResponseCache {
+        ResponseCache::new(ResponseCacheConfig {
+            enabled: true,
+            ttl,
+            max_entries,
+        })
+    }
+
+    fn response(body: &str, headers: &[(HeaderName, &str)]) -> CachedResponse {
+        let mut map = HeaderMap::new();
+        for (k, v) in headers {
+            map.append(k, v.parse().unwrap());
+        }
+        CachedResponse {
+            status: StatusCode::OK,
+            headers: map,
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn tags(tables: &[&str]) -> HashSet<String> {
+        tables.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn base_key_works() {
+        let c = cache(0, 10);
+        assert!(c
+            .base_key("cid", &Method::GET, "/a?b=1", false, None)
+            .is_some());
+        assert!(c.base_key("cid", &Method::GET, "/a", true, None).is_none());
+        assert!(c
+            .base_key("cid", &Method::POST, "/a", false, None)
+            .is_none());
+        assert!(ResponseCache::new(ResponseCacheConfig::default())
+            .base_key("cid", &Method::GET, "/a", false, None)
+            .is_none());
+
+        // A response cached for one client isn't served to another.
+        let key = |ip| {
+            c.base_key("cid", &Method::GET, "/a", false, Some(ip))
+                .unwrap()
+        };
+        let headers = HeaderMap::new();
+        c.insert(
+            key("1.1.1.1"),
+            &headers,
+            response("hello 1.1.1.1", &[]),
+            tags(&[]),
+        );
+        assert!(c.get(&key("1.1.1.1"), &headers).is_some());
+        assert!(c.get(&key("2.2.2.2"), &headers).is_none());
+    }
+
+    #[test]
+    fn invalidate_works() {
+        let c = cache(0, 10);
+        let none = HeaderMap::new();
+        c.insert("a".into(), &none, response("a", &[]), tags(&["1_1"]));
+        c.insert("b".into(), &none, response("b", &[]), tags(&["1_1", "1_2"]));
+        c.insert("c".into(), &none, response("c", &[]), tags(&[]));
+        assert_eq!(c.get("b", &none).unwrap().body, b"b");
+
+        assert_eq!(c.invalidate(&["players_1_2".into(), "nope".into()]), 1);
+        assert!(c.get("b", &none).is_none());
+        assert_eq!(c.invalidate(&["_1_1".into()]), 1);
+        assert!(c.get("a", &none).is_none());
+        assert!(c.get("c", &none).is_some());
+        assert_eq!(c.invalidate(&["_1_1".into()]), 0);
+
+        c.insert("a GET /".into(), &none, response("a", &[]), tags(&[]));
+        c.insert("ab GET /".into(), &none, response("b", &[]), tags(&[]));
+        assert_eq!(c.invalidate_function("a"), 1);
+        assert!(c.get("a GET /", &none).is_none());
+        assert!(c.get("ab GET /", &none).is_some());
+    }
+
+    #[test]
+    fn insert_respects_vary_and_opt_outs() {
+        let c = cache(0, 10);
+        let mut svg = HeaderMap::new();
+        svg.insert(ACCEPT, "image/svg+xml".parse().unwrap());
+        let mut png = HeaderMap::new();
+        png.insert(ACCEPT, "image/png".parse().unwrap());
+
+        c.insert(
+            "v".into(),
+            &svg,
+            response("svg", &[(VARY, "Accept")]),
+            tags(&[]),
+        );
+        assert_eq!(c.get("v", &svg).unwrap().body, b"svg");
+        assert!(c.get("v", &png).is_none());
+        c.insert(
+            "v".into(),
+            &png,
+            response("png", &[(VARY, "accept")]),
+            tags(&[]),
+        );
+        assert_eq!(c.get("v", &svg).unwrap().body, b"svg");
+        assert_eq!(c.get("v", &png).unwrap().body, b"png");
+
+        let none = HeaderMap::new();
+        c.insert(
+            "s".into(),
+            &none,
+            response("s", &[(CACHE_CONTROL, "max-age=0, private")]),
+            tags(&[]),
+        );
+        c.insert("w".into(), &none, response("w", &[(VARY, "*")]), tags(&[]));
+        let mut error = response("e", &[]);
+        error.status = StatusCode::NOT_FOUND;
+        c.insert("e".into(), &none, error, tags(&[]));
+        assert!(c.get("s", &none).is_none());
+        assert!(c.get("w", &none).is_none());
+        assert!(c.get("e", &none).is_none());
+    }
+
+    #[test]
+    fn entries_expire_and_are_evicted() {
+        let none = HeaderMap::new();
+        let c = cache(1, 2);
+        c.insert("a".into(), &none, response("a", &[]), tags(&["1_1"]));
+        c.insert("b".into(), &none, response("b", &[]), tags(&[]));
+        c.insert("c".into(), &none, response("c", &[]), tags(&[]));
+        // "a" was the oldest.
+        assert!(c.get("a", &none).is_none());
+        assert!(c.get("b", &none).is_some());
+        assert!(c.entries.lock().unwrap().tables.is_empty());
+
+        std::thread::sleep(Duration::from_millis(1100));
+        assert!(c.get("c", &none).is_none());
+    }
+}
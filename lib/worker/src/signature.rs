@@ -0,0 +1,330 @@
+use bytes::Bytes;
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use sha3::{Digest, Keccak256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use warp::{
+    http::{HeaderMap, Method},
+    path::FullPath,
+    Filter, Rejection,
+};
+
+use crate::config::SignatureConfig;
+use crate::errors::{StoreError, WorkerError};
+use crate::evict::evict_oldest;
+use crate::ledger::now;
+
+pub const ADDRESS_HEADER: &str = "x-tbl-address";
+pub const TIMESTAMP_HEADER: &str = "x-tbl-timestamp";
+pub const NONCE_HEADER: &str = "x-tbl-nonce";
+pub const SIGNATURE_HEADER: &str = "x-tbl-signature";
+
+/// Max number of nonces remembered. Once reached, the half closest to expiry
+/// is dropped.
+const MAX_TRACKED_NONCES: usize = 100_000;
+
+/// Verifies EIP-191 (`personal_sign`) request signatures.
+///
+/// The signed message is the method, path (with query), hex-encoded keccak256
+/// hash of the body, timestamp and nonce, separated by newlines.
+#[derive(Clone)]
+pub struct Verifier {
+    config: SignatureConfig,
+    nonces: Arc<Mutex<Nonces>>,
+}
+
+#[derive(Default)]
+struct Nonces {
+    /// Seen `address:nonce` pairs and when they can be forgotten.
+    seen: HashMap<String, u64>,
+    /// Nonces expiring at or before this may have been forgotten early, so
+    /// requests that would expire by then are rejected as stale.
+    floor: u64,
+}
+
+impl Verifier {
+    pub fn new(config: SignatureConfig) -> Self {
+        Verifier {
+            config,
+            nonces: Arc::new(Mutex::new(Nonces::default())),
+        }
+    }
+
+    /// Returns the address that signed the request, or `None` if the request
+    /// isn't signed.
+    pub fn verify(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Option<String>, StoreError> {
+        let signature = match header(headers, SIGNATURE_HEADER) {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let timestamp = header(headers, TIMESTAMP_HEADER)
+            .and_then(|t| t.parse::<u64>().ok())
+            .ok_or_else(|| StoreError::unauthorized_err("missing or invalid timestamp"))?;
+        let nonce = header(headers, NONCE_HEADER)
+            .filter(|n| !n.is_empty())
+            .ok_or_else(|| StoreError::unauthorized_err("missing nonce"))?;
+        let claimed = header(headers, ADDRESS_HEADER)
+            .ok_or_else(|| StoreError::unauthorized_err("missing address"))?;
+
+        let now = now();
+        if now.abs_diff(timestamp) > self.config.max_age {
+            return Err(StoreError::unauthorized_err("stale timestamp"));
+        }
+
+        let message = signing_message(method, path, body, timestamp, nonce);
+        let address = recover_address(message.as_bytes(), signature)?;
+        // Any signature recovers to some address, so a tampered request is
+        // only detectable by comparing against the claimed signer.
+        if !claimed.eq_ignore_ascii_case(&address) {
+            return Err(StoreError::unauthorized_err("signer mismatch"));
+        }
+
+        // A nonce only needs remembering while its timestamp is still accepted.
+        let expires = timestamp + self.config.max_age + 1;
+        let mut nonces = self.nonces.lock().unwrap();
+        if expires <= nonces.floor {
+            return Err(StoreError::unauthorized_err("stale timestamp"));
+        }
+        let key = format!("{}:{}", address, nonce);
+        if nonces.seen.contains_key(&key) {
+            return Err(StoreError::unauthorized_err("nonce already used"));
+        }
+        if let Some(cutoff) = evict_oldest(&mut nonces.seen, MAX_TRACKED_NONCES, |e| *e) {
+            nonces.floor = nonces.floor.max(cutoff);
+        }
+        nonces.seen.insert(key, expires);
+
+        Ok(Some(address))
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+pub fn signing_message(
+    method: &Method,
+    path: &str,
+    body: &[u8],
+    timestamp: u64,
+    nonce: &str,
+) -> String {
+    format!(
+        "{}\n{}\n0x{}\n{}\n{}",
+        method,
+        path,
+        hex::encode(Keccak256::digest(body)),
+        timestamp,
+        nonce
+    )
+}
+
+/// Hashes a message as specified by EIP-191 version `0x45` (`personal_sign`).
+pub fn eip191_hash(message: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(format!("\x19Ethereum Signed Message:\n{}", message.len()));
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+/// Recovers the address that produced a hex-encoded 65-byte `r || s || v`
+/// signature over `message`.
+pub fn recover_address(message: &[u8], signature: &str) -> Result<String, StoreError> {
+    let invalid = || StoreError::unauthorized_err("invalid signature");
+
+    let bytes = hex::decode(signature.trim_start_matches("0x")).map_err(|_| invalid())?;
+    if bytes.len() != 65 {
+        return Err(invalid());
+    }
+    let sig = Signature::from_slice(&bytes[..64]).map_err(|_| invalid())?;
+    let v = match bytes[64] {
+        v @ 27..=28 => v - 27,
+        v => v,
+    };
+    let recovery_id = RecoveryId::from_byte(v).ok_or_else(invalid)?;
+    let key = VerifyingKey::recover_from_prehash(&eip191_hash(message), &sig, recovery_id)
+        .map_err(|_| invalid())?;
+
+    let point = key.to_encoded_point(false);
+    let hash = Keccak256::digest(&point.as_bytes()[1..]);
+    Ok(format!("0x{}", hex::encode(&hash[12..])))
+}
+
+/// Extracts the request body along with the verified caller address,
+/// rejecting with 401 if a signature is present but invalid.
+pub fn with_caller(
+    verifier: Verifier,
+) -> impl Filter<Extract = (Option<String>, Bytes), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(
+            warp::query::raw()
+                .or(warp::any().map(String::default))
+                .unify(),
+        )
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .and(warp::any().map(move || verifier.clone()))
+        .and_then(check_signature)
+        .untuple_one()
+}
+
+async fn check_signature(
+    method: Method,
+    full_path: FullPath,
+    query: String,
+    headers: HeaderMap,
+    body: Bytes,
+    verifier: Verifier,
+) -> Result<(Option<String>, Bytes), Rejection> {
+    let mut path = full_path.as_str().to_string();
+    if !query.is_empty() {
+        path = format!("{}?{}", path, query);
+    }
+    let caller = verifier
+        .verify(&method, &path, &headers, &body)
+        .map_err(|e| warp::reject::custom(WorkerError::new(e, None)))?;
+    Ok((caller, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::SigningKey;
+
+    const KEY: [u8; 32] = [7u8; 32];
+
+    fn address() -> String {
+        let key = SigningKey::from_bytes(&KEY.into()).unwrap();
+        let point = key.verifying_key().to_encoded_point(false);
+        let hash = Keccak256::digest(&point.as_bytes()[1..]);
+        format!("0x{}", hex::encode(&hash[12..]))
+    }
+
+    fn sign(message: &str) -> String {
+        let key = SigningKey::from_bytes(&KEY.into()).unwrap();
+        let (sig, recovery_id) = key
+            .sign_prehash_recoverable(&eip191_hash(message.as_bytes()))
+            .unwrap();
+        let mut bytes = sig.to_vec();
+        bytes.push(recovery_id.to_byte() + 27);
+        format!("0x{}", hex::encode(bytes))
+    }
+
+    fn signed_headers(method: &Method, path: &str, body: &[u8], ts: u64, nonce: &str) -> HeaderMap {
+        let message = signing_message(method, path, body, ts, nonce);
+        let mut headers = HeaderMap::new();
+        headers.insert(SIGNATURE_HEADER, sign(&message).parse().unwrap());
+        headers.insert(TIMESTAMP_HEADER, ts.into());
+        headers.insert(NONCE_HEADER, nonce.parse().unwrap());
+        headers.insert(ADDRESS_HEADER, address().parse().unwrap());
+        headers
+    }
+
+    fn verifier() -> Verifier {
+        Verifier::new(SignatureConfig { max_age: 60 })
+    }
+
+    #[test]
+    fn verify_works() {
+        let verifier = verifier();
+        let path = "/v1/functions/cid/foo?bar=baz";
+        let mut headers = signed_headers(&Method::POST, path, b"body", now(), "1");
+        headers.insert(
+            ADDRESS_HEADER,
+            address()
+                .replace("0x", "0X")
+                .to_uppercase()
+                .parse()
+                .unwrap(),
+        );
+        let caller = verifier
+            .verify(&Method::POST, path, &headers, b"body")
+            .unwrap();
+        assert_eq!(caller, Some(address()));
+    }
+
+    #[test]
+    fn verify_unsigned() {
+        let caller = verifier()
+            .verify(&Method::GET, "/", &HeaderMap::new(), b"")
+            .unwrap();
+        assert_eq!(caller, None);
+    }
+
+    #[test]
+    fn verify_rejects_tampering() {
+        let verifier = verifier();
+        let headers = signed_headers(&Method::POST, "/a", b"body", now(), "1");
+        assert!(verifier
+            .verify(&Method::POST, "/b", &headers, b"body")
+            .is_err());
+        assert!(verifier
+            .verify(&Method::PUT, "/a", &headers, b"body")
+            .is_err());
+        assert!(verifier
+            .verify(&Method::POST, "/a", &headers, b"other")
+            .is_err());
+
+        let mut headers = signed_headers(&Method::GET, "/a", b"", now(), "2");
+        headers.insert(
+            ADDRESS_HEADER,
+            "0x0000000000000000000000000000000000000000"
+                .parse()
+                .unwrap(),
+        );
+        assert!(verifier.verify(&Method::GET, "/a", &headers, b"").is_err());
+    }
+
+    #[test]
+    fn verify_rejects_stale_and_replayed() {
+        let verifier = verifier();
+        let headers = signed_headers(&Method::GET, "/", b"", now() - 120, "1");
+        match verifier.verify(&Method::GET, "/", &headers, b"") {
+            Err(StoreError::Unauthorized(msg)) => assert_eq!(msg, "stale timestamp"),
+            _ => panic!("expected stale timestamp"),
+        }
+
+        let headers = signed_headers(&Method::GET, "/", b"", now(), "2");
+        assert!(verifier.verify(&Method::GET, "/", &headers, b"").is_ok());
+        match verifier.verify(&Method::GET, "/", &headers, b"") {
+            Err(StoreError::Unauthorized(msg)) => assert_eq!(msg, "nonce already used"),
+            _ => panic!("expected replayed nonce"),
+        }
+    }
+
+    #[test]
+    fn verify_bounds_nonces() {
+        let verifier = verifier();
+        let ts = now();
+        for i in 0..MAX_TRACKED_NONCES {
+            verifier
+                .nonces
+                .lock()
+                .unwrap()
+                .seen
+                .insert(i.to_string(), ts + 61 + i as u64);
+        }
+
+        // Evicting nonces that haven't expired rejects requests that could
+        // replay them.
+        let headers = signed_headers(&Method::GET, "/", b"", ts, "a");
+        assert!(verifier.verify(&Method::GET, "/", &headers, b"").is_ok());
+        assert_eq!(
+            verifier.nonces.lock().unwrap().seen.len(),
+            MAX_TRACKED_NONCES / 2 + 1
+        );
+        assert!(verifier.verify(&Method::GET, "/", &headers, b"").is_err());
+        let headers = signed_headers(&Method::GET, "/", b"", ts, "b");
+        match verifier.verify(&Method::GET, "/", &headers, b"") {
+            Err(StoreError::Unauthorized(msg)) => assert_eq!(msg, "stale timestamp"),
+            _ => panic!("expected stale timestamp"),
+        }
+    }
+}
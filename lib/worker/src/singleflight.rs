@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+type Calls<T> = Arc<Mutex<HashMap<String, broadcast::Sender<T>>>>;
+
+/// Shares the result of one call among concurrent calls with the same key.
+#[derive(Clone)]
+pub struct SingleFlight<T: Clone> {
+    calls: Calls<T>,
+}
+
+impl<T: Clone> Default for SingleFlight<T> {
+    fn default() -> Self {
+        SingleFlight {
+            calls: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    /// Runs `f`, unless a call with the same key is already in flight, in
+    /// which case its result is returned instead. The second value is `true`
+    /// for shared results. If the call being waited on is dropped before it
+    /// finishes, `f` runs after all.
+    pub async fn run<F, Fut>(&self, key: String, f: F) -> (T, bool)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let waiting = {
+            let mut calls = self.calls.lock().unwrap();
+            match calls.get(&key) {
+                Some(tx) => Some(tx.subscribe()),
+                None => {
+                    calls.insert(key.clone(), broadcast::channel(1).0);
+                    None
+                }
+            }
+        };
+        if let Some(mut rx) = waiting {
+            if let Ok(v) = rx.recv().await {
+                return (v, true);
+            }
+            return (f().await, false);
+        }
+
+        let mut leader = Leader {
+            calls: self.calls.clone(),
+            key: Some(key),
+        };
+        let v = f().await;
+        if let Some(tx) = leader.finish() {
+            // Nobody waiting is fine.
+            let _ = tx.send(v.clone());
+        }
+        (v, false)
+    }
+}
+
+/// Removes an in-flight call when it finishes or is dropped.
+struct Leader<T: Clone> {
+    calls: Calls<T>,
+    key: Option<String>,
+}
+
+impl<T: Clone> Leader<T> {
+    fn finish(&mut self) -> Option<broadcast::Sender<T>> {
+        let key = self.key.take()?;
+        self.calls.lock().unwrap().remove(&key)
+    }
+}
+
+impl<T: Clone> Drop for Leader<T> {
+    fn drop(&mut self) {
+        // Waiters see the channel close and run the call themselves.
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn run_shares_results() {
+        let flight = SingleFlight::<usize>::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let call = |key: &str| {
+            let (flight, runs) = (flight.clone(), runs.clone());
+            let key = key.to_string();
+            tokio::spawn(async move {
+                flight
+                    .run(key, || async move {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        runs.fetch_add(1, Ordering::SeqCst) + 1
+                    })
+                    .await
+            })
+        };
+
+        let calls: Vec<_> = (0..10).map(|_| call("a")).collect();
+        let other = call("b");
+        let mut shared = 0;
+        for c in calls {
+            let (v, s) = c.await.unwrap();
+            assert!(v <= 2);
+            shared += s as usize;
+        }
+        assert_eq!(shared, 9);
+        assert!(!other.await.unwrap().1);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert!(flight.calls.lock().unwrap().is_empty());
+
+        // Finished calls aren't shared.
+        assert_eq!(call("a").await.unwrap(), (3, false));
+    }
+
+    #[tokio::test]
+    async fn run_recovers_from_dropped_leader() {
+        let flight = SingleFlight::<u8>::default();
+        let f = flight.clone();
+        let leader = tokio::spawn(async move {
+            f.run("a".into(), || async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                1
+            })
+            .await
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let f = flight.clone();
+        let waiter = tokio::spawn(async move { f.run("a".into(), || async { 2 }).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        leader.abort();
+        assert_eq!(waiter.await.unwrap(), (2, false));
+        assert!(flight.calls.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,559 @@
+use async_trait::async_trait;
+use reqwest::{Client, Response};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::car::cid::{Cid, RAW};
+use crate::config::{FetchStrategy, IpfsConfig, SourceConfig};
+use crate::errors::StoreError;
+
+/// Somewhere modules can be fetched from by CID.
+#[async_trait]
+pub trait ModuleSource: Send + Sync {
+    /// Describes the source in logs and errors.
+    fn name(&self) -> String;
+
+    /// Fetches a module, failing if it's larger than `max_size` bytes.
+    async fn fetch(&self, cid: &str, max_size: u64) -> Result<Vec<u8>, StoreError>;
+
+    /// Checks that the source is reachable.
+    async fn probe(&self) -> Result<(), StoreError>;
+}
+
+/// An HTTP IPFS gateway, e.g., `https://ipfs.io/ipfs`.
+pub struct GatewaySource {
+    client: Client,
+    url: String,
+}
+
+impl GatewaySource {
+    pub fn new(url: String, timeout: Duration) -> Self {
+        GatewaySource {
+            client: http_client(timeout),
+            url,
+        }
+    }
+}
+
+#[async_trait]
+impl ModuleSource for GatewaySource {
+    fn name(&self) -> String {
+        format!("gateway {}", self.url)
+    }
+
+    async fn fetch(&self, cid: &str, max_size: u64) -> Result<Vec<u8>, StoreError> {
+        let res = self
+            .client
+            .get(format!("{}/{}", self.url, cid))
+            .send()
+            .await?;
+        read_limited(res, max_size).await
+    }
+
+    async fn probe(&self) -> Result<(), StoreError> {
+        // Gateways don't serve their root path, so any response means it's reachable.
+        self.client.get(self.url.as_str()).send().await?;
+        Ok(())
+    }
+}
+
+/// The HTTP RPC API of an IPFS node, e.g., `http://localhost:5001`.
+pub struct IpfsApiSource {
+    client: Client,
+    url: String,
+}
+
+impl IpfsApiSource {
+    pub fn new(url: String, timeout: Duration) -> Self {
+        IpfsApiSource {
+            client: http_client(timeout),
+            url,
+        }
+    }
+}
+
+#[async_trait]
+impl ModuleSource for IpfsApiSource {
+    fn name(&self) -> String {
+        format!("ipfs api {}", self.url)
+    }
+
+    async fn fetch(&self, cid: &str, max_size: u64) -> Result<Vec<u8>, StoreError> {
+        let res = self
+            .client
+            .post(format!("{}/api/v0/cat", self.url))
+            .query(&[("arg", cid)])
+            .send()
+            .await?;
+        read_limited(res, max_size).await
+    }
+
+    async fn probe(&self) -> Result<(), StoreError> {
+        self.client
+            .post(format!("{}/api/v0/version", self.url))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// A local directory of `{cid}.wasm` files.
+pub struct DirectorySource {
+    path: String,
+}
+
+impl DirectorySource {
+    pub fn new(path: String) -> Self {
+        DirectorySource { path }
+    }
+}
+
+#[async_trait]
+impl ModuleSource for DirectorySource {
+    fn name(&self) -> String {
+        format!("directory {}", self.path)
+    }
+
+    async fn fetch(&self, cid: &str, max_size: u64) -> Result<Vec<u8>, StoreError> {
+        let file_name = format!("{}/{}.wasm", self.path, cid);
+        let len = tokio::fs::metadata(&file_name)
+            .await
+            .map_err(|e| StoreError::Ipfs(e.to_string()))?
+            .len();
+        if len > max_size {
+            return Err(too_large(max_size));
+        }
+        tokio::fs::read(&file_name)
+            .await
+            .map_err(|e| StoreError::Ipfs(e.to_string()))
+    }
+
+    async fn probe(&self) -> Result<(), StoreError> {
+        let meta = tokio::fs::metadata(&self.path)
+            .await
+            .map_err(|e| StoreError::Ipfs(e.to_string()))?;
+        if !meta.is_dir() {
+            return Err(StoreError::Ipfs(format!(
+                "{} is not a directory",
+                self.path
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Any HTTP(S) URL. `{cid}` in the template is replaced with the CID, e.g.,
+/// `http://peer:3030/v1/modules/{cid}` to mirror another worker.
+pub struct UrlSource {
+    client: Client,
+    template: String,
+}
+
+impl UrlSource {
+    pub fn new(template: String, timeout: Duration) -> Self {
+        UrlSource {
+            client: http_client(timeout),
+            template,
+        }
+    }
+}
+
+#[async_trait]
+impl ModuleSource for UrlSource {
+    fn name(&self) -> String {
+        format!("url {}", self.template)
+    }
+
+    async fn fetch(&self, cid: &str, max_size: u64) -> Result<Vec<u8>, StoreError> {
+        let res = self
+            .client
+            .get(self.template.replace("{cid}", cid))
+            .send()
+            .await?;
+        read_limited(res, max_size).await
+    }
+
+    async fn probe(&self) -> Result<(), StoreError> {
+        // Any response, e.g., a 404 for the empty CID, means it's reachable.
+        self.client
+            .get(self.template.replace("{cid}", ""))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+fn http_client(timeout: Duration) -> Client {
+    Client::builder().timeout(timeout).build().unwrap()
+}
+
+fn too_large(max_size: u64) -> StoreError {
+    StoreError::Ipfs(format!("module is larger than {} bytes", max_size))
+}
+
+/// Reads a response body, giving up as soon as it's larger than `max_size`.
+async fn read_limited(res: Response, max_size: u64) -> Result<Vec<u8>, StoreError> {
+    let mut res = res.error_for_status()?;
+    if res.content_length().unwrap_or_default() > max_size {
+        return Err(too_large(max_size));
+    }
+    let mut body = vec![];
+    while let Some(chunk) = res.chunk().await? {
+        if (body.len() + chunk.len()) as u64 > max_size {
+            return Err(too_large(max_size));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Returns the CID to check a fetched module against, or `None` if the CID
+/// doesn't address the module's bytes directly. Only raw sha2-256 CIDs do;
+/// other codecs hash a DAG that sources don't return.
+fn verifiable(cid: &str) -> Option<Cid> {
+    cid.parse::<Cid>().ok().filter(|c| c.codec == RAW)
+}
+
+/// Checks a module fetched from `source` against `expected`, if it's known.
+fn check(
+    cid: &str,
+    expected: Option<&Cid>,
+    source: String,
+    module: Vec<u8>,
+) -> Result<Vec<u8>, StoreError> {
+    match expected {
+        Some(c) => {
+            c.verify(&module)
+                .map_err(|_| StoreError::Ipfs(format!("{} returned the wrong bytes", source)))?;
+        }
+        None => println!("fetched unverified {} from {}", cid, source),
+    }
+    Ok(module)
+}
+
+/// Fetches modules from a list of sources, either in order or by racing them,
+/// and remembers failed lookups for a while. Modules with raw CIDs are
+/// checked against their CID.
+pub struct Sources {
+    sources: Vec<Arc<dyn ModuleSource>>,
+    strategy: FetchStrategy,
+    max_size: u64,
+    negative_ttl: Duration,
+    allow_unverified: bool,
+    failed: Mutex<HashMap<String, Instant>>,
+}
+
+impl Sources {
+    pub fn new(
+        sources: Vec<Arc<dyn ModuleSource>>,
+        strategy: FetchStrategy,
+        max_size: u64,
+        negative_ttl: Duration,
+        allow_unverified: bool,
+    ) -> Self {
+        Sources {
+            sources,
+            strategy,
+            max_size,
+            negative_ttl,
+            allow_unverified,
+            failed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Builds sources from config. With no sources configured, the
+    /// configured gateway is used.
+    pub fn from_config(config: &IpfsConfig) -> Self {
+        let mut sources: Vec<Arc<dyn ModuleSource>> = vec![];
+        for source in config.sources.iter() {
+            sources.push(match source.clone() {
+                SourceConfig::Gateway { url, timeout } => {
+                    Arc::new(GatewaySource::new(url, Duration::from_secs(timeout)))
+                }
+                SourceConfig::IpfsApi { url, timeout } => {
+                    Arc::new(IpfsApiSource::new(url, Duration::from_secs(timeout)))
+                }
+                SourceConfig::Directory { path } => Arc::new(DirectorySource::new(path)),
+                SourceConfig::Url { template, timeout } => {
+                    Arc::new(UrlSource::new(template, Duration::from_secs(timeout)))
+                }
+            });
+        }
+        if sources.is_empty() {
+            sources.push(Arc::new(GatewaySource::new(
+                config.gateway.clone(),
+                Duration::from_secs(5),
+            )));
+        }
+        Self::new(
+            sources,
+            config.strategy,
+            config.max_size,
+            Duration::from_secs(config.negative_ttl),
+            config.allow_unverified,
+        )
+    }
+
+    /// Checks that at least one source is reachable.
+    pub async fn probe(&self) -> Result<(), StoreError> {
+        let mut tasks = tokio::task::JoinSet::new();
+        for source in self.sources.iter() {
+            let source = source.clone();
+            tasks.spawn(async move { (source.name(), source.probe().await) });
+        }
+
+        let mut errors = vec![];
+        while let Some(res) = tasks.join_next().await {
+            match res? {
+                (_, Ok(())) => {
+                    tasks.abort_all();
+                    return Ok(());
+                }
+                (name, Err(e)) => errors.push(format!("{}: {}", name, e)),
+            }
+        }
+        Err(StoreError::Ipfs(errors.join("; ")))
+    }
+
+    pub async fn fetch(&self, cid: &str) -> Result<Vec<u8>, StoreError> {
+        let expected = verifiable(cid);
+        if expected.is_none() && !self.allow_unverified {
+            return Err(StoreError::Ipfs(format!(
+                "{} can't be verified; only raw CIDs can be fetched",
+                cid
+            )));
+        }
+        {
+            let mut failed = self.failed.lock().unwrap();
+            failed.retain(|_, at| at.elapsed() < self.negative_ttl);
+            if failed.contains_key(cid) {
+                return Err(StoreError::Ipfs(format!("{} was recently not found", cid)));
+            }
+        }
+
+        let res = match self.strategy {
+            FetchStrategy::Ordered => self.fetch_ordered(cid, expected.as_ref()).await,
+            FetchStrategy::Race => self.fetch_race(cid, expected.as_ref()).await,
+        };
+        if res.is_err() && !self.negative_ttl.is_zero() {
+            self.failed
+                .lock()
+                .unwrap()
+                .insert(cid.to_string(), Instant::now());
+        }
+        res
+    }
+
+    async fn fetch_ordered(
+        &self,
+        cid: &str,
+        expected: Option<&Cid>,
+    ) -> Result<Vec<u8>, StoreError> {
+        let mut errors = vec![];
+        for source in self.sources.iter() {
+            let res = source.fetch(cid, self.max_size).await;
+            match res.and_then(|m| check(cid, expected, source.name(), m)) {
+                Ok(m) => return Ok(m),
+                Err(e) => errors.push(format!("{}: {}", source.name(), e)),
+            }
+        }
+        Err(StoreError::Ipfs(errors.join("; ")))
+    }
+
+    async fn fetch_race(&self, cid: &str, expected: Option<&Cid>) -> Result<Vec<u8>, StoreError> {
+        let mut tasks = tokio::task::JoinSet::new();
+        for source in self.sources.iter() {
+            let source = source.clone();
+            let cid = cid.to_string();
+            let expected = expected.cloned();
+            let max_size = self.max_size;
+            tasks.spawn(async move {
+                let res = source
+                    .fetch(cid.as_str(), max_size)
+                    .await
+                    .and_then(|m| check(cid.as_str(), expected.as_ref(), source.name(), m));
+                (source.name(), res)
+            });
+        }
+
+        let mut errors = vec![];
+        while let Some(res) = tasks.join_next().await {
+            match res? {
+                (_, Ok(m)) => {
+                    tasks.abort_all();
+                    return Ok(m);
+                }
+                (name, Err(e)) => errors.push(format!("{}: {}", name, e)),
+            }
+        }
+        Err(StoreError::Ipfs(errors.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use warp::Filter;
+
+    /// Serves `hello` as a gateway, an IPFS API and a worker would.
+    fn serve(delay: Duration) -> SocketAddr {
+        let gateway = warp::path!("ipfs" / String).map(|cid: String| cid.into_bytes());
+        let api = warp::path!("api" / "v0" / "cat")
+            .and(warp::post())
+            .and(warp::query::<HashMap<String, String>>())
+            .map(|q: HashMap<String, String>| q["arg"].clone().into_bytes());
+        let modules =
+            warp::path!("v1" / "modules" / String).and_then(move |cid: String| async move {
+                tokio::time::sleep(delay).await;
+                Ok::<_, warp::Rejection>(cid.into_bytes())
+            });
+        let (addr, server) =
+            warp::serve(gateway.or(api).or(modules)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        addr
+    }
+
+    fn sources(sources: Vec<Arc<dyn ModuleSource>>, strategy: FetchStrategy) -> Sources {
+        Sources::new(sources, strategy, 1024, Duration::from_secs(60), true)
+    }
+
+    #[tokio::test]
+    async fn sources_work() {
+        let addr = serve(Duration::default());
+        let timeout = Duration::from_secs(1);
+
+        let gateway = GatewaySource::new(format!("http://{}/ipfs", addr), timeout);
+        assert_eq!(gateway.fetch("abc", 1024).await.unwrap(), b"abc");
+        let api = IpfsApiSource::new(format!("http://{}", addr), timeout);
+        assert_eq!(api.fetch("abc", 1024).await.unwrap(), b"abc");
+        let url = UrlSource::new(format!("http://{}/v1/modules/{{cid}}", addr), timeout);
+        assert_eq!(url.fetch("abc", 1024).await.unwrap(), b"abc");
+        assert!(matches!(
+            url.fetch("abc", 2).await,
+            Err(StoreError::Ipfs(msg)) if msg == "module is larger than 2 bytes"
+        ));
+
+        let dir = std::env::temp_dir().join(format!("tbl-sources-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("abc.wasm"), b"abc").unwrap();
+        let local = DirectorySource::new(dir.to_str().unwrap().to_string());
+        assert_eq!(local.fetch("abc", 1024).await.unwrap(), b"abc");
+        assert!(local.fetch("abc", 2).await.is_err());
+        assert!(local.fetch("xyz", 1024).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fetch_falls_back_in_order() {
+        let addr = serve(Duration::default());
+        let timeout = Duration::from_secs(1);
+        let sources = sources(
+            vec![
+                Arc::new(GatewaySource::new(format!("http://{}/nope", addr), timeout)),
+                Arc::new(GatewaySource::new(format!("http://{}/ipfs", addr), timeout)),
+            ],
+            FetchStrategy::Ordered,
+        );
+        assert_eq!(sources.fetch("abc").await.unwrap(), b"abc");
+    }
+
+    #[tokio::test]
+    async fn fetch_races() {
+        let slow = serve(Duration::from_secs(5));
+        let fast = serve(Duration::from_millis(10));
+        let timeout = Duration::from_secs(10);
+        let sources = sources(
+            vec![
+                Arc::new(UrlSource::new(
+                    format!("http://{}/v1/modules/{{cid}}", slow),
+                    timeout,
+                )),
+                Arc::new(UrlSource::new(
+                    format!("http://{}/v1/modules/{{cid}}", fast),
+                    timeout,
+                )),
+            ],
+            FetchStrategy::Race,
+        );
+        let start = Instant::now();
+        assert_eq!(sources.fetch("abc").await.unwrap(), b"abc");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn fetch_caches_failures() {
+        let dir = std::env::temp_dir().join(format!("tbl-negative-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sources = sources(
+            vec![Arc::new(DirectorySource::new(
+                dir.to_str().unwrap().to_string(),
+            ))],
+            FetchStrategy::Ordered,
+        );
+        assert!(sources.fetch("abc").await.is_err());
+
+        // Still fails after the module shows up.
+        std::fs::write(dir.join("abc.wasm"), b"abc").unwrap();
+        match sources.fetch("abc").await {
+            Err(StoreError::Ipfs(msg)) => assert_eq!(msg, "abc was recently not found"),
+            _ => panic!("expected cached failure"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fetch_verifies_raw_cids() {
+        let dir = std::env::temp_dir().join(format!("tbl-verify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cid = Cid::new_v1(RAW, b"hello").to_string();
+        let other = Cid::new_v1(RAW, b"other").to_string();
+        std::fs::write(dir.join(format!("{}.wasm", cid)), b"hello").unwrap();
+        std::fs::write(dir.join(format!("{}.wasm", other)), b"hello").unwrap();
+        std::fs::write(dir.join("abc.wasm"), b"abc").unwrap();
+        let sources = Sources::new(
+            vec![Arc::new(DirectorySource::new(
+                dir.to_str().unwrap().to_string(),
+            ))],
+            FetchStrategy::Ordered,
+            1024,
+            Duration::default(),
+            false,
+        );
+
+        assert_eq!(sources.fetch(cid.as_str()).await.unwrap(), b"hello");
+        match sources.fetch(other.as_str()).await {
+            Err(StoreError::Ipfs(msg)) => assert!(msg.ends_with("returned the wrong bytes")),
+            _ => panic!("expected hash mismatch"),
+        }
+        match sources.fetch("abc").await {
+            Err(StoreError::Ipfs(msg)) => {
+                assert_eq!(msg, "abc can't be verified; only raw CIDs can be fetched")
+            }
+            _ => panic!("expected unverifiable CID"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn probe_works() {
+        let addr = serve(Duration::default());
+        let timeout = Duration::from_secs(1);
+        let unreachable: Arc<dyn ModuleSource> = Arc::new(GatewaySource::new(
+            "http://127.0.0.1:9/ipfs".to_string(),
+            timeout,
+        ));
+        let reachable = sources(
+            vec![
+                unreachable.clone(),
+                Arc::new(GatewaySource::new(format!("http://{}/ipfs", addr), timeout)),
+            ],
+            FetchStrategy::Ordered,
+        );
+        reachable.probe().await.unwrap();
+        let unreachable = sources(vec![unreachable], FetchStrategy::Ordered);
+        assert!(unreachable.probe().await.is_err());
+    }
+}
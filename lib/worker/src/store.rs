@@ -1,104 +1,456 @@
-use reqwest::Client;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
 use tableland_client::{Tableland, TablelandClient};
 use tableland_std::{FuncResult, Request, Response};
-use tableland_vm::{call_fetch, GasReport, Instance, VmResult};
+use tableland_vm::{call_fetch, validate_wasm, GasReport, ValidationReport, VmResult};
+use warp::http::Method;
 
-use crate::backend::Api;
-use crate::config::Config;
+use crate::backend::{track_reads, with_invocation, Invocation};
+use crate::blocks::BlockTracker;
+use crate::cache::{gc_dir, new_fn_cache, touch, CachedFunction, FnCache, FnInstance};
+use crate::config::{Config, ResponseCacheConfig};
+use crate::env::Env;
 use crate::errors::StoreError;
 use crate::instance::{instance_with_options, ApiInstanceOptions};
+use crate::limits::{Limiter, Permit, QueueReport};
+use crate::moderation::Moderation;
+use crate::response_cache::ResponseCache;
+use crate::singleflight::SingleFlight;
+use crate::sources::Sources;
+
+/// Request headers that identify a client rather than a request, so requests
+/// that differ only in these are still coalesced.
+const IGNORED_HEADERS: &[&str] = &[
+    "connection",
+    "user-agent",
+    "referer",
+    "forwarded",
+    "x-forwarded-for",
+    "x-real-ip",
+    "x-request-id",
+    "traceparent",
+    "tracestate",
+    "cache-control",
+    "pragma",
+    "if-none-match",
+    "if-modified-since",
+];
+
+/// Outcome of running a function.
+#[derive(Clone)]
+pub struct Execution {
+    pub result: Result<Response, StoreError>,
+    pub report: GasReport,
+    pub queue: QueueReport,
+    /// Tags of the tables the function read.
+    pub tables: HashSet<String>,
+    /// ID of the request the function saw, which belongs to the first of a
+    /// group of coalesced requests.
+    pub request_id: Option<String>,
+}
 
 #[derive(Clone)]
 pub struct Store {
     config: Config,
-    http_client: Client,
-    fn_cache: stretto::AsyncCache<String, Instance<Api<TablelandClient>>>,
+    sources: Arc<Sources>,
+    limiter: Limiter,
+    fn_cache: FnCache,
+    /// Instances that are never evicted.
+    pinned: Arc<RwLock<HashMap<String, FnInstance>>>,
+    executions: SingleFlight<Execution>,
+    loads: SingleFlight<Result<FnInstance, StoreError>>,
+    moderation: Moderation,
+    env: Option<Env>,
+    blocks: BlockTracker,
+    responses: ResponseCache,
 }
 
 impl Store {
     pub fn new(config: Config) -> Self {
         Store {
+            limiter: Limiter::new(config.limits.clone()),
+            fn_cache: new_fn_cache(config.cache.max_memory).unwrap(),
+            sources: Arc::new(Sources::from_config(&config.ipfs)),
             config,
-            http_client: Client::builder()
-                .timeout(std::time::Duration::new(5, 0))
-                .build()
-                .unwrap(),
-            fn_cache: stretto::AsyncCache::new(12960, 1e6 as i64, tokio::spawn).unwrap(),
+            pinned: Arc::new(RwLock::new(HashMap::new())),
+            executions: SingleFlight::default(),
+            loads: SingleFlight::default(),
+            moderation: Moderation::default(),
+            env: None,
+            blocks: BlockTracker::default(),
+            responses: ResponseCache::new(ResponseCacheConfig::default()),
         }
     }
 
-    pub async fn add(&self, cid: String) -> Result<bool, StoreError> {
-        let module = self
-            .http_client
-            .get(format!("{}/{}", self.config.ipfs.gateway, cid))
-            .send()
-            .await?
-            .error_for_status()?
-            .bytes()
-            .await?
-            .to_vec();
+    /// Enforces operator moderation lists when adding, loading, and running
+    /// functions.
+    pub fn with_moderation(self, moderation: Moderation) -> Self {
+        Store { moderation, ..self }
+    }
+
+    /// Provides functions with the latest block.
+    pub fn with_blocks(self, blocks: BlockTracker) -> Self {
+        Store { blocks, ..self }
+    }
+
+    /// Drops a function's cached responses when it's removed.
+    pub fn with_response_cache(self, responses: ResponseCache) -> Self {
+        Store { responses, ..self }
+    }
+
+    /// Provides functions with their variables and secrets.
+    pub fn with_env(self, env: Env) -> Self {
+        Store {
+            env: Some(env),
+            ..self
+        }
+    }
+
+    /// Compiles and caches every module in the cache directory, plus any
+    /// pinned modules that aren't on disk yet, using up to
+    /// `cache.preload_workers` tasks at once.
+    pub async fn preload(&self) -> Result<usize, StoreError> {
+        let mut cids: Vec<String> = vec![];
+        let mut dir = tokio::fs::read_dir(&self.config.cache.directory).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("wasm") {
+                continue;
+            }
+            if let Some(cid) = path.file_stem().and_then(|s| s.to_str()) {
+                cids.push(cid.to_string());
+            }
+        }
+        let on_disk: HashSet<String> = cids.iter().cloned().collect();
+
+        let workers = Arc::new(tokio::sync::Semaphore::new(
+            self.config.cache.preload_workers.max(1),
+        ));
+        let mut tasks = tokio::task::JoinSet::new();
+        for cid in cids {
+            let store = self.clone();
+            let workers = workers.clone();
+            tasks.spawn(async move {
+                let _permit = workers.acquire_owned().await;
+                let res = store.load(cid.clone()).await.map(|_| ());
+                (cid, res)
+            });
+        }
+        for cid in self.config.cache.pinned.iter() {
+            if on_disk.contains(cid) {
+                continue;
+            }
+            let store = self.clone();
+            let workers = workers.clone();
+            let cid = cid.clone();
+            tasks.spawn(async move {
+                let _permit = workers.acquire_owned().await;
+                let res = store.add(cid.clone(), None).await.map(|_| ());
+                (cid, res)
+            });
+        }
+
+        let mut loaded = 0;
+        while let Some(res) = tasks.join_next().await {
+            match res? {
+                (_, Ok(_)) => loaded += 1,
+                (cid, Err(e)) => eprintln!("error preloading {}: {}", cid, e),
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Fetches, validates, and stores a module. Invalid modules are never
+    /// written to disk. `owner` is who the function will belong to, if known.
+    pub async fn add(
+        &self,
+        cid: String,
+        owner: Option<String>,
+    ) -> Result<ValidationReport, StoreError> {
+        check_cid(cid.as_str())?;
+        let module = self.sources.fetch(cid.as_str()).await?;
+        self.persist(cid, module, owner).await
+    }
+
+    /// Adds the module held in a CARv1 archive, returning its root CID.
+    /// Blocks are verified locally, so no network is needed.
+    pub async fn import(&self, car: Vec<u8>) -> Result<(String, ValidationReport), StoreError> {
+        let (cid, module) = self.unpack(car).await?;
+        let report = self.persist(cid.clone(), module, None).await?;
+        Ok((cid, report))
+    }
+
+    /// Extracts the module held in a CARv1 archive without storing it.
+    pub async fn unpack(&self, car: Vec<u8>) -> Result<(String, Vec<u8>), StoreError> {
+        let max_size = self.config.ipfs.max_size as usize;
+        let (cid, module) =
+            tokio::task::spawn_blocking(move || crate::car::import(&car, max_size)).await??;
+        check_cid(cid.as_str())?;
+        Ok((cid, module))
+    }
+
+    /// Validates and compiles a module, then writes it to the cache directory
+    /// and caches the instance. The moderation lists are checked against
+    /// `owner`, if known, since it may not be recorded yet.
+    pub async fn persist(
+        &self,
+        cid: String,
+        module: Vec<u8>,
+        owner: Option<String>,
+    ) -> Result<ValidationReport, StoreError> {
+        self.moderation
+            .check(cid.as_str(), Some(&module), owner.as_deref())
+            .await?;
+        let (mut report, module) = tokio::task::spawn_blocking(move || {
+            let options = ApiInstanceOptions::default();
+            (
+                validate_wasm(&module, &options.available_capabilities),
+                module,
+            )
+        })
+        .await?;
+        if !report.valid {
+            return Err(StoreError::Invalid(Box::new(report)));
+        }
+        let instance = match self.compile(module.clone()).await? {
+            Ok(i) => i,
+            Err(e) => {
+                report.valid = false;
+                report.errors.push(e.to_string());
+                return Err(StoreError::Invalid(Box::new(report)));
+            }
+        };
 
         let file_name = format!("{}/{}.wasm", self.config.cache.directory, cid);
         tokio::fs::write(&file_name, &module).await?;
+        self.gc_disk().await;
+
+        self.cache(cid, instance).await?;
+        Ok(report)
+    }
+
+    /// Drops a module from memory and the cache directory, along with its
+    /// cached responses.
+    pub async fn remove(&self, cid: String) -> Result<(), StoreError> {
+        check_cid(cid.as_str())?;
+        let file_name = format!("{}/{}.wasm", self.config.cache.directory, cid);
+        match tokio::fs::remove_file(&file_name).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        self.pinned.write().unwrap().remove(&cid);
+        self.fn_cache.remove(&cid).await;
+        self.moderation.forget(cid.as_str());
+        self.responses.invalidate_function(cid.as_str());
+        self.fn_cache
+            .wait()
+            .await
+            .map_err(|e| StoreError::cache_err(e.to_string()))
+    }
+
+    /// Returns the raw module for a CID from the cache directory.
+    pub async fn module(&self, cid: String) -> Result<Vec<u8>, StoreError> {
+        check_cid(cid.as_str())?;
+        let file_name = format!("{}/{}.wasm", self.config.cache.directory, cid);
+        tokio::fs::read(&file_name)
+            .await
+            .map_err(|_| StoreError::Ipfs(format!("{} not found", cid)))
+    }
+
+    /// Removes least recently used modules from disk once the cache directory
+    /// grows past `cache.max_disk` bytes.
+    async fn gc_disk(&self) {
+        if self.config.cache.max_disk == 0 {
+            return;
+        }
+        let dir = self.config.cache.directory.clone();
+        let max_disk = self.config.cache.max_disk;
+        let keep: HashSet<String> = self.config.cache.pinned.iter().cloned().collect();
+        match tokio::task::spawn_blocking(move || gc_dir(Path::new(&dir), max_disk, &keep)).await {
+            Ok(Ok(removed)) => {
+                for cid in removed {
+                    println!("removed {} from disk", cid);
+                }
+            }
+            Ok(Err(e)) => eprintln!("error collecting cache directory: {}", e),
+            Err(e) => eprintln!("error collecting cache directory: {}", e),
+        }
+    }
+
+    /// Runs a function. Identical concurrent `GET` and `HEAD` requests share
+    /// one execution, and its request ID; only the first reports gas and
+    /// queueing. Callers check
+    /// the moderation lists first with [`Store::moderate`].
+    pub async fn run(&self, cid: String, req: Request) -> Execution {
+        let key = match coalesce_key(cid.as_str(), &req) {
+            Some(k) => k,
+            None => return self.run_once(cid, req).await,
+        };
+        let (out, shared) = self
+            .executions
+            .run(key, || self.run_once(cid.clone(), req))
+            .await;
+        if !shared {
+            return out;
+        }
+        println!("coalesced request for {}", cid);
+        Execution {
+            report: GasReport::default(),
+            queue: QueueReport::default(),
+            ..out
+        }
+    }
+
+    /// Takes an execution slot for work done on a function's behalf outside of
+    /// [`Store::run`].
+    pub async fn acquire(&self, cid: &str) -> Result<Permit, StoreError> {
+        self.limiter.acquire(cid).await
+    }
+
+    /// Checks the moderation lists for a function that may already be loaded.
+    /// Modules that haven't been checked yet are read from disk so their
+    /// checksum is known.
+    pub async fn moderate(&self, cid: &str) -> Result<(), StoreError> {
+        if !self.moderation.has_checksum(cid) {
+            let file_name = format!("{}/{}.wasm", self.config.cache.directory, cid);
+            if let Ok(module) = tokio::fs::read(&file_name).await {
+                return self.moderation.check(cid, Some(&module), None).await;
+            }
+        }
+        self.moderation.check(cid, None, None).await
+    }
 
-        self.save(cid.clone(), module).await
+    /// Drops the owner moderation remembered for a function, after it changes.
+    pub fn forget_owner(&self, cid: &str) {
+        self.moderation.forget_owner(cid);
     }
 
-    pub async fn run(
+    async fn run_once(&self, cid: String, req: Request) -> Execution {
+        let request_id = req.meta().map(|m| m.request_id);
+        let permit = match self.limiter.acquire(cid.as_str()).await {
+            Ok(p) => p,
+            Err(e) => {
+                println!("rejected {}: {}", cid, e);
+                return Execution {
+                    result: Err(e),
+                    report: GasReport::default(),
+                    queue: QueueReport::default(),
+                    tables: HashSet::new(),
+                    request_id,
+                };
+            }
+        };
+        if permit.report.depth > 0 {
+            println!(
+                "queued {} behind {} requests for {}ms",
+                cid,
+                permit.report.depth,
+                permit.report.wait.as_millis()
+            );
+        }
+
+        let (result, report, tables) = self.execute(cid, req).await;
+        Execution {
+            result,
+            report,
+            queue: permit.report,
+            tables,
+            request_id,
+        }
+    }
+
+    async fn execute(
         &self,
         cid: String,
         req: Request,
-    ) -> (Result<Response, StoreError>, GasReport) {
-        let value = match self.fn_cache.get_mut(cid.as_str()) {
-            Some(v) => v,
-            None => {
-                if let Err(e) = self.load(cid.clone()).await {
-                    return (Err(e), GasReport::default());
-                };
-                match self.fn_cache.get_mut(cid.as_str()) {
-                    Some(v) => v,
-                    None => {
-                        return (
-                            Err(StoreError::cache_err("failed to get runtime")),
-                            GasReport::default(),
-                        );
-                    }
-                }
-            }
+    ) -> (Result<Response, StoreError>, GasReport, HashSet<String>) {
+        let mut instance = match self.get(cid.as_str()) {
+            Some(i) => i,
+            None => match self.load(cid.clone()).await {
+                Ok(i) => i,
+                Err(e) => return (Err(e), GasReport::default(), HashSet::new()),
+            },
+        };
+
+        let vars = match &self.env {
+            Some(env) => match env.vars(cid.as_str()).await {
+                Ok(v) => v,
+                Err(e) => return (Err(e), GasReport::default(), HashSet::new()),
+            },
+            None => Arc::default(),
+        };
+        let invocation = Invocation {
+            vars,
+            block: self.blocks.latest(),
         };
-        let mut instance = value.clone_inner();
 
         let vmr = match tokio::task::spawn_blocking(
-            move || -> (VmResult<FuncResult<Response>>, GasReport) {
-                let res = call_fetch(&mut instance, &req);
+            move || -> (VmResult<FuncResult<Response>>, GasReport, HashSet<String>) {
+                let (res, tables) = with_invocation(invocation, || {
+                    track_reads(|| call_fetch(&mut instance, &req))
+                });
                 let report = instance.create_gas_report();
-                (res, report)
+                (res, report, tables)
             },
         )
         .await
         {
             Ok(v) => v,
-            Err(e) => return (Err(StoreError::from(e)), GasReport::default()),
+            Err(e) => {
+                return (
+                    Err(StoreError::from(e)),
+                    GasReport::default(),
+                    HashSet::new(),
+                )
+            }
         };
         match vmr.0 {
             Ok(r) => match r {
-                FuncResult::Ok(r) => (Ok(r), vmr.1),
-                FuncResult::Err(s) => (Err(StoreError::func_err(s)), vmr.1),
+                FuncResult::Ok(r) => (Ok(r), vmr.1, vmr.2),
+                FuncResult::Err(s) => (Err(StoreError::func_err(s)), vmr.1, vmr.2),
             },
-            Err(e) => (Err(StoreError::from(e)), vmr.1),
+            Err(e) => (Err(StoreError::from(e)), vmr.1, vmr.2),
+        }
+    }
+
+    fn get(&self, cid: &str) -> Option<FnInstance> {
+        if let Some(i) = self.pinned.read().unwrap().get(cid) {
+            return Some(i.clone());
         }
+        self.fn_cache.get(cid).map(|v| {
+            v.value().touch(self.config.cache.directory.as_str());
+            v.value().instance.clone()
+        })
+    }
+
+    /// Loads a module from disk. Concurrent loads of the same module share
+    /// one compilation.
+    async fn load(&self, cid: String) -> Result<FnInstance, StoreError> {
+        self.loads.run(cid.clone(), || self.load_once(cid)).await.0
     }
 
-    async fn load(&self, cid: String) -> Result<bool, StoreError> {
+    async fn load_once(&self, cid: String) -> Result<FnInstance, StoreError> {
         let file_name = format!("{}/{}.wasm", self.config.cache.directory, cid);
         let module = tokio::fs::read(&file_name).await?;
+        touch(Path::new(&file_name));
+        self.moderation
+            .check(cid.as_str(), Some(&module), None)
+            .await?;
 
         self.save(cid, module).await
     }
 
-    async fn save(&self, cid: String, module: Vec<u8>) -> Result<bool, StoreError> {
+    /// Compiles and caches a module.
+    async fn save(&self, cid: String, module: Vec<u8>) -> Result<FnInstance, StoreError> {
+        let instance = self.compile(module).await??;
+        self.cache(cid, instance.clone()).await?;
+        Ok(instance)
+    }
+
+    async fn compile(&self, module: Vec<u8>) -> Result<VmResult<FnInstance>, StoreError> {
         let chain_id = self.config.clone().chain.id;
-        let instance = tokio::task::spawn_blocking(move || -> Instance<Api<TablelandClient>> {
+        let instance = tokio::task::spawn_blocking(move || -> VmResult<FnInstance> {
             instance_with_options(
                 module.as_slice(),
                 ApiInstanceOptions::default(),
@@ -106,12 +458,184 @@ impl Store {
             )
         })
         .await?;
+        Ok(instance)
+    }
 
-        if self.fn_cache.insert(cid, instance, 1).await {
-            self.fn_cache.wait().await.unwrap();
-            Ok(true)
+    /// Caches an instance. Instances the cache doesn't admit, e.g., because
+    /// they're larger than the budget, are dropped.
+    async fn cache(&self, cid: String, instance: FnInstance) -> Result<(), StoreError> {
+        if self.config.cache.pinned.contains(&cid) {
+            self.pinned.write().unwrap().insert(cid, instance);
+            return Ok(());
+        }
+
+        let cost = instance.size() as i64;
+        let cached = CachedFunction::new(cid.clone(), instance);
+        if self.fn_cache.insert(cid.clone(), cached, cost).await {
+            self.fn_cache
+                .wait()
+                .await
+                .map_err(|e| StoreError::cache_err(e.to_string()))?;
         } else {
-            Err(StoreError::cache_err("failed to cache runtime"))
+            println!("not caching {} ({} bytes)", cid, cost);
         }
+        Ok(())
+    }
+}
+
+/// Returns the key identical requests share, or `None` if the request isn't
+/// idempotent. Only requests from the same client are identical, since
+/// functions can see the client's IP.
+fn coalesce_key(cid: &str, req: &Request) -> Option<String> {
+    let method = req.method();
+    if method != Method::GET && method != Method::HEAD {
+        return None;
+    }
+    let headers = req.headers();
+    let mut pairs: Vec<(&str, &[u8])> = headers
+        .iter()
+        .filter(|(k, _)| !IGNORED_HEADERS.contains(&k.as_str()))
+        .map(|(k, v)| (k.as_str(), v.as_bytes()))
+        .collect();
+    pairs.sort();
+    let mut key = format!(
+        "{} {} {} {} {}",
+        cid,
+        method,
+        req.uri(),
+        req.caller().unwrap_or_default(),
+        req.meta().and_then(|m| m.client_ip).unwrap_or_default()
+    );
+    for (k, v) in pairs {
+        key.push_str(&format!("\n{}: {}", k, String::from_utf8_lossy(v)));
+    }
+    Some(key)
+}
+
+/// CIDs are used in file names and URLs, so only allow multibase characters.
+fn check_cid(cid: &str) -> Result<(), StoreError> {
+    if cid.is_empty() || !cid.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(StoreError::Ipfs(format!("invalid CID {}", cid)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tableland_std::Meta;
+
+    const EXAMPLE_JSON_WASM: &[u8] = include_bytes!("../testdata/json.wasm");
+
+    #[tokio::test]
+    async fn preload_works() {
+        let dir = std::env::temp_dir().join(format!("tbl-preload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.wasm"), EXAMPLE_JSON_WASM).unwrap();
+        std::fs::write(dir.join("b.wasm"), EXAMPLE_JSON_WASM).unwrap();
+        std::fs::write(dir.join("notes.txt"), b"skip me").unwrap();
+
+        let mut config = Config::default();
+        config.cache.directory = dir.to_str().unwrap().to_string();
+        config.cache.pinned = vec!["b".to_string()];
+        let store = Store::new(config);
+
+        assert_eq!(store.preload().await.unwrap(), 2);
+        assert!(store.get("a").is_some());
+        assert!(store.get("b").is_some());
+        assert!(store.fn_cache.get("b").is_none());
+        assert!(store.pinned.read().unwrap().contains_key("b"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn save_skips_cache_when_over_budget() {
+        let mut config = Config::default();
+        config.cache.max_memory = 1024;
+        let store = Store::new(config);
+
+        let instance = store
+            .save("a".to_string(), EXAMPLE_JSON_WASM.to_vec())
+            .await
+            .unwrap();
+        assert!(instance.size() > 1024);
+        assert!(store.get("a").is_none());
+    }
+
+    #[tokio::test]
+    async fn persist_rejects_invalid_modules() {
+        let dir = std::env::temp_dir().join(format!("tbl-persist-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = Config::default();
+        config.cache.directory = dir.to_str().unwrap().to_string();
+        let store = Store::new(config);
+
+        match store
+            .persist("a".to_string(), b"not wasm".to_vec(), None)
+            .await
+        {
+            Err(StoreError::Invalid(report)) => {
+                assert!(!report.valid);
+                assert_eq!(report.size, 8);
+            }
+            _ => panic!("expected invalid module"),
+        }
+        assert!(!dir.join("a.wasm").exists());
+
+        let report = store
+            .persist("b".to_string(), EXAMPLE_JSON_WASM.to_vec(), None)
+            .await
+            .unwrap();
+        assert!(report.valid);
+        assert!(dir.join("b.wasm").exists());
+        assert!(store.get("b").is_some());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn coalesce_key_works() {
+        let req = |method: Method, headers: &[(&'static str, &str)]| {
+            let mut map = warp::http::HeaderMap::new();
+            for (k, v) in headers {
+                map.insert(*k, v.parse().unwrap());
+            }
+            Request::new(
+                "a".to_string(),
+                "/1/image?w=2".parse().unwrap(),
+                method,
+                map,
+                None,
+            )
+        };
+        let key = |r: &Request| coalesce_key("a", r);
+
+        let base = key(&req(Method::GET, &[("accept", "image/png")]));
+        assert!(base.is_some());
+        assert_eq!(
+            key(&req(
+                Method::GET,
+                &[("user-agent", "curl"), ("accept", "image/png")]
+            )),
+            base
+        );
+        assert_ne!(key(&req(Method::GET, &[("accept", "image/svg+xml")])), base);
+        assert_ne!(
+            key(&req(Method::GET, &[("accept", "image/png")]).with_caller(Some("0x1".into()))),
+            base
+        );
+        assert!(key(&req(Method::POST, &[])).is_none());
+
+        // Functions can see the client's IP, so clients don't share.
+        let from = |ip: &str| {
+            req(Method::GET, &[("accept", "image/png")]).with_meta(Meta {
+                request_id: ip.to_string(),
+                client_ip: Some(ip.to_string()),
+                ..Meta::default()
+            })
+        };
+        assert_eq!(key(&from("1.1.1.1")), key(&from("1.1.1.1")));
+        assert_ne!(key(&from("1.1.1.1")), key(&from("2.2.2.2")));
     }
 }